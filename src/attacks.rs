@@ -0,0 +1,148 @@
+use crate::board::Board;
+use crate::eval::Score;
+use crate::file::File;
+use crate::rank::Rank;
+use crate::piece::Color;
+use crate::piece::Piece;
+use crate::piece::PieceType;
+
+// Per safe square a piece can move to, beyond the number a piece of its
+// type usually has
+pub const MOBILITY: [(PieceType, Score, i32); 4] = [
+    (PieceType::Knight, Score::new(4, 4), 4),
+    (PieceType::Bishop, Score::new(5, 5), 7),
+    (PieceType::Rook, Score::new(2, 4), 7),
+    (PieceType::Queen, Score::new(1, 2), 14),
+];
+
+// One bit per square, file * 8 + rank, in the sets of squares here and in
+// the exchange evaluator
+pub fn bit(file: File, rank: Rank) -> u64 {
+    1 << (file.value() * 8 + rank.value())
+}
+
+// A piece other than a pawn or king and the squares it attacks
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PieceAttacks {
+    pub piece: Piece,
+    pub file: File,
+    pub rank: Rank,
+    pub squares: u64,
+}
+
+// Which squares each side attacks, one bit per square (file * 8 + rank),
+// taken from the same destination squares move generation uses. Pawns
+// attack diagonally whether or not there is anything to capture.
+pub struct AttackMap {
+    by_color: [u64; 2],
+    by_pawns: [u64; 2],
+    pieces: Vec<PieceAttacks>,
+}
+
+impl AttackMap {
+    pub fn new(board: &Board) -> AttackMap {
+        let mut map = AttackMap{by_color: [0; 2], by_pawns: [0; 2], pieces: Vec::new()};
+        for square in board.pieces() {
+            let piece = square.piece.unwrap();
+            let side = piece.get_color().index();
+            let squares = if piece.piece_type() == PieceType::Pawn {
+                let forward = match piece.get_color() {
+                    Color::White => 1,
+                    Color::Black => -1
+                };
+                let squares = [-1, 1].iter()
+                    .filter_map(|step| match (square.file.offset(*step), square.rank.offset(forward)) {
+                        (Ok(file), Ok(rank)) => Some(bit(file, rank)),
+                        _ => None
+                    })
+                    .fold(0, |squares, square| squares | square);
+                map.by_pawns[side] |= squares;
+                squares
+            } else {
+                let squares = piece.get_moves(board, square).iter()
+                    .fold(0, |squares, target| squares | bit(target.file, target.rank));
+                if piece.piece_type() != PieceType::King {
+                    map.pieces.push(PieceAttacks{piece, file: square.file, rank: square.rank, squares});
+                }
+                squares
+            };
+            map.by_color[side] |= squares;
+        }
+        map
+    }
+
+    pub fn attacked_by(&self, color: Color) -> u64 {
+        self.by_color[color.index()]
+    }
+
+    pub fn attacked_by_pawns(&self, color: Color) -> u64 {
+        self.by_pawns[color.index()]
+    }
+
+    // Knights, bishops, rooks and queens of a color
+    pub fn pieces(&self, color: Color) -> impl Iterator<Item = &PieceAttacks> {
+        self.pieces.iter().filter(move |attacks| attacks.piece.get_color() == color)
+    }
+}
+
+// Safe squares a color's pieces attack beyond the usual number, summed by
+// piece type in the order of MOBILITY. Squares an enemy pawn covers are not
+// safe.
+pub fn mobility_counts(attacks: &AttackMap, color: Color) -> [i32; 4] {
+    let unsafe_squares = attacks.attacked_by_pawns(color.opposite());
+    let mut counts = [0; 4];
+    for piece in attacks.pieces(color) {
+        let index = MOBILITY.iter()
+            .position(|(piece_type, _, _)| *piece_type == piece.piece.piece_type())
+            .unwrap();
+        let count = (piece.squares & !unsafe_squares).count_ones() as i32;
+        counts[index] += count - MOBILITY[index].2;
+    }
+    counts
+}
+
+// Mobility of a color's pieces
+pub fn mobility(attacks: &AttackMap, color: Color) -> Score {
+    let counts = mobility_counts(attacks, color);
+    MOBILITY.iter()
+        .zip(counts.iter())
+        .fold(Score::default(), |score, ((_, weight, _), count)| score + *weight * *count)
+}
+
+#[cfg(test)]
+mod attacks_tests {
+    use super::*;
+
+    fn square(name: &str) -> u64 {
+        let mut chars = name.chars();
+        let file = File::from_char(chars.next().unwrap()).unwrap();
+        let rank = Rank::from_char(chars.next().unwrap()).unwrap();
+        bit(file, rank)
+    }
+
+    #[test]
+    fn maps_attacks_of_each_side() {
+        let board = Board::from_fen("4k3/8/8/3p4/8/2N5/8/R3K3 w - - 0 1").unwrap();
+        let attacks = AttackMap::new(&board);
+        let white = attacks.attacked_by(Color::White);
+        // Knight on c3, rook along the first rank and a-file up to its king
+        assert!(white & square("d5") != 0 && white & square("b1") != 0 && white & square("a8") != 0);
+        assert!(white & square("e1") == 0 && white & square("h1") == 0);
+        assert_eq!(attacks.attacked_by_pawns(Color::Black), square("c4") | square("e4"));
+        assert_eq!(attacks.pieces(Color::White).count(), 2);
+        assert_eq!(attacks.pieces(Color::Black).count(), 0);
+    }
+
+    #[test]
+    fn mobility_counts_safe_squares() {
+        // A centralised knight has eight squares, one in the corner two
+        let centre = AttackMap::new(&Board::from_fen("4k3/8/8/8/3N4/8/8/4K3 w - - 0 1").unwrap());
+        let corner = AttackMap::new(&Board::from_fen("4k3/8/8/8/8/8/8/N3K3 w - - 0 1").unwrap());
+        assert_eq!(mobility(&centre, Color::White), Score::new(16, 16));
+        assert_eq!(mobility(&corner, Color::White), Score::new(-8, -8));
+
+        // Squares covered by enemy pawns do not count
+        let covered = AttackMap::new(&Board::from_fen("4k3/3p4/8/8/3N4/8/8/4K3 w - - 0 1").unwrap());
+        assert_eq!(mobility(&covered, Color::White), Score::new(8, 8));
+    }
+}
@@ -5,15 +5,73 @@ use crate::file;
 use crate::file::File;
 use crate::piece::Piece;
 use crate::piece::Color;
+use crate::piece::PieceType;
+use crate::piece::BISHOP_DIRECTIONS;
+use crate::piece::ROOK_DIRECTIONS;
+use crate::piece::KNIGHT_OFFSETS;
+use crate::piece::KING_OFFSETS;
+use crate::chess_move::Move;
+use crate::render;
+use crate::render::RenderOptions;
+use crate::zobrist;
+use crate::eval;
+use crate::eval::Score;
+use crate::nnue::Accumulator;
+use crate::nnue::Network;
 
 use std::cmp;
+use std::fmt;
 use std::iter;
+use std::sync::Arc;
 
+#[derive(Debug, Clone)]
 pub struct Board {
     squares: [[Square; 8]; 8],
 
     white_castling_rights: CastlingState,
-    black_castling_rights: CastlingState
+    black_castling_rights: CastlingState,
+
+    side_to_move: Color,
+    // Square a pawn skipped over on the previous move, if any
+    en_passant: Option<(File, Rank)>,
+    halfmove_clock: u32,
+    fullmove_number: u32,
+
+    // Zobrist key of the pieces alone, kept up to date by add_piece and remove_piece
+    pieces_key: u64,
+    // The same for the pawns only
+    pawn_key: u64,
+    // Material and piece-square score, White minus Black, and game phase,
+    // kept up to date the same way
+    psqt: Score,
+    phase: i32,
+    // Hidden layer of the NNUE network, when one is set, kept up to date
+    // the same way
+    accumulator: Option<Accumulator>,
+    history: Vec<MoveRecord>,
+    null_moves: Vec<NullMoveRecord>
+}
+
+// Everything make_move destroys, so unmake_move can put it back
+#[derive(Debug, Clone, Copy)]
+struct MoveRecord {
+    mv: Move,
+    moved: Piece,
+    captured: Option<(Piece, File, Rank)>,
+    white_castling_rights: CastlingState,
+    black_castling_rights: CastlingState,
+    en_passant: Option<(File, Rank)>,
+    halfmove_clock: u32,
+    // Key of the position before the move
+    key: u64
+}
+
+// State a null move destroys, and how many real moves preceded it
+#[derive(Debug, Clone, Copy)]
+struct NullMoveRecord {
+    ply: usize,
+    en_passant: Option<(File, Rank)>,
+    halfmove_clock: u32
 }
 
 impl Board {
@@ -31,20 +89,62 @@ impl Board {
         ];
         let white_castling_rights = CastlingState::new();
         let black_castling_rights = CastlingState::new();
-        Ok(Board{
-            squares,
-            white_castling_rights,
-            black_castling_rights
-        })
+        Ok(Board::build_custom(squares, white_castling_rights, black_castling_rights))
+    }
+
+    // Build a board with all pieces on their starting squares
+    pub fn build_starting() -> Result<Board, &'static str> {
+        let mut board = Board::build_empty()?;
+        let back_rank = [
+            PieceType::Rook,
+            PieceType::Knight,
+            PieceType::Bishop,
+            PieceType::Queen,
+            PieceType::King,
+            PieceType::Bishop,
+            PieceType::Knight,
+            PieceType::Rook
+        ];
+        for (file, piece_type) in File::iter_files(0..file::MAX_NUMBER_OF_FILES).zip(back_rank.iter()) {
+            board.add_piece(Piece::new(Color::White, *piece_type, false), file, Rank::build(0)?);
+            board.add_piece(Piece::new(Color::White, PieceType::Pawn, false), file, Rank::build(1)?);
+            board.add_piece(Piece::new(Color::Black, PieceType::Pawn, false), file, Rank::build(6)?);
+            board.add_piece(Piece::new(Color::Black, *piece_type, false), file, Rank::build(7)?);
+        }
+        Ok(board)
     }
 
     // Build a custom board
     pub fn build_custom(squares: [[Square; 8]; 8], white_castling_rights: CastlingState, black_castling_rights: CastlingState) -> Board {
-        Board{
+        let mut board = Board{
             squares,
             white_castling_rights,
-            black_castling_rights
-        }
+            black_castling_rights,
+            side_to_move: Color::White,
+            en_passant: None,
+            halfmove_clock: 0,
+            fullmove_number: 1,
+            pieces_key: 0,
+            pawn_key: 0,
+            psqt: Score::default(),
+            phase: 0,
+            accumulator: None,
+            history: Vec::new(),
+            null_moves: Vec::new()
+        };
+        board.pieces_key = board.pieces().fold(0, |key, square| {
+            key ^ zobrist::piece_key(&square.piece.unwrap(), square.file, square.rank)
+        });
+        board.pawn_key = board.pieces()
+            .filter(|square| square.piece.is_some_and(|piece| piece.piece_type() == PieceType::Pawn))
+            .fold(0, |key, square| key ^ zobrist::piece_key(&square.piece.unwrap(), square.file, square.rank));
+        let (psqt, phase) = board.pieces().fold((Score::default(), 0), |(psqt, phase), square| {
+            let piece = square.piece.unwrap();
+            (psqt + eval::piece_square(&piece, square.file, square.rank), phase + eval::phase_weight(piece.piece_type()))
+        });
+        board.psqt = psqt;
+        board.phase = phase;
+        board
     }
 
     pub fn get_square(&self, file: File, rank: Rank) -> &Square {
@@ -102,6 +202,16 @@ impl Board {
             Some(_) => panic!("Square already has a piece"),
             None => {square.piece = Some(piece);}
         }
+        self.pieces_key ^= zobrist::piece_key(&piece, file, rank);
+        if piece.piece_type() == PieceType::Pawn {
+            self.pawn_key ^= zobrist::piece_key(&piece, file, rank);
+        }
+        self.psqt += eval::piece_square(&piece, file, rank);
+        self.phase += eval::phase_weight(piece.piece_type());
+        if let Some(mut accumulator) = self.accumulator.take() {
+            accumulator.add_piece(self, piece, file, rank);
+            self.accumulator = Some(accumulator);
+        }
     }
 
     pub fn move_pice(&mut self, sq_a: &mut Square, sq_b: &mut Square) {
@@ -111,6 +221,462 @@ impl Board {
         }
     }
 
+    pub fn piece_at(&self, file: File, rank: Rank) -> Option<Piece> {
+        self.get_square(file, rank).piece
+    }
+
+    pub fn remove_piece(&mut self, file: File, rank: Rank) -> Option<Piece> {
+        let piece = self.squares[usize::from(file.value())][usize::from(rank.value())].piece.take();
+        if let Some(piece) = &piece {
+            self.pieces_key ^= zobrist::piece_key(piece, file, rank);
+            if piece.piece_type() == PieceType::Pawn {
+                self.pawn_key ^= zobrist::piece_key(piece, file, rank);
+            }
+            self.psqt -= eval::piece_square(piece, file, rank);
+            self.phase -= eval::phase_weight(piece.piece_type());
+            if let Some(accumulator) = &mut self.accumulator {
+                accumulator.remove_piece(*piece, file, rank);
+            }
+        }
+        piece
+    }
+
+    // Zobrist key of the piece placement, without castling, en passant or side to move
+    pub fn pieces_key(&self) -> u64 {
+        self.pieces_key
+    }
+
+    pub fn pawn_key(&self) -> u64 {
+        self.pawn_key
+    }
+
+    pub fn psqt(&self) -> Score {
+        self.psqt
+    }
+
+    pub fn phase(&self) -> i32 {
+        self.phase
+    }
+
+    // Start or stop keeping an accumulator for an NNUE network
+    pub fn set_network(&mut self, network: Option<Arc<Network>>) {
+        let accumulator = network.map(|network| Accumulator::new(network, self));
+        self.accumulator = accumulator;
+    }
+
+    pub fn accumulator(&self) -> Option<&Accumulator> {
+        self.accumulator.as_ref()
+    }
+
+    pub fn side_to_move(&self) -> Color {
+        self.side_to_move
+    }
+
+    pub fn set_side_to_move(&mut self, color: Color) {
+        self.side_to_move = color;
+    }
+
+    pub fn en_passant(&self) -> Option<(File, Rank)> {
+        self.en_passant
+    }
+
+    pub fn halfmove_clock(&self) -> u32 {
+        self.halfmove_clock
+    }
+
+    pub fn fullmove_number(&self) -> u32 {
+        self.fullmove_number
+    }
+
+    // Number of moves made on this board that can still be unmade
+    pub fn ply(&self) -> usize {
+        self.history.len()
+    }
+
+    // None right after a null move
+    pub fn last_move(&self) -> Option<Move> {
+        if self.after_null_move() {
+            return None;
+        }
+        self.history.last().map(|record| record.mv)
+    }
+
+    // Whether the position occurred before, with the same side to move, since
+    // the last capture or pawn move. Positions before a null move do not count.
+    pub fn is_repetition(&self) -> bool {
+        let key = self.zobrist_key();
+        let since_null = match self.null_moves.last() {
+            Some(record) => self.history.len() - record.ply,
+            None => self.history.len()
+        };
+        self.history.iter().rev()
+            .take((self.halfmove_clock as usize).min(since_null))
+            .skip(1)
+            .step_by(2)
+            .any(|record| record.key == key)
+    }
+
+    // All occupied squares, rank by rank from a1
+    pub fn pieces(&self) -> impl Iterator<Item = &Square> {
+        Rank::iter_ranks(0..rank::MAX_NUMBER_OF_RANKS).flat_map(move |rank| {
+            File::iter_files(0..file::MAX_NUMBER_OF_FILES).map(move |file| self.get_square(file, rank))
+        }).filter(|square| !square.is_empty())
+    }
+
+    pub fn find_king(&self, color: Color) -> Option<(File, Rank)> {
+        self.pieces().find(|square| {
+            match &square.piece {
+                Some(piece) => piece.get_color() == color && piece.piece_type() == PieceType::King,
+                None => false
+            }
+        }).map(|square| (square.file, square.rank))
+    }
+
+    pub fn in_check(&self) -> bool {
+        self.is_king_attacked(self.side_to_move)
+    }
+
+    pub fn is_king_attacked(&self, color: Color) -> bool {
+        match self.find_king(color) {
+            Some((file, rank)) => self.is_square_attacked(file, rank, color.opposite()),
+            None => false
+        }
+    }
+
+    // Whether any piece of the given color attacks the square
+    pub fn is_square_attacked(&self, file: File, rank: Rank, by: Color) -> bool {
+        let attacked_by = |file_step: i8, rank_step: i8, piece_types: &[PieceType]| {
+            match (file.offset(file_step), rank.offset(rank_step)) {
+                (Ok(file), Ok(rank)) => match self.piece_at(file, rank) {
+                    Some(piece) => piece.get_color() == by && piece_types.contains(&piece.piece_type()),
+                    None => false
+                },
+                _ => false
+            }
+        };
+
+        // A pawn attacks diagonally forward, so look one rank behind the square
+        let pawn_step = match by {
+            Color::White => -1,
+            Color::Black => 1
+        };
+        if attacked_by(-1, pawn_step, &[PieceType::Pawn]) || attacked_by(1, pawn_step, &[PieceType::Pawn]) {
+            return true;
+        }
+        if KNIGHT_OFFSETS.iter().any(|(f, r)| attacked_by(*f, *r, &[PieceType::Knight])) {
+            return true;
+        }
+        if KING_OFFSETS.iter().any(|(f, r)| attacked_by(*f, *r, &[PieceType::King])) {
+            return true;
+        }
+
+        let diagonal_sliders = [PieceType::Bishop, PieceType::Queen];
+        let straight_sliders = [PieceType::Rook, PieceType::Queen];
+        BISHOP_DIRECTIONS.iter().any(|dir| self.first_piece_on_ray(file, rank, *dir).is_some_and(|piece| {
+            piece.get_color() == by && diagonal_sliders.contains(&piece.piece_type())
+        })) || ROOK_DIRECTIONS.iter().any(|dir| self.first_piece_on_ray(file, rank, *dir).is_some_and(|piece| {
+            piece.get_color() == by && straight_sliders.contains(&piece.piece_type())
+        }))
+    }
+
+    fn first_piece_on_ray(&self, file: File, rank: Rank, (file_step, rank_step): (i8, i8)) -> Option<Piece> {
+        let mut file = file;
+        let mut rank = rank;
+        while let (Ok(next_file), Ok(next_rank)) = (file.offset(file_step), rank.offset(rank_step)) {
+            file = next_file;
+            rank = next_rank;
+            if let Some(piece) = self.piece_at(file, rank) {
+                return Some(piece);
+            }
+        }
+        None
+    }
+
+    // Apply a move without checking that it is legal. Moves should come from
+    // legal_moves(); use play() for moves from an untrusted source.
+    pub fn make_move(&mut self, mv: Move) {
+        let moved = self.piece_at(mv.from_file, mv.from_rank).expect("No piece on the move's origin square");
+        let color = moved.get_color();
+        let is_pawn = moved.piece_type() == PieceType::Pawn;
+
+        let key = self.zobrist_key();
+        let mut captured = self.remove_piece(mv.to_file, mv.to_rank).map(|piece| (piece, mv.to_file, mv.to_rank));
+        if is_pawn && captured.is_none() && self.en_passant == Some(mv.to()) && mv.from_file != mv.to_file {
+            // En passant captures the pawn beside the origin square
+            captured = self.remove_piece(mv.to_file, mv.from_rank).map(|piece| (piece, mv.to_file, mv.from_rank));
+        }
+
+        self.history.push(MoveRecord{
+            mv,
+            moved,
+            captured,
+            white_castling_rights: self.white_castling_rights,
+            black_castling_rights: self.black_castling_rights,
+            en_passant: self.en_passant,
+            halfmove_clock: self.halfmove_clock,
+            key
+        });
+
+        self.remove_piece(mv.from_file, mv.from_rank);
+        let placed = match mv.promotion {
+            Some(piece_type) => Piece::new(color, piece_type, true),
+            None => {
+                let mut piece = moved;
+                piece.set_moved();
+                piece
+            }
+        };
+        self.add_piece(placed, mv.to_file, mv.to_rank);
+
+        if moved.piece_type() == PieceType::King && mv.from_file.value().abs_diff(mv.to_file.value()) == 2 {
+            let (rook_from, rook_to) = Board::castling_rook_files(mv.to_file);
+            if let Some(mut rook) = self.remove_piece(rook_from, mv.from_rank) {
+                rook.set_moved();
+                self.add_piece(rook, rook_to, mv.from_rank);
+            }
+        }
+
+        self.update_castling_rights(mv.from_file, mv.from_rank);
+        self.update_castling_rights(mv.to_file, mv.to_rank);
+
+        self.en_passant = None;
+        if is_pawn && mv.from_rank.value().abs_diff(mv.to_rank.value()) == 2 {
+            let skipped = (mv.from_rank.value() + mv.to_rank.value()) / 2;
+            self.en_passant = Rank::build(skipped).ok().map(|rank| (mv.from_file, rank));
+        }
+
+        if is_pawn || captured.is_some() {
+            self.halfmove_clock = 0;
+        } else {
+            self.halfmove_clock += 1;
+        }
+        if color == Color::Black {
+            self.fullmove_number += 1;
+        }
+        self.side_to_move = color.opposite();
+    }
+
+    // Take back the last move made with make_move
+    pub fn unmake_move(&mut self) {
+        let record = match self.history.pop() {
+            Some(record) => record,
+            None => return
+        };
+        let mv = record.mv;
+
+        self.side_to_move = record.moved.get_color();
+        if self.side_to_move == Color::Black {
+            self.fullmove_number -= 1;
+        }
+
+        self.remove_piece(mv.to_file, mv.to_rank);
+        self.add_piece(record.moved, mv.from_file, mv.from_rank);
+        if let Some((piece, file, rank)) = record.captured {
+            self.add_piece(piece, file, rank);
+        }
+
+        if record.moved.piece_type() == PieceType::King && mv.from_file.value().abs_diff(mv.to_file.value()) == 2 {
+            let (rook_from, rook_to) = Board::castling_rook_files(mv.to_file);
+            if self.remove_piece(rook_to, mv.from_rank).is_some() {
+                self.add_piece(Piece::new(self.side_to_move, PieceType::Rook, false), rook_from, mv.from_rank);
+            }
+        }
+
+        self.white_castling_rights = record.white_castling_rights;
+        self.black_castling_rights = record.black_castling_rights;
+        self.en_passant = record.en_passant;
+        self.halfmove_clock = record.halfmove_clock;
+    }
+
+    // Pass the turn to the opponent, for null-move pruning. Must not be
+    // used while in check.
+    pub fn make_null_move(&mut self) {
+        self.null_moves.push(NullMoveRecord{
+            ply: self.history.len(),
+            en_passant: self.en_passant,
+            halfmove_clock: self.halfmove_clock
+        });
+        self.en_passant = None;
+        self.halfmove_clock += 1;
+        if self.side_to_move == Color::Black {
+            self.fullmove_number += 1;
+        }
+        self.side_to_move = self.side_to_move.opposite();
+    }
+
+    // Take back the last null move, after any moves made since
+    pub fn unmake_null_move(&mut self) {
+        let record = match self.null_moves.pop() {
+            Some(record) => record,
+            None => return
+        };
+        self.side_to_move = self.side_to_move.opposite();
+        if self.side_to_move == Color::Black {
+            self.fullmove_number -= 1;
+        }
+        self.en_passant = record.en_passant;
+        self.halfmove_clock = record.halfmove_clock;
+    }
+
+    // Whether the last move made was a null move
+    pub fn after_null_move(&self) -> bool {
+        self.null_moves.last().is_some_and(|record| record.ply == self.history.len())
+    }
+
+    // Make a move after checking it is legal in the current position
+    pub fn play(&mut self, mv: Move) -> Result<(), &'static str> {
+        if !self.legal_moves().contains(&mv) {
+            return Err("Illegal move");
+        }
+        self.make_move(mv);
+        Ok(())
+    }
+
+    // Whether the move takes a piece, including en passant captures
+    pub fn is_capture(&self, mv: Move) -> bool {
+        if self.piece_at(mv.to_file, mv.to_rank).is_some() {
+            return true;
+        }
+        let is_pawn = self.piece_at(mv.from_file, mv.from_rank).is_some_and(|piece| piece.piece_type() == PieceType::Pawn);
+        is_pawn && mv.from_file != mv.to_file && self.en_passant == Some(mv.to())
+    }
+
+    // Origin and destination files of the rook when the king castles to the given file
+    fn castling_rook_files(king_to: File) -> (File, File) {
+        let files = if king_to.value() > 4 { (7, 5) } else { (0, 3) };
+        (File::build(files.0).unwrap(), File::build(files.1).unwrap())
+    }
+
+    // Moving from or capturing on a king or rook home square loses the matching rights
+    fn update_castling_rights(&mut self, file: File, rank: Rank) {
+        let castling_rights = match rank.value() {
+            0 => &mut self.white_castling_rights,
+            7 => &mut self.black_castling_rights,
+            _ => return
+        };
+        match file.value() {
+            0 => castling_rights.queenside_rook_has_moved = true,
+            4 => castling_rights.king_has_moved = true,
+            7 => castling_rights.kingside_rook_has_moved = true,
+            _ => {}
+        }
+    }
+
+    // Build a board from Forsyth-Edwards Notation
+    pub fn from_fen(fen: &str) -> Result<Board, &'static str> {
+        let fields: Vec<&str> = fen.split_whitespace().collect();
+        if fields.len() < 4 {
+            return Err("FEN must have at least four fields");
+        }
+
+        let mut board = Board::build_empty()?;
+        let rows: Vec<&str> = fields[0].split('/').collect();
+        if rows.len() != usize::from(rank::MAX_NUMBER_OF_RANKS) {
+            return Err("FEN board must have eight ranks");
+        }
+        for (row, rank) in rows.iter().zip(Rank::iter_ranks((0..rank::MAX_NUMBER_OF_RANKS).rev())) {
+            let mut file_value: u8 = 0;
+            for c in row.chars() {
+                if let Some(skip) = c.to_digit(10) {
+                    if skip == 0 || skip > u32::from(file::MAX_NUMBER_OF_FILES) {
+                        return Err("FEN empty square counts must be 1 to 8");
+                    }
+                    file_value = file_value.checked_add(skip as u8)
+                        .filter(|value| *value <= file::MAX_NUMBER_OF_FILES)
+                        .ok_or("FEN rank has too many squares")?;
+                    continue;
+                }
+                let file = File::build(file_value).map_err(|_| "FEN rank has too many squares")?;
+                let mut piece = Piece::from_char(c)?;
+                let starting_pawn_rank = match piece.get_color() {
+                    Color::White => 1,
+                    Color::Black => 6
+                };
+                if piece.piece_type() == PieceType::Pawn && rank.value() != starting_pawn_rank {
+                    piece.set_moved();
+                }
+                board.add_piece(piece, file, rank);
+                file_value += 1;
+            }
+            if file_value != file::MAX_NUMBER_OF_FILES {
+                return Err("FEN rank must have eight squares");
+            }
+        }
+
+        board.side_to_move = match fields[1] {
+            "w" => Color::White,
+            "b" => Color::Black,
+            _ => return Err("Invalid side to move in FEN")
+        };
+
+        board.white_castling_rights = CastlingState::from_fen_flags(fields[2], 'K', 'Q');
+        board.black_castling_rights = CastlingState::from_fen_flags(fields[2], 'k', 'q');
+
+        board.en_passant = match fields[3] {
+            "-" => None,
+            square => {
+                let chars: Vec<char> = square.chars().collect();
+                if chars.len() != 2 {
+                    return Err("Invalid en passant square in FEN");
+                }
+                Some((File::from_char(chars[0])?, Rank::from_char(chars[1])?))
+            }
+        };
+
+        if let Some(halfmove_clock) = fields.get(4) {
+            board.halfmove_clock = halfmove_clock.parse().map_err(|_| "Invalid halfmove clock in FEN")?;
+        }
+        if let Some(fullmove_number) = fields.get(5) {
+            board.fullmove_number = fullmove_number.parse().map_err(|_| "Invalid fullmove number in FEN")?;
+        }
+        Ok(board)
+    }
+
+    pub fn to_fen(&self) -> String {
+        let mut rows: Vec<String> = Vec::new();
+        for rank in Rank::iter_ranks((0..rank::MAX_NUMBER_OF_RANKS).rev()) {
+            let mut row = String::new();
+            let mut empty = 0;
+            for square in self.get_rank(rank) {
+                match &square.piece {
+                    Some(piece) => {
+                        if empty > 0 {
+                            row.push_str(&empty.to_string());
+                            empty = 0;
+                        }
+                        row.push(piece.to_char());
+                    },
+                    None => empty += 1
+                }
+            }
+            if empty > 0 {
+                row.push_str(&empty.to_string());
+            }
+            rows.push(row);
+        }
+
+        let side_to_move = match self.side_to_move {
+            Color::White => "w",
+            Color::Black => "b"
+        };
+
+        let mut castling = String::new();
+        if self.can_castle_kingside(Color::White) { castling.push('K'); }
+        if self.can_castle_queenside(Color::White) { castling.push('Q'); }
+        if self.can_castle_kingside(Color::Black) { castling.push('k'); }
+        if self.can_castle_queenside(Color::Black) { castling.push('q'); }
+        if castling.is_empty() {
+            castling.push('-');
+        }
+
+        let en_passant = match self.en_passant {
+            Some((file, rank)) => format!("{}{}", file, rank),
+            None => String::from("-")
+        };
+
+        format!("{} {} {} {} {} {}", rows.join("/"), side_to_move, castling, en_passant, self.halfmove_clock, self.fullmove_number)
+    }
+
     pub fn can_castle_kingside(&self, color: Color) -> bool {
         match color {
             Color::White => self.kingside_castle_valid(&self.white_castling_rights, &self.squares[4][0]),
@@ -126,20 +692,52 @@ impl Board {
     }
 
     fn kingside_castle_valid(&self, castling_state: &CastlingState, king_square: &Square) -> bool {
-        castling_state.has_kingside_castling_rights()
+        castling_state.has_kingside_castling_rights() && self.castling_pieces_in_place(king_square, 7)
     }
 
     fn queenside_castle_valid(&self, castling_state: &CastlingState, king_square: &Square) -> bool {
-        castling_state.has_queenside_castling_rights()
+        castling_state.has_queenside_castling_rights() && self.castling_pieces_in_place(king_square, 0)
+    }
+
+    // Rights alone are not enough, e.g. on a board built with add_piece
+    fn castling_pieces_in_place(&self, king_square: &Square, rook_file: u8) -> bool {
+        let king = match &king_square.piece {
+            Some(piece) if piece.piece_type() == PieceType::King => *piece,
+            _ => return false
+        };
+        let home_rank = match king.get_color() {
+            Color::White => 0,
+            Color::Black => 7
+        };
+        if king_square.rank.value() != home_rank {
+            return false;
+        }
+        match self.squares[usize::from(rook_file)][usize::from(home_rank)].piece {
+            Some(rook) => rook.piece_type() == PieceType::Rook && rook.get_color() == king.get_color(),
+            None => false
+        }
+    }
+}
+
+impl fmt::Display for Board {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", render::render_text(self, &RenderOptions::new()))
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct CastlingState {
     king_has_moved: bool,
     queenside_rook_has_moved: bool,
     kingside_rook_has_moved: bool
 }
 
+impl Default for CastlingState {
+    fn default() -> CastlingState {
+        CastlingState::new()
+    }
+}
+
 impl CastlingState {
     pub fn new() -> CastlingState {
         CastlingState{
@@ -149,6 +747,15 @@ impl CastlingState {
         }
     }
 
+    // Rights for one color from the FEN castling field, e.g. "KQkq"
+    fn from_fen_flags(flags: &str, kingside: char, queenside: char) -> CastlingState {
+        CastlingState{
+            king_has_moved: false,
+            queenside_rook_has_moved: !flags.contains(queenside),
+            kingside_rook_has_moved: !flags.contains(kingside)
+        }
+    }
+
     fn has_kingside_castling_rights(&self) -> bool {
         !self.king_has_moved && !self.kingside_rook_has_moved
     }
@@ -156,4 +763,101 @@ impl CastlingState {
     fn has_queenside_castling_rights(&self) -> bool {
         !self.king_has_moved && !self.queenside_rook_has_moved
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod board_tests {
+    use super::*;
+
+    #[test]
+    fn fen_round_trip() {
+        let fens = [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3",
+            "8/8/8/8/8/8/8/K6k b - - 12 60",
+        ];
+        for fen in fens.iter() {
+            assert_eq!(Board::from_fen(fen).unwrap().to_fen(), *fen);
+        }
+        assert_eq!(Board::build_starting().unwrap().to_fen(), fens[0]);
+    }
+
+    #[test]
+    fn invalid_fen() {
+        assert!(Board::from_fen("").is_err());
+        assert!(Board::from_fen("8/8/8/8/8/8/8 w - -").is_err());
+        assert!(Board::from_fen("9/8/8/8/8/8/8/8 w - -").is_err());
+        assert!(Board::from_fen("8/8/8/8/8/8/8/8 x - -").is_err());
+        assert!(Board::from_fen("x7/8/8/8/8/8/8/8 w - -").is_err());
+        assert_eq!(Board::from_fen("08/8/8/8/8/8/8/8 w - -").err(), Some("FEN empty square counts must be 1 to 8"));
+        assert_eq!(Board::from_fen(&format!("{}/8/8/8/8/8/8/8 w - -", "9".repeat(30))).err(), Some("FEN empty square counts must be 1 to 8"));
+        assert_eq!(Board::from_fen(&format!("{}/8/8/8/8/8/8/8 w - -", "8".repeat(40))).err(), Some("FEN rank has too many squares"));
+        assert_eq!(Board::from_fen("44k/8/8/8/8/8/8/8 w - -").err(), Some("FEN rank has too many squares"));
+    }
+
+    #[test]
+    fn castling_updates_rook_and_rights() {
+        let mut board = Board::from_fen("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1").unwrap();
+        board.make_move(Move::from_uci("e1g1").unwrap());
+        assert_eq!(board.to_fen(), "r3k2r/8/8/8/8/8/8/R4RK1 b kq - 1 1");
+        board.make_move(Move::from_uci("a8a1").unwrap());
+        assert_eq!(board.to_fen(), "4k2r/8/8/8/8/8/8/r4RK1 w k - 0 2");
+        board.unmake_move();
+        board.unmake_move();
+        assert_eq!(board.to_fen(), "r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1");
+    }
+
+    #[test]
+    fn square_attacks() {
+        let board = Board::from_fen("4k3/8/8/3p4/8/8/8/R3K3 w - - 0 1").unwrap();
+        let square = |name: &str| {
+            let chars: Vec<char> = name.chars().collect();
+            (File::from_char(chars[0]).unwrap(), Rank::from_char(chars[1]).unwrap())
+        };
+        let (file, rank) = square("a8");
+        assert!(board.is_square_attacked(file, rank, Color::White));
+        let (file, rank) = square("e4");
+        assert!(board.is_square_attacked(file, rank, Color::Black));
+        let (file, rank) = square("d4");
+        assert!(!board.is_square_attacked(file, rank, Color::Black));
+    }
+
+    #[test]
+    fn detects_repetition() {
+        let mut board = Board::build_starting().unwrap();
+        let start_key = board.zobrist_key();
+        for uci in ["g1f3", "g8f6", "f3g1", "f6g8"].iter() {
+            assert!(!board.is_repetition());
+            board.make_move(Move::from_uci(uci).unwrap());
+        }
+        assert!(board.is_repetition());
+        assert_eq!(board.zobrist_key(), start_key);
+
+        // A pawn move makes earlier positions unreachable
+        board.make_move(Move::from_uci("e2e4").unwrap());
+        board.make_move(Move::from_uci("g8f6").unwrap());
+        board.make_move(Move::from_uci("g1f3").unwrap());
+        board.make_move(Move::from_uci("f6g8").unwrap());
+        assert!(!board.is_repetition());
+        board.unmake_move();
+        board.make_move(Move::from_uci("b8c6").unwrap());
+        assert!(!board.is_repetition());
+    }
+
+    #[test]
+    fn null_move_passes_the_turn() {
+        let fen = "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3";
+        let mut board = Board::from_fen(fen).unwrap();
+        let key = board.zobrist_key();
+        board.make_null_move();
+        assert!(board.after_null_move());
+        assert_eq!(board.last_move(), None);
+        assert_eq!(board.to_fen(), "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR b KQkq - 1 3");
+        board.make_move(Move::from_uci("g8f6").unwrap());
+        assert!(!board.after_null_move());
+        board.unmake_move();
+        board.unmake_null_move();
+        assert_eq!(board.to_fen(), fen);
+        assert_eq!(board.zobrist_key(), key);
+    }
+}
@@ -0,0 +1,221 @@
+use crate::pgn;
+use crate::pgn::GameResult;
+use crate::pgn::PgnGame;
+use crate::piece::Color;
+use crate::polyglot;
+use crate::polyglot::BookEntry;
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::io::BufWriter;
+use std::io::Write;
+use std::path::Path;
+
+#[derive(Debug, Clone)]
+pub struct BookOptions {
+    // Only moves made in the first max_ply half-moves of each game are counted
+    pub max_ply: usize,
+    // Moves played fewer times than this are left out of the book
+    pub min_count: u32,
+    // Points credited to a move by the result of the game, from the mover's side
+    pub win_points: u32,
+    pub draw_points: u32,
+    pub loss_points: u32,
+}
+
+impl BookOptions {
+    pub fn new() -> BookOptions {
+        BookOptions{
+            max_ply: 24,
+            min_count: 1,
+            win_points: 2,
+            draw_points: 1,
+            loss_points: 0,
+        }
+    }
+}
+
+impl Default for BookOptions {
+    fn default() -> BookOptions {
+        BookOptions::new()
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct MoveStats {
+    count: u32,
+    points: u64,
+}
+
+// Collects move statistics from games and turns them into Polyglot entries
+pub struct BookBuilder {
+    options: BookOptions,
+    stats: HashMap<(u64, u16), MoveStats>,
+    games: usize,
+}
+
+impl BookBuilder {
+    pub fn new(options: BookOptions) -> BookBuilder {
+        BookBuilder{options, stats: HashMap::new(), games: 0}
+    }
+
+    pub fn games(&self) -> usize {
+        self.games
+    }
+
+    // Replay a game and count its moves. Games with an unknown result carry
+    // no information about move quality and are skipped. A game with a move
+    // that can't be played adds nothing to the book.
+    pub fn add_game(&mut self, game: &PgnGame) -> Result<(), &'static str> {
+        if game.result == GameResult::Unknown {
+            return Ok(());
+        }
+        let mut board = game.starting_board()?;
+        let mut played = Vec::new();
+        for san in game.moves.iter().take(self.options.max_ply) {
+            let mv = pgn::parse_san(&mut board, san)?;
+            let points = self.points_for(game.result, board.side_to_move());
+            played.push(((board.zobrist_key(), polyglot::encode_move(&board, &mv)), points));
+            board.make_move(mv);
+        }
+
+        self.games += 1;
+        for (key, points) in played {
+            let stats = self.stats.entry(key).or_default();
+            stats.count += 1;
+            stats.points += u64::from(points);
+        }
+        Ok(())
+    }
+
+    fn points_for(&self, result: GameResult, mover: Color) -> u32 {
+        match (result, mover) {
+            (GameResult::WhiteWins, Color::White) | (GameResult::BlackWins, Color::Black) => self.options.win_points,
+            (GameResult::WhiteWins, Color::Black) | (GameResult::BlackWins, Color::White) => self.options.loss_points,
+            _ => self.options.draw_points
+        }
+    }
+
+    // Book entries sorted by key, and by descending weight within a key.
+    // Weights are scaled down if needed to fit Polyglot's 16 bits.
+    pub fn entries(&self) -> Vec<BookEntry> {
+        let kept: Vec<(&(u64, u16), &MoveStats)> = self.stats.iter().filter(|(_, stats)| {
+            stats.count >= self.options.min_count && stats.points > 0
+        }).collect();
+
+        let max_points = kept.iter().map(|(_, stats)| stats.points).max().unwrap_or(0);
+        let scale = |points: u64| -> u16 {
+            if max_points <= u64::from(u16::MAX) {
+                points as u16
+            } else {
+                (points * u64::from(u16::MAX) / max_points).max(1) as u16
+            }
+        };
+
+        let mut entries: Vec<BookEntry> = kept.iter().map(|((key, raw_move), stats)| {
+            BookEntry{key: *key, raw_move: *raw_move, weight: scale(stats.points), learn: 0}
+        }).collect();
+        entries.sort_by(|a, b| {
+            a.key.cmp(&b.key).then(b.weight.cmp(&a.weight)).then(a.raw_move.cmp(&b.raw_move))
+        });
+        entries
+    }
+
+    // Write the book in Polyglot .bin format, returning the number of entries
+    pub fn write<W: Write>(&self, writer: &mut W) -> io::Result<usize> {
+        let entries = self.entries();
+        for entry in entries.iter() {
+            writer.write_all(&entry.to_bytes())?;
+        }
+        Ok(entries.len())
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<usize> {
+        let mut writer = BufWriter::new(fs::File::create(path)?);
+        let written = self.write(&mut writer)?;
+        writer.flush()?;
+        Ok(written)
+    }
+}
+
+#[cfg(test)]
+mod book_builder_tests {
+    use super::*;
+    use crate::board::Board;
+    use crate::chess_move::Move;
+    use crate::polyglot::PolyglotBook;
+    use std::io::Cursor;
+
+    fn game(moves: &str, result: &str) -> PgnGame {
+        PgnGame::parse(&format!("[Result \"{}\"]\n\n{} {}\n", result, moves, result))
+    }
+
+    #[test]
+    fn weights_moves_by_result() {
+        let mut builder = BookBuilder::new(BookOptions::new());
+        builder.add_game(&game("1. e4 e5", "1-0")).unwrap();
+        builder.add_game(&game("1. e4 c5", "0-1")).unwrap();
+        builder.add_game(&game("1. d4 d5", "1/2-1/2")).unwrap();
+        builder.add_game(&game("1. c4 c5", "*")).unwrap();
+        assert_eq!(builder.games(), 3);
+
+        let mut bytes = Vec::new();
+        builder.write(&mut bytes).unwrap();
+        let mut book = PolyglotBook::from_reader(Cursor::new(bytes)).unwrap();
+
+        let mut board = Board::build_starting().unwrap();
+        let moves = book.moves(&mut board).unwrap();
+        // e4 scored a win and a loss for 2 points, d4 a draw for 1, c4 is unknown
+        assert_eq!(moves.len(), 2);
+        assert_eq!(moves[0].mv, Move::from_uci("e2e4").unwrap());
+        assert_eq!(moves[0].weight, 2);
+        assert_eq!(moves[1].mv, Move::from_uci("d2d4").unwrap());
+        assert_eq!(moves[1].weight, 1);
+
+        // After 1. e4 only c5 won for black, e5 lost and scores nothing
+        board.make_move(Move::from_uci("e2e4").unwrap());
+        assert_eq!(book.best_move(&mut board).unwrap(), Some(Move::from_uci("c7c5").unwrap()));
+        assert_eq!(book.moves(&mut board).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn respects_ply_limit_and_minimum_count() {
+        let mut options = BookOptions::new();
+        options.max_ply = 1;
+        options.min_count = 2;
+        let mut builder = BookBuilder::new(options);
+        builder.add_game(&game("1. e4 e5", "1-0")).unwrap();
+        builder.add_game(&game("1. e4 e5", "1-0")).unwrap();
+        builder.add_game(&game("1. d4 d5", "1-0")).unwrap();
+
+        let entries = builder.entries();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].key, Board::build_starting().unwrap().zobrist_key());
+        assert_eq!(entries[0].weight, 4);
+    }
+
+    #[test]
+    fn entries_are_sorted_and_castling_is_encoded() {
+        let mut builder = BookBuilder::new(BookOptions::new());
+        builder.add_game(&game("1. e4 e5 2. Nf3 Nc6 3. Bc4 Bc5 4. O-O Nf6", "1-0")).unwrap();
+        // Black lost, so only white's four moves score any points
+        let entries = builder.entries();
+        assert_eq!(entries.len(), 4);
+        assert!(entries.windows(2).all(|pair| pair[0].key <= pair[1].key));
+
+        let mut board = Board::from_fen("r1bqk1nr/pppp1ppp/2n5/2b1p3/2B1P3/5N2/PPPP1PPP/RNBQK2R w KQkq - 4 4").unwrap();
+        let mut book = PolyglotBook::from_reader(Cursor::new(entries.iter().flat_map(|e| e.to_bytes().to_vec()).collect::<Vec<u8>>())).unwrap();
+        assert_eq!(book.best_move(&mut board).unwrap(), Some(Move::from_uci("e1g1").unwrap()));
+    }
+
+    #[test]
+    fn rejects_illegal_games() {
+        let mut builder = BookBuilder::new(BookOptions::new());
+        assert!(builder.add_game(&game("1. e5", "1-0")).is_err());
+        // Nothing from a game that goes wrong part way through is kept
+        assert!(builder.add_game(&game("1. e4 e5 2. Ke3", "1-0")).is_err());
+        assert_eq!(builder.games(), 0);
+        assert!(builder.entries().is_empty());
+    }
+}
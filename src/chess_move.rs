@@ -0,0 +1,100 @@
+use crate::file::File;
+use crate::rank::Rank;
+use crate::piece::PieceType;
+
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Move {
+    pub from_file: File,
+    pub from_rank: Rank,
+    pub to_file: File,
+    pub to_rank: Rank,
+
+    pub promotion: Option<PieceType>,
+}
+
+impl Move {
+    pub fn new(from_file: File, from_rank: Rank, to_file: File, to_rank: Rank) -> Move {
+        Move{from_file, from_rank, to_file, to_rank, promotion: None}
+    }
+
+    pub fn with_promotion(from_file: File, from_rank: Rank, to_file: File, to_rank: Rank, promotion: PieceType) -> Move {
+        Move{from_file, from_rank, to_file, to_rank, promotion: Some(promotion)}
+    }
+
+    // Parse a move in long algebraic (UCI) notation, e.g. "e2e4" or "e7e8q"
+    pub fn from_uci(uci: &str) -> Result<Move, &'static str> {
+        let chars: Vec<char> = uci.chars().collect();
+        if chars.len() != 4 && chars.len() != 5 {
+            return Err("Move must be 4 or 5 characters long");
+        }
+        let from_file = File::from_char(chars[0])?;
+        let from_rank = Rank::from_char(chars[1])?;
+        let to_file = File::from_char(chars[2])?;
+        let to_rank = Rank::from_char(chars[3])?;
+        match chars.get(4) {
+            None => Ok(Move::new(from_file, from_rank, to_file, to_rank)),
+            Some(c) => {
+                let promotion = match c {
+                    'n' => PieceType::Knight,
+                    'b' => PieceType::Bishop,
+                    'r' => PieceType::Rook,
+                    'q' => PieceType::Queen,
+                    _ => return Err("Invalid promotion piece")
+                };
+                Ok(Move::with_promotion(from_file, from_rank, to_file, to_rank, promotion))
+            }
+        }
+    }
+
+    pub fn from(&self) -> (File, Rank) {
+        (self.from_file, self.from_rank)
+    }
+
+    pub fn to(&self) -> (File, Rank) {
+        (self.to_file, self.to_rank)
+    }
+}
+
+impl fmt::Display for Move {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{}{}{}", self.from_file, self.from_rank, self.to_file, self.to_rank)?;
+        match self.promotion {
+            Some(PieceType::Knight) => write!(f, "n"),
+            Some(PieceType::Bishop) => write!(f, "b"),
+            Some(PieceType::Rook) => write!(f, "r"),
+            Some(PieceType::Queen) => write!(f, "q"),
+            _ => Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod move_tests {
+    use super::*;
+
+    #[test]
+    fn uci_round_trip() {
+        let mv = Move::from_uci("e2e4").unwrap();
+        assert_eq!(mv.from(), (File::build(4).unwrap(), Rank::build(1).unwrap()));
+        assert_eq!(mv.to(), (File::build(4).unwrap(), Rank::build(3).unwrap()));
+        assert_eq!(mv.promotion, None);
+        assert_eq!(mv.to_string(), "e2e4");
+    }
+
+    #[test]
+    fn uci_promotion() {
+        let mv = Move::from_uci("a7a8q").unwrap();
+        assert_eq!(mv.promotion, Some(PieceType::Queen));
+        assert_eq!(mv.to_string(), "a7a8q");
+    }
+
+    #[test]
+    fn uci_invalid() {
+        assert!(Move::from_uci("e2").is_err());
+        assert!(Move::from_uci("i2e4").is_err());
+        assert!(Move::from_uci("e2e9").is_err());
+        assert!(Move::from_uci("e7e8k").is_err());
+    }
+}
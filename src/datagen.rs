@@ -0,0 +1,251 @@
+use crate::board::Board;
+use crate::piece::Color;
+use crate::piece::PieceType;
+use crate::random::Random;
+use crate::search;
+use crate::search::SearchLimits;
+use crate::search::Searcher;
+
+use std::fmt;
+use std::io;
+use std::io::Write;
+use std::sync::Mutex;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::thread;
+
+#[derive(Debug, Clone)]
+pub struct DatagenOptions {
+    pub games: usize,
+    pub threads: usize,
+    // Every move is searched to this depth or for this many nodes, whichever
+    // comes first
+    pub depth: Option<u32>,
+    pub nodes: Option<u64>,
+    // Random moves played from the starting position before the engine
+    // takes over, so that no two games are alike
+    pub random_plies: usize,
+    // Games still going after this many plies are scored as draws
+    pub max_plies: usize,
+    // Transposition table size of each thread's searcher
+    pub hash_mb: usize,
+    pub seed: u64,
+}
+
+impl DatagenOptions {
+    pub fn new() -> DatagenOptions {
+        DatagenOptions{
+            games: 100,
+            threads: 1,
+            depth: Some(6),
+            nodes: None,
+            random_plies: 8,
+            max_plies: 400,
+            hash_mb: 16,
+            seed: 1,
+        }
+    }
+}
+
+impl Default for DatagenOptions {
+    fn default() -> DatagenOptions {
+        DatagenOptions::new()
+    }
+}
+
+// A position from a self-play game with the search score, in centipawns,
+// and the result of the game, 1 for a White win, 0.5 for a draw and 0 for
+// a Black win, both from White's point of view
+#[derive(Debug, Clone, PartialEq)]
+pub struct DataPoint {
+    pub fen: String,
+    pub score: i32,
+    pub result: f64,
+}
+
+// One line per position: FEN | score | result
+impl fmt::Display for DataPoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} | {} | {:.1}", self.fen, self.score, self.result)
+    }
+}
+
+// Neither side can possibly mate: bare kings or a single minor piece
+fn insufficient_material(board: &Board) -> bool {
+    let others: Vec<PieceType> = board.pieces()
+        .map(|square| square.piece.unwrap().piece_type())
+        .filter(|piece_type| *piece_type != PieceType::King)
+        .collect();
+    match others.as_slice() {
+        [] => true,
+        [piece_type] => *piece_type == PieceType::Knight || *piece_type == PieceType::Bishop,
+        _ => false
+    }
+}
+
+fn white_wins(color: Color) -> f64 {
+    match color {
+        Color::White => 1.0,
+        Color::Black => 0.0
+    }
+}
+
+// A position to start a game from, after the random opening moves, with
+// moves left to play
+fn random_opening(random: &mut Random, plies: usize) -> Board {
+    loop {
+        let mut board = Board::build_starting().unwrap();
+        for _ in 0..plies {
+            let moves = board.legal_moves();
+            if moves.is_empty() {
+                break;
+            }
+            board.make_move(moves[random.below(moves.len() as u64) as usize]);
+        }
+        if !board.legal_moves().is_empty() {
+            return board;
+        }
+    }
+}
+
+// Play one game against itself and return its quiet positions: those not
+// in check where the move chosen is not a capture or promotion. A game ends
+// in mate, stalemate, a repetition, the fifty-move rule, insufficient
+// material or once the search finds a forced mate.
+pub fn play_game(searcher: &mut Searcher, random: &mut Random, options: &DatagenOptions) -> Vec<DataPoint> {
+    let mut board = random_opening(random, options.random_plies);
+    let limits = SearchLimits{depth: options.depth, nodes: options.nodes, ..SearchLimits::new()};
+    let mut positions = Vec::new();
+    let mut plies = 0;
+    let result = loop {
+        if board.legal_moves().is_empty() {
+            break if board.in_check() { white_wins(board.side_to_move().opposite()) } else { 0.5 };
+        }
+        if board.halfmove_clock() >= 100 || board.is_repetition() || insufficient_material(&board) || plies >= options.max_plies {
+            break 0.5;
+        }
+
+        let found = searcher.search(&mut board, &limits);
+        let mv = match found.best_move {
+            Some(mv) => mv,
+            None => break 0.5
+        };
+        let score = match board.side_to_move() {
+            Color::White => found.score,
+            Color::Black => -found.score
+        };
+        if search::is_mate_score(score) {
+            break if score > 0 { 1.0 } else { 0.0 };
+        }
+        if !board.in_check() && !board.is_capture(mv) && mv.promotion.is_none() {
+            positions.push(DataPoint{fen: board.to_fen(), score, result: 0.0});
+        }
+        board.make_move(mv);
+        plies += 1;
+    };
+    for position in positions.iter_mut() {
+        position.result = result;
+    }
+    positions
+}
+
+// Play options.games games over options.threads threads, writing every
+// position as a line of text. progress is called after each game with the
+// games and positions so far. Returns the number of positions written.
+pub fn generate<W: Write + Send, F: Fn(usize, usize) + Sync>(options: &DatagenOptions, writer: &mut W, progress: F) -> io::Result<usize> {
+    let next_game = AtomicUsize::new(0);
+    let failed = AtomicBool::new(false);
+    // The writer, games finished, positions written and the first error
+    let output = Mutex::new((writer, 0, 0, None));
+    thread::scope(|scope| {
+        for _ in 0..options.threads.max(1) {
+            let (next_game, failed, output, progress) = (&next_game, &failed, &output, &progress);
+            scope.spawn(move || {
+                let mut searcher = Searcher::new();
+                searcher.set_hash_size(options.hash_mb);
+                // Each game gets its own generator, so the games played do
+                // not depend on how they are spread over the threads
+                while !failed.load(Ordering::Relaxed) {
+                    let game = next_game.fetch_add(1, Ordering::Relaxed);
+                    if game >= options.games {
+                        break;
+                    }
+                    let mut random = Random::new(options.seed.wrapping_add(game as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15));
+                    searcher.clear_hash();
+                    let positions = play_game(&mut searcher, &mut random, options);
+
+                    let mut output = output.lock().unwrap();
+                    let (writer, games, written, error) = &mut *output;
+                    if let Err(err) = positions.iter().try_for_each(|position| writeln!(writer, "{}", position)) {
+                        *error = Some(err);
+                        failed.store(true, Ordering::Relaxed);
+                        break;
+                    }
+                    *games += 1;
+                    *written += positions.len();
+                    progress(*games, *written);
+                }
+            });
+        }
+    });
+    let (writer, _, written, error) = output.into_inner().unwrap();
+    match error {
+        Some(err) => Err(err),
+        None => writer.flush().map(|_| written)
+    }
+}
+
+#[cfg(test)]
+mod datagen_tests {
+    use super::*;
+
+    fn options() -> DatagenOptions {
+        DatagenOptions{games: 4, threads: 2, depth: Some(1), max_plies: 40, hash_mb: 1, ..DatagenOptions::new()}
+    }
+
+    #[test]
+    fn games_record_quiet_positions_with_the_result() {
+        let mut searcher = Searcher::new();
+        let options = options();
+        let positions = play_game(&mut searcher, &mut Random::new(3), &options);
+        assert!(!positions.is_empty() && positions.len() <= options.max_plies);
+        let result = positions[0].result;
+        assert!([0.0, 0.5, 1.0].contains(&result));
+        for position in positions.iter() {
+            assert_eq!(position.result, result);
+            let board = Board::from_fen(&position.fen).unwrap();
+            assert!(!board.in_check());
+        }
+        // The same seed plays the same game
+        assert_eq!(play_game(&mut Searcher::new(), &mut Random::new(3), &options), positions);
+    }
+
+    #[test]
+    fn recognises_dead_draws() {
+        assert!(insufficient_material(&Board::from_fen("4k3/8/8/8/8/8/8/4K3 w - - 0 1").unwrap()));
+        assert!(insufficient_material(&Board::from_fen("4k3/8/8/8/8/8/8/2B1K3 w - - 0 1").unwrap()));
+        assert!(!insufficient_material(&Board::from_fen("4k3/8/8/8/8/8/8/2BBK3 w - - 0 1").unwrap()));
+        assert!(!insufficient_material(&Board::from_fen("4k3/8/8/8/8/8/4P3/4K3 w - - 0 1").unwrap()));
+    }
+
+    #[test]
+    fn threads_write_every_game() {
+        let mut output = Vec::new();
+        let games = AtomicUsize::new(0);
+        let written = generate(&options(), &mut output, |finished, _| { games.fetch_max(finished, Ordering::Relaxed); }).unwrap();
+        assert_eq!(games.load(Ordering::Relaxed), 4);
+        let text = String::from_utf8(output).unwrap();
+        assert_eq!(text.lines().count(), written);
+        for line in text.lines() {
+            let fields: Vec<&str> = line.split(" | ").collect();
+            assert_eq!(fields.len(), 3);
+            assert!(Board::from_fen(fields[0]).is_ok());
+            assert!(fields[1].parse::<i32>().is_ok());
+            assert!(["0.0", "0.5", "1.0"].contains(&fields[2]));
+        }
+        // The positions can be tuned on as they are
+        let mut tuner = crate::tuner::Tuner::new();
+        assert_eq!(tuner.read_epd(text.as_bytes()).unwrap(), written);
+    }
+}
@@ -0,0 +1,475 @@
+use crate::board::Board;
+use crate::file::File;
+use crate::rank::Rank;
+use crate::piece::Color;
+use crate::piece::Piece;
+use crate::piece::PieceType;
+use crate::attacks;
+use crate::attacks::AttackMap;
+use crate::king_safety::king_safety;
+use crate::pawns;
+use crate::pawns::PawnEntry;
+use crate::pawns::PawnTable;
+
+use std::fmt;
+use std::ops::Add;
+use std::ops::AddAssign;
+use std::ops::Mul;
+use std::ops::Neg;
+use std::ops::Sub;
+use std::ops::SubAssign;
+
+// Phase of a position with all pieces on the board; it falls towards 0 as
+// knights, bishops, rooks and queens come off
+pub const MAX_PHASE: i32 = 24;
+
+// A middlegame and an endgame value, blended by game phase
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Score {
+    pub mg: i32,
+    pub eg: i32,
+}
+
+impl Score {
+    pub const fn new(mg: i32, eg: i32) -> Score {
+        Score{mg, eg}
+    }
+
+    // Interpolate between the endgame value at phase 0 and the middlegame
+    // value at MAX_PHASE
+    pub fn taper(&self, phase: i32) -> i32 {
+        let phase = phase.clamp(0, MAX_PHASE);
+        (self.mg * phase + self.eg * (MAX_PHASE - phase)) / MAX_PHASE
+    }
+}
+
+impl Add for Score {
+    type Output = Score;
+
+    fn add(self, other: Score) -> Score {
+        Score::new(self.mg + other.mg, self.eg + other.eg)
+    }
+}
+
+impl AddAssign for Score {
+    fn add_assign(&mut self, other: Score) {
+        *self = *self + other;
+    }
+}
+
+impl Sub for Score {
+    type Output = Score;
+
+    fn sub(self, other: Score) -> Score {
+        Score::new(self.mg - other.mg, self.eg - other.eg)
+    }
+}
+
+impl SubAssign for Score {
+    fn sub_assign(&mut self, other: Score) {
+        *self = *self - other;
+    }
+}
+
+impl Neg for Score {
+    type Output = Score;
+
+    fn neg(self) -> Score {
+        Score::new(-self.mg, -self.eg)
+    }
+}
+
+impl Mul<i32> for Score {
+    type Output = Score;
+
+    fn mul(self, factor: i32) -> Score {
+        Score::new(self.mg * factor, self.eg * factor)
+    }
+}
+
+// Material by piece type, in the order of piece_index
+pub const MATERIAL: [Score; 6] = [
+    Score::new(82, 94),
+    Score::new(337, 281),
+    Score::new(365, 297),
+    Score::new(477, 512),
+    Score::new(1025, 936),
+    Score::new(0, 0),
+];
+
+const PHASE_WEIGHTS: [i32; 6] = [0, 1, 1, 2, 4, 0];
+
+// Piece-square tables from White's point of view, laid out as the board is
+// drawn: the first row is the eighth rank, a-file first. Black uses them
+// mirrored.
+pub const PST_MG: [[i32; 64]; 6] = [
+    [
+          0,   0,   0,   0,   0,   0,   0,   0,
+         98, 134,  61,  95,  68, 126,  34, -11,
+         -6,   7,  26,  31,  65,  56,  25, -20,
+        -14,  13,   6,  21,  23,  12,  17, -23,
+        -27,  -2,  -5,  12,  17,   6,  10, -25,
+        -26,  -4,  -4, -10,   3,   3,  33, -12,
+        -35,  -1, -20, -23, -15,  24,  38, -22,
+          0,   0,   0,   0,   0,   0,   0,   0,
+    ],
+    [
+       -167, -89, -34, -49,  61, -97, -15,-107,
+        -73, -41,  72,  36,  23,  62,   7, -17,
+        -47,  60,  37,  65,  84, 129,  73,  44,
+         -9,  17,  19,  53,  37,  69,  18,  22,
+        -13,   4,  16,  13,  28,  19,  21,  -8,
+        -23,  -9,  12,  10,  19,  17,  25, -16,
+        -29, -53, -12,  -3,  -1,  18, -14, -19,
+       -105, -21, -58, -33, -17, -28, -19, -23,
+    ],
+    [
+        -29,   4, -82, -37, -25, -42,   7,  -8,
+        -26,  16, -18, -13,  30,  59,  18, -47,
+        -16,  37,  43,  40,  35,  50,  37,  -2,
+         -4,   5,  19,  50,  37,  37,   7,  -2,
+         -6,  13,  13,  26,  34,  12,  10,   4,
+          0,  15,  15,  15,  14,  27,  18,  10,
+          4,  15,  16,   0,   7,  21,  33,   1,
+        -33,  -3, -14, -21, -13, -12, -39, -21,
+    ],
+    [
+         32,  42,  32,  51,  63,   9,  31,  43,
+         27,  32,  58,  62,  80,  67,  26,  44,
+         -5,  19,  26,  36,  17,  45,  61,  16,
+        -24, -11,   7,  26,  24,  35,  -8, -20,
+        -36, -26, -12,  -1,   9,  -7,   6, -23,
+        -45, -25, -16, -17,   3,   0,  -5, -33,
+        -44, -16, -20,  -9,  -1,  11,  -6, -71,
+        -19, -13,   1,  17,  16,   7, -37, -26,
+    ],
+    [
+        -28,   0,  29,  12,  59,  44,  43,  45,
+        -24, -39,  -5,   1, -16,  57,  28,  54,
+        -13, -17,   7,   8,  29,  56,  47,  57,
+        -27, -27, -16, -16,  -1,  17,  -2,   1,
+         -9, -26,  -9, -10,  -2,  -4,   3,  -3,
+        -14,   2, -11,  -2,  -5,   2,  14,   5,
+        -35,  -8,  11,   2,   8,  15,  -3,   1,
+         -1, -18,  -9,  10, -15, -25, -31, -50,
+    ],
+    [
+        -65,  23,  16, -15, -56, -34,   2,  13,
+         29,  -1, -20,  -7,  -8,  -4, -38, -29,
+         -9,  24,   2, -16, -20,   6,  22, -22,
+        -17, -20, -12, -27, -30, -25, -14, -36,
+        -49,  -1, -27, -39, -46, -44, -33, -51,
+        -14, -14, -22, -46, -44, -30, -15, -27,
+          1,   7,  -8, -64, -43, -16,   9,   8,
+        -15,  36,  12, -54,   8, -28,  24,  14,
+    ],
+];
+
+pub const PST_EG: [[i32; 64]; 6] = [
+    [
+          0,   0,   0,   0,   0,   0,   0,   0,
+        178, 173, 158, 134, 147, 132, 165, 187,
+         94, 100,  85,  67,  56,  53,  82,  84,
+         32,  24,  13,   5,  -2,   4,  17,  17,
+         13,   9,  -3,  -7,  -7,  -8,   3,  -1,
+          4,   7,  -6,   1,   0,  -5,  -1,  -8,
+         13,   8,   8,  10,  13,   0,   2,  -7,
+          0,   0,   0,   0,   0,   0,   0,   0,
+    ],
+    [
+        -58, -38, -13, -28, -31, -27, -63, -99,
+        -25,  -8, -25,  -2,  -9, -25, -24, -52,
+        -24, -20,  10,   9,  -1,  -9, -19, -41,
+        -17,   3,  22,  22,  22,  11,   8, -18,
+        -18,  -6,  16,  25,  16,  17,   4, -18,
+        -23,  -3,  -1,  15,  10,  -3, -20, -22,
+        -42, -20, -10,  -5,  -2, -20, -23, -44,
+        -29, -51, -23, -15, -22, -18, -50, -64,
+    ],
+    [
+        -14, -21, -11,  -8,  -7,  -9, -17, -24,
+         -8,  -4,   7, -12,  -3, -13,  -4, -14,
+          2,  -8,   0,  -1,  -2,   6,   0,   4,
+         -3,   9,  12,   9,  14,  10,   3,   2,
+         -6,   3,  13,  19,   7,  10,  -3,  -9,
+        -12,  -3,   8,  10,  13,   3,  -7, -15,
+        -14, -18,  -7,  -1,   4,  -9, -15, -27,
+        -23,  -9, -23,  -5,  -9, -16,  -5, -17,
+    ],
+    [
+         13,  10,  18,  15,  12,  12,   8,   5,
+         11,  13,  13,  11,  -3,   3,   8,   3,
+          7,   7,   7,   5,   4,  -3,  -5,  -3,
+          4,   3,  13,   1,   2,   1,  -1,   2,
+          3,   5,   8,   4,  -5,  -6,  -8, -11,
+         -4,   0,  -5,  -1,  -7, -12,  -8, -16,
+         -6,  -6,   0,   2,  -9,  -9, -11,  -3,
+         -9,   2,   3,  -1,  -5, -13,   4, -20,
+    ],
+    [
+         -9,  22,  22,  27,  27,  19,  10,  20,
+        -17,  20,  32,  41,  58,  25,  30,   0,
+        -20,   6,   9,  49,  47,  35,  19,   9,
+          3,  22,  24,  45,  57,  40,  57,  36,
+        -18,  28,  19,  47,  31,  34,  39,  23,
+        -16, -27,  15,   6,   9,  17,  10,   5,
+        -22, -23, -30, -16, -16, -23, -36, -32,
+        -33, -28, -22, -43,  -5, -32, -20, -41,
+    ],
+    [
+        -74, -35, -18, -18, -11,  15,   4, -17,
+        -12,  17,  14,  17,  17,  38,  23,  11,
+         10,  17,  23,  15,  20,  45,  44,  13,
+         -8,  22,  24,  27,  26,  33,  26,   3,
+        -18,  -4,  21,  24,  27,  23,   9, -11,
+        -19,  -3,  11,  21,  23,  16,   7,  -9,
+        -27, -11,   4,  13,  14,   4,  -5, -17,
+        -53, -34, -21, -11, -28, -14, -24, -43,
+    ],
+];
+
+pub fn piece_index(piece_type: PieceType) -> usize {
+    match piece_type {
+        PieceType::Pawn => 0,
+        PieceType::Knight => 1,
+        PieceType::Bishop => 2,
+        PieceType::Rook => 3,
+        PieceType::Queen => 4,
+        PieceType::King => 5,
+    }
+}
+
+// Where a piece on a square is found in its piece-square tables
+pub fn table_square(piece: &Piece, file: File, rank: Rank) -> usize {
+    let relative_rank = match piece.get_color() {
+        Color::White => 7 - rank.value(),
+        Color::Black => rank.value()
+    };
+    usize::from(relative_rank * 8 + file.value())
+}
+
+// Material and table value of a piece, from its own side's point of view
+fn material_and_table(piece: &Piece, file: File, rank: Rank) -> (Score, Score) {
+    let index = piece_index(piece.piece_type());
+    let square = table_square(piece, file, rank);
+    (MATERIAL[index], Score::new(PST_MG[index][square], PST_EG[index][square]))
+}
+
+// Material and table value of a piece on a square, positive for White
+pub fn piece_square(piece: &Piece, file: File, rank: Rank) -> Score {
+    let (material, table) = material_and_table(piece, file, rank);
+    let score = material + table;
+    match piece.get_color() {
+        Color::White => score,
+        Color::Black => -score
+    }
+}
+
+// How much a piece adds to the game phase
+pub fn phase_weight(piece_type: PieceType) -> i32 {
+    PHASE_WEIGHTS[piece_index(piece_type)]
+}
+
+// Static evaluation in centipawns from the side to move's point of view,
+// analysing the pawns afresh. Searches use an Evaluator instead.
+pub fn evaluate(board: &Board) -> i32 {
+    evaluate_with(board, &PawnEntry::analyse(board))
+}
+
+fn evaluate_with(board: &Board, pawns: &PawnEntry) -> i32 {
+    let attacks = AttackMap::new(board);
+    let mut total = board.psqt() + pawns.score();
+    total += pawns::passed_pawn_extras(board, pawns, Color::White) - pawns::passed_pawn_extras(board, pawns, Color::Black);
+    total += attacks::mobility(&attacks, Color::White) - attacks::mobility(&attacks, Color::Black);
+    total += king_safety(board, &attacks, Color::White) - king_safety(board, &attacks, Color::Black);
+    let score = total.taper(board.phase());
+    match board.side_to_move() {
+        Color::White => score,
+        Color::Black => -score
+    }
+}
+
+// One evaluation term for each side, each from its own point of view
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceTerm {
+    pub name: &'static str,
+    pub white: Score,
+    pub black: Score,
+}
+
+// The evaluation of a position broken down by term
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EvalTrace {
+    pub terms: Vec<TraceTerm>,
+    pub phase: i32,
+    pub side_to_move: Color,
+}
+
+impl EvalTrace {
+    // White minus Black over all terms
+    pub fn total(&self) -> Score {
+        self.terms.iter().fold(Score::default(), |total, term| total + term.white - term.black)
+    }
+
+    // The evaluation, from the side to move's point of view
+    pub fn score(&self) -> i32 {
+        let score = self.total().taper(self.phase);
+        match self.side_to_move {
+            Color::White => score,
+            Color::Black => -score
+        }
+    }
+}
+
+impl fmt::Display for EvalTrace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{:<14} | {:^13} | {:^13} | {:^13}", "Term", "White", "Black", "Total")?;
+        writeln!(f, "{:<14} | {:>6} {:>6} | {:>6} {:>6} | {:>6} {:>6}", "", "mg", "eg", "mg", "eg", "mg", "eg")?;
+        writeln!(f, "{:-<14}-+-{:-<13}-+-{:-<13}-+-{:-<13}", "", "", "", "")?;
+        for term in self.terms.iter() {
+            let total = term.white - term.black;
+            writeln!(f, "{:<14} | {:>6} {:>6} | {:>6} {:>6} | {:>6} {:>6}", term.name,
+                term.white.mg, term.white.eg, term.black.mg, term.black.eg, total.mg, total.eg)?;
+        }
+        writeln!(f, "{:-<14}-+-{:-<13}-+-{:-<13}-+-{:-<13}", "", "", "", "")?;
+        let total = self.total();
+        writeln!(f, "{:<14} | {:>13} | {:>13} | {:>6} {:>6}", "Total", "", "", total.mg, total.eg)?;
+        writeln!(f, "Phase {}/{}, {} cp for White, {} cp for the side to move",
+            self.phase.clamp(0, MAX_PHASE), MAX_PHASE, total.taper(self.phase), self.score())
+    }
+}
+
+// Every term of the evaluation for both sides and both game phases, adding
+// up to what evaluate returns
+pub fn eval_trace(board: &Board) -> EvalTrace {
+    let mut material = [Score::default(); 2];
+    let mut tables = [Score::default(); 2];
+    for square in board.pieces() {
+        let piece = square.piece.unwrap();
+        let side = piece.get_color().index();
+        let (piece_material, table) = material_and_table(&piece, square.file, square.rank);
+        material[side] += piece_material;
+        tables[side] += table;
+    }
+    let pawns = PawnEntry::analyse(board);
+    let attacks = AttackMap::new(board);
+    let term = |name: &'static str, value: &dyn Fn(Color) -> Score| TraceTerm{name, white: value(Color::White), black: value(Color::Black)};
+    let terms = vec![
+        TraceTerm{name: "Material", white: material[0], black: material[1]},
+        TraceTerm{name: "Piece-square", white: tables[0], black: tables[1]},
+        TraceTerm{name: "Pawns", white: pawns.scores[0], black: pawns.scores[1]},
+        term("Passed pawns", &|color| pawns::passed_pawn_extras(board, &pawns, color)),
+        term("Mobility", &|color| attacks::mobility(&attacks, color)),
+        term("King safety", &|color| king_safety(board, &attacks, color)),
+    ];
+    EvalTrace{terms, phase: board.phase(), side_to_move: board.side_to_move()}
+}
+
+// Evaluates positions with a cache of pawn structures, or with the NNUE
+// network when the board keeps an accumulator for one
+pub struct Evaluator {
+    pawn_table: PawnTable,
+}
+
+impl Evaluator {
+    pub fn new() -> Evaluator {
+        Evaluator{pawn_table: PawnTable::new()}
+    }
+
+    pub fn clear(&mut self) {
+        self.pawn_table.clear();
+    }
+
+    pub fn evaluate(&mut self, board: &Board) -> i32 {
+        if let Some(score) = board.accumulator().and_then(|accumulator| accumulator.evaluate(board.side_to_move())) {
+            return score;
+        }
+        let pawns = self.pawn_table.probe(board);
+        evaluate_with(board, &pawns)
+    }
+}
+
+impl Default for Evaluator {
+    fn default() -> Evaluator {
+        Evaluator::new()
+    }
+}
+
+#[cfg(test)]
+mod eval_tests {
+    use super::*;
+    use crate::chess_move::Move;
+
+    #[test]
+    fn starting_position_is_balanced() {
+        let board = Board::build_starting().unwrap();
+        assert_eq!(board.phase(), MAX_PHASE);
+        assert_eq!(board.psqt(), Score::default());
+        assert_eq!(evaluate(&board), 0);
+        assert_eq!(Evaluator::new().evaluate(&board), 0);
+    }
+
+    #[test]
+    fn scores_from_the_side_to_move() {
+        let white = Board::from_fen("4k3/8/8/8/8/8/8/3QK3 w - - 0 1").unwrap();
+        let black = Board::from_fen("4k3/8/8/8/8/8/8/3QK3 b - - 0 1").unwrap();
+        assert!(evaluate(&white) > 800);
+        assert_eq!(evaluate(&black), -evaluate(&white));
+        // Mirrored, Black is as well off as White was
+        let mirrored = Board::from_fen("3qk3/8/8/8/8/8/8/4K3 b - - 0 1").unwrap();
+        assert_eq!(evaluate(&mirrored), evaluate(&white));
+    }
+
+    #[test]
+    fn tapers_between_middlegame_and_endgame() {
+        let score = Score::new(100, -20);
+        assert_eq!(score.taper(MAX_PHASE), 100);
+        assert_eq!(score.taper(0), -20);
+        assert_eq!(score.taper(MAX_PHASE / 2), 40);
+        assert_eq!(score.taper(MAX_PHASE + 6), 100);
+        // A king wants shelter with queens on and the centre without
+        let corner = piece_square(&Piece::new(Color::White, PieceType::King, false), File::build(6).unwrap(), Rank::build(0).unwrap());
+        let centre = piece_square(&Piece::new(Color::White, PieceType::King, false), File::build(4).unwrap(), Rank::build(3).unwrap());
+        assert!(corner.mg > centre.mg && corner.eg < centre.eg);
+    }
+
+    #[test]
+    fn incremental_update_matches_a_fresh_board() {
+        let mut board = Board::from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1").unwrap();
+        let start = (board.psqt(), board.phase());
+        // Castling, a capture, en passant and a promotion
+        for uci in ["e1g1", "h3g2", "a2a4", "b4a3", "f3f6", "a3b2", "d2e3", "b2a1q"].iter() {
+            board.play(Move::from_uci(uci).unwrap()).unwrap();
+            let fresh = Board::from_fen(&board.to_fen()).unwrap();
+            assert_eq!((board.psqt(), board.phase(), board.pawn_key()), (fresh.psqt(), fresh.phase(), fresh.pawn_key()));
+        }
+        while board.last_move().is_some() {
+            board.unmake_move();
+        }
+        assert_eq!((board.psqt(), board.phase()), start);
+    }
+
+    #[test]
+    fn trace_adds_up_to_the_evaluation() {
+        for fen in ["rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+                "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R b KQkq - 0 1",
+                "8/5k2/1P6/8/3K4/8/6p1/8 w - - 0 1"].iter() {
+            let board = Board::from_fen(fen).unwrap();
+            let trace = eval_trace(&board);
+            assert_eq!(trace.score(), evaluate(&board));
+            assert_eq!(trace.terms[0].white + trace.terms[1].white - trace.terms[0].black - trace.terms[1].black, board.psqt());
+        }
+    }
+
+    #[test]
+    fn trace_prints_a_row_per_term() {
+        let board = Board::from_fen("4k3/8/8/8/8/8/4P3/3QK3 w - - 0 1").unwrap();
+        let trace = eval_trace(&board);
+        assert_eq!(trace.terms[0], TraceTerm{name: "Material", white: MATERIAL[4] + MATERIAL[0], black: Score::default()});
+        let table = trace.to_string();
+        for term in trace.terms.iter() {
+            assert!(table.lines().any(|line| line.starts_with(term.name)));
+        }
+        assert!(table.contains(&format!("{} cp for the side to move", evaluate(&board))));
+    }
+}
@@ -18,19 +18,46 @@ impl File {
     }
 
     pub fn iter_files(range: impl Iterator<Item = u8>) -> impl Iterator<Item = File> {
-        range.filter(|file| file < &MAX_NUMBER_OF_FILES).map(|file| File::new(file))
+        range.filter(|file| file < &MAX_NUMBER_OF_FILES).map(File::new)
     }
 
     pub fn value(&self) -> u8 {
         self.val
     }
 
+    // Parse a file from its algebraic character ('a'-'h')
+    pub fn from_char(c: char) -> Result<File, &'static str> {
+        match (c as u32).checked_sub('a' as u32) {
+            Some(val) if val < u32::from(MAX_NUMBER_OF_FILES) => Ok(File{val: val as u8}),
+            _ => Err("File character outside allowable bounds")
+        }
+    }
+
+    pub fn to_char(&self) -> char {
+        (b'a' + self.val) as char
+    }
+
     pub fn next_by(&self, jump: u8) -> Result<File, &'static str> {
-        File::build(self.val + jump)
+        match self.val.checked_add(jump) {
+            Some(val) => File::build(val),
+            None => Err("File outside allowable bounds")
+        }
     }
 
     pub fn previous_by(&self, jump: u8) -> Result<File, &'static str> {
-        File::build(self.val - jump)
+        match self.val.checked_sub(jump) {
+            Some(val) => File::build(val),
+            None => Err("File outside allowable bounds")
+        }
+    }
+
+    // Step by a signed amount, used when walking rays across the board
+    pub fn offset(&self, delta: i8) -> Result<File, &'static str> {
+        if delta >= 0 {
+            self.next_by(delta as u8)
+        } else {
+            self.previous_by(delta.unsigned_abs())
+        }
     }
 }
 
@@ -56,4 +83,19 @@ impl PartialOrd for File {
     fn partial_cmp(&self, other: &File) -> Option<Ordering> {
         Some(self.cmp(other))
     }
-}
\ No newline at end of file
+}
+
+use std::hash::Hash;
+use std::hash::Hasher;
+impl Hash for File {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.val.hash(state);
+    }
+}
+
+use std::fmt;
+impl fmt::Display for File {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.to_char())
+    }
+}
@@ -0,0 +1,145 @@
+use crate::attacks::bit;
+use crate::attacks::AttackMap;
+use crate::board::Board;
+use crate::eval::Score;
+use crate::file::File;
+use crate::rank::Rank;
+use crate::piece::Color;
+use crate::piece::PieceType;
+use crate::piece::KING_OFFSETS;
+
+// Attack units per king zone square a piece attacks
+const ATTACK_WEIGHTS: [(PieceType, i32); 4] = [
+    (PieceType::Knight, 2),
+    (PieceType::Bishop, 2),
+    (PieceType::Rook, 3),
+    (PieceType::Queen, 5),
+];
+// The middlegame penalty grows with the square of the attack units, up to
+// this much
+const MAX_KING_DANGER: i32 = 500;
+// Shield pawns on the king's file and its neighbours: one square ahead of
+// the king is ideal, two squares is weaker, none at all worst
+const SHIELD_ADVANCED: Score = Score::new(-10, 0);
+const SHIELD_MISSING: Score = Score::new(-25, 0);
+const SEMI_OPEN_FILE: Score = Score::new(-15, 0);
+const OPEN_FILE: Score = Score::new(-25, 0);
+// Per square an enemy piece is closer to the king than the far side of the board
+const TROPISM: [(PieceType, i32); 4] = [
+    (PieceType::Knight, 3),
+    (PieceType::Bishop, 2),
+    (PieceType::Rook, 2),
+    (PieceType::Queen, 4),
+];
+
+fn weight(table: &[(PieceType, i32); 4], piece_type: PieceType) -> i32 {
+    table.iter().find(|(kind, _)| *kind == piece_type).map_or(0, |(_, weight)| *weight)
+}
+
+// The squares around the king and the three further ahead of it
+fn king_zone(file: File, rank: Rank, color: Color) -> u64 {
+    let forward = match color {
+        Color::White => 2,
+        Color::Black => -2
+    };
+    KING_OFFSETS.iter()
+        .chain([(-1, forward), (0, forward), (1, forward)].iter())
+        .filter_map(|(file_step, rank_step)| match (file.offset(*file_step), rank.offset(*rank_step)) {
+            (Ok(file), Ok(rank)) => Some(bit(file, rank)),
+            _ => None
+        })
+        .fold(bit(file, rank), |zone, square| zone | square)
+}
+
+// How safe a color's king is, negative when it is in danger
+pub fn king_safety(board: &Board, attacks: &AttackMap, color: Color) -> Score {
+    let (king_file, king_rank) = match board.find_king(color) {
+        Some(square) => square,
+        None => return Score::default()
+    };
+    let mut score = Score::default();
+
+    // Enemy pieces bearing on the zone, counted once at least two take part
+    let zone = king_zone(king_file, king_rank, color);
+    let mut attackers = 0;
+    let mut units = 0;
+    for piece in attacks.pieces(color.opposite()) {
+        let hits = (piece.squares & zone).count_ones() as i32;
+        if hits > 0 {
+            attackers += 1;
+            units += hits * weight(&ATTACK_WEIGHTS, piece.piece.piece_type());
+        }
+    }
+    if attackers >= 2 {
+        score -= Score::new((units * units / 4).min(MAX_KING_DANGER), 0);
+    }
+
+    // Pawn shield and open files next to the king
+    let forward = match color {
+        Color::White => 1,
+        Color::Black => -1
+    };
+    let has_pawn = |file: File, rank: Rank, color: Color| board.piece_at(file, rank)
+        .is_some_and(|piece| piece.piece_type() == PieceType::Pawn && piece.get_color() == color);
+    for file in [-1, 0, 1].iter().filter_map(|step| king_file.offset(*step).ok()) {
+        let shield = |steps: i8| king_rank.offset(steps * forward).is_ok_and(|rank| has_pawn(file, rank, color));
+        if !shield(1) {
+            score += if shield(2) { SHIELD_ADVANCED } else { SHIELD_MISSING };
+        }
+        let on_file = |color: Color| Rank::iter_ranks(0..8).any(|rank| has_pawn(file, rank, color));
+        if !on_file(color) {
+            score += if on_file(color.opposite()) { SEMI_OPEN_FILE } else { OPEN_FILE };
+        }
+    }
+
+    // Enemy pieces drawing near
+    for piece in attacks.pieces(color.opposite()) {
+        let distance = piece.file.value().abs_diff(king_file.value()).max(piece.rank.value().abs_diff(king_rank.value()));
+        score -= Score::new(weight(&TROPISM, piece.piece.piece_type()) * (7 - i32::from(distance)), 0);
+    }
+    score
+}
+
+#[cfg(test)]
+mod king_safety_tests {
+    use super::*;
+
+    fn safety(fen: &str, color: Color) -> Score {
+        let board = Board::from_fen(fen).unwrap();
+        king_safety(&board, &AttackMap::new(&board), color)
+    }
+
+    #[test]
+    fn zone_covers_the_king_and_ahead() {
+        let zone = king_zone(File::build(6).unwrap(), Rank::build(0).unwrap(), Color::White);
+        // f1, h1, f2-h2 and f3-h3 around g1
+        assert_eq!(zone.count_ones(), 9);
+        let corner = king_zone(File::build(7).unwrap(), Rank::build(7).unwrap(), Color::Black);
+        assert_eq!(corner.count_ones(), 6);
+    }
+
+    #[test]
+    fn shield_pawns_and_open_files() {
+        let sheltered = safety("6k1/8/8/8/8/8/5PPP/6K1 w - - 0 1", Color::White);
+        assert_eq!(sheltered, Score::default());
+        let advanced = safety("6k1/8/8/8/8/6P1/5P1P/6K1 w - - 0 1", Color::White);
+        assert_eq!(advanced, SHIELD_ADVANCED);
+        // Missing the g-pawn, with a black pawn still on the file
+        let semi_open = safety("6k1/6p1/8/8/8/8/5P1P/6K1 w - - 0 1", Color::White);
+        assert_eq!(semi_open, SHIELD_MISSING + SEMI_OPEN_FILE);
+        let open = safety("6k1/8/8/8/8/8/5P1P/6K1 w - - 0 1", Color::White);
+        assert_eq!(open, SHIELD_MISSING + OPEN_FILE);
+    }
+
+    #[test]
+    fn attackers_near_the_king_are_dangerous() {
+        // Queen and knight both bear on the castled king
+        let attacked = safety("6k1/8/8/8/8/5n2/5PPP/4q1K1 w - - 0 1", Color::White);
+        let one_attacker = safety("6k1/8/8/8/8/8/5PPP/4q1K1 w - - 0 1", Color::White);
+        let far = safety("qn4k1/8/8/8/8/8/5PPP/6K1 w - - 0 1", Color::White);
+        assert!(attacked.mg < one_attacker.mg);
+        assert!(one_attacker.mg < far.mg);
+        // From the far corner they are no threat at all
+        assert_eq!(far, Score::default());
+    }
+}
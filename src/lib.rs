@@ -0,0 +1,29 @@
+pub mod rank;
+pub mod file;
+pub mod square;
+pub mod board;
+pub mod piece;
+pub mod chess_move;
+pub mod render;
+pub mod svg;
+pub mod movegen;
+pub mod zobrist;
+pub mod random;
+pub mod polyglot;
+pub mod pgn;
+pub mod book_builder;
+pub mod syzygy;
+pub mod search;
+pub mod time_manager;
+pub mod tt;
+pub mod see;
+pub mod move_picker;
+pub mod eval;
+pub mod pawns;
+pub mod attacks;
+pub mod king_safety;
+pub mod nnue;
+pub mod tuner;
+pub mod datagen;
+pub mod uci;
+pub mod xboard;
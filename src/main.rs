@@ -1,22 +1,330 @@
-mod rank;
-mod file;
-mod square;
-mod board;
-mod piece;
+use chess_engine::board::Board;
+use chess_engine::book_builder::BookBuilder;
+use chess_engine::book_builder::BookOptions;
+use chess_engine::chess_move::Move;
+use chess_engine::datagen;
+use chess_engine::datagen::DatagenOptions;
+use chess_engine::eval;
+use chess_engine::nnue::Network;
+use chess_engine::pgn;
+use chess_engine::pgn::PgnReader;
+use chess_engine::piece::Color;
+use chess_engine::search;
+use chess_engine::search::EngineOptions;
+use chess_engine::search::SearchLimits;
+use chess_engine::search::Searcher;
+use chess_engine::syzygy::Tablebase;
+use chess_engine::tt;
+use chess_engine::tuner;
+use chess_engine::tuner::Tuner;
+use chess_engine::tuner::TunerOptions;
+use chess_engine::uci::UciEngine;
+use chess_engine::xboard::XboardEngine;
 
-use crate::rank::Rank;
-use crate::file::File;
-use crate::square::Square;
-use crate::piece::Piece;
+use std::env;
+use std::fs;
+use std::io;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Write;
+use std::process;
+use std::sync::Arc;
+use std::time::Duration;
 
 fn main() {
-    let rank = Rank::build(4).expect("Error creating a rank");
-    let file = File::build(7).expect("Error creating a file");
-    let piece: Option<Piece> = None;
-    let sq = Square{
-        rank,
-        file,
-        piece
+    let args: Vec<String> = env::args().collect();
+    match args.get(1).map(|arg| arg.as_str()) {
+        Some("makebook") => {
+            if let Err(err) = make_book(&args[2..]) {
+                eprintln!("makebook: {}", err);
+                process::exit(1);
+            }
+        },
+        Some("eval") => {
+            if let Err(err) = eval(&args[2..]) {
+                eprintln!("eval: {}", err);
+                process::exit(1);
+            }
+        },
+        Some("tune") => {
+            if let Err(err) = tune(&args[2..]) {
+                eprintln!("tune: {}", err);
+                process::exit(1);
+            }
+        },
+        Some("datagen") => {
+            if let Err(err) = generate_data(&args[2..]) {
+                eprintln!("datagen: {}", err);
+                process::exit(1);
+            }
+        },
+        Some("play") => {
+            if let Err(err) = play(&args[2..]) {
+                eprintln!("play: {}", err);
+                process::exit(1);
+            }
+        },
+        Some("xboard") => {
+            if let Err(err) = XboardEngine::new(io::stdout()).run(io::stdin().lock()) {
+                eprintln!("xboard: {}", err);
+                process::exit(1);
+            }
+        },
+        // Without a subcommand the engine speaks UCI, or xboard if that is
+        // what the GUI says first
+        Some("uci") | None => {
+            if let Err(err) = speak_protocol() {
+                eprintln!("{}", err);
+                process::exit(1);
+            }
+        },
+        Some(command) => {
+            eprintln!("unknown command {}", command);
+            process::exit(1);
+        }
+    }
+}
+
+fn speak_protocol() -> io::Result<()> {
+    let stdin = io::stdin();
+    let mut first = String::new();
+    stdin.lock().read_line(&mut first)?;
+    if first.trim() == "xboard" {
+        let mut engine = XboardEngine::new(io::stdout());
+        engine.handle(&first);
+        engine.run(stdin.lock())
+    } else {
+        let mut engine = UciEngine::new(io::stdout());
+        if engine.handle(&first) {
+            engine.run(stdin.lock())?;
+        }
+        Ok(())
+    }
+}
+
+// makebook <games.pgn> <book.bin> [-ply N] [-min N] [-win N] [-draw N] [-loss N]
+fn make_book(args: &[String]) -> Result<(), String> {
+    if args.len() < 2 {
+        return Err(String::from("usage: makebook <games.pgn> <book.bin> [-ply N] [-min N] [-win N] [-draw N] [-loss N]"));
+    }
+    let mut options = BookOptions::new();
+    let mut rest = args[2..].iter();
+    while let Some(flag) = rest.next() {
+        let value: u32 = rest.next()
+            .ok_or_else(|| format!("missing value for {}", flag))?
+            .parse()
+            .map_err(|_| format!("invalid value for {}", flag))?;
+        match flag.as_str() {
+            "-ply" => options.max_ply = value as usize,
+            "-min" => options.min_count = value,
+            "-win" => options.win_points = value,
+            "-draw" => options.draw_points = value,
+            "-loss" => options.loss_points = value,
+            _ => return Err(format!("unknown option {}", flag))
+        }
+    }
+
+    let file = fs::File::open(&args[0]).map_err(|err| format!("{}: {}", args[0], err))?;
+    let mut builder = BookBuilder::new(options);
+    let mut skipped = 0;
+    for game in PgnReader::new(BufReader::new(file)) {
+        let game = game.map_err(|err| err.to_string())?;
+        if builder.add_game(&game).is_err() {
+            skipped += 1;
+        }
+    }
+    let written = builder.save(&args[1]).map_err(|err| format!("{}: {}", args[1], err))?;
+    println!("{} games, {} with unplayable moves, {} book entries written", builder.games(), skipped, written);
+    Ok(())
+}
+
+// eval [FEN]
+// Show the static evaluation of a position term by term
+fn eval(args: &[String]) -> Result<(), String> {
+    let board = match args.len() {
+        0 => Board::build_starting().map_err(String::from)?,
+        _ => Board::from_fen(&args.join(" ")).map_err(String::from)?
     };
-    println!("The best square is {:?}", sq);
+    println!("{}", board);
+    print!("{}", eval::eval_trace(&board));
+    Ok(())
+}
+
+// tune <positions.epd> [-iterations N] [-rate R] [-threads N] [-start FILE] [-params FILE]
+// Tune the evaluation weights on positions labelled with game results and
+// print them as Rust source, optionally also writing a parameter file
+fn tune(args: &[String]) -> Result<(), String> {
+    if args.is_empty() {
+        return Err(String::from("usage: tune <positions.epd> [-iterations N] [-rate R] [-threads N] [-start FILE] [-params FILE]"));
+    }
+    let mut options = TunerOptions::new();
+    let mut tuner = Tuner::new();
+    let mut params_path = None;
+    let mut rest = args[1..].iter();
+    while let Some(flag) = rest.next() {
+        let value = rest.next().ok_or_else(|| format!("missing value for {}", flag))?;
+        let invalid = || format!("invalid value for {}", flag);
+        match flag.as_str() {
+            "-iterations" => options.iterations = value.parse().map_err(|_| invalid())?,
+            "-rate" => options.learning_rate = value.parse().map_err(|_| invalid())?,
+            "-threads" => options.threads = value.parse().map_err(|_| invalid())?,
+            "-start" => {
+                let file = fs::File::open(value).map_err(|err| format!("{}: {}", value, err))?;
+                let params = tuner::read_params(BufReader::new(file)).map_err(|err| format!("{}: {}", value, err))?;
+                tuner.set_params(params).map_err(String::from)?;
+            },
+            "-params" => params_path = Some(value),
+            _ => return Err(format!("unknown option {}", flag))
+        }
+    }
+
+    let file = fs::File::open(&args[0]).map_err(|err| format!("{}: {}", args[0], err))?;
+    let added = tuner.read_epd(BufReader::new(file)).map_err(|err| format!("{}: {}", args[0], err))?;
+    let k = tuner.fit_k();
+    eprintln!("{} positions, K = {:.4}, error {:.6}", added, k, tuner.error(k));
+    tuner.tune(&options, |iteration, error| eprintln!("iteration {}: error {:.6}", iteration, error));
+
+    if let Some(path) = params_path {
+        let mut file = fs::File::create(path).map_err(|err| format!("{}: {}", path, err))?;
+        tuner.write_params(&mut file).map_err(|err| format!("{}: {}", path, err))?;
+    }
+    tuner.write_rust(&mut io::stdout().lock()).map_err(|err| err.to_string())
+}
+
+// datagen <out.txt> [-games N] [-threads N] [-depth N] [-nodes N] [-random N] [-hash MB] [-seed N]
+// Play games against itself and write their quiet positions with the
+// search score and game result, one "FEN | score | result" line each
+fn generate_data(args: &[String]) -> Result<(), String> {
+    if args.is_empty() {
+        return Err(String::from("usage: datagen <out.txt> [-games N] [-threads N] [-depth N] [-nodes N] [-random N] [-hash MB] [-seed N]"));
+    }
+    let mut options = DatagenOptions::new();
+    let mut rest = args[1..].iter();
+    while let Some(flag) = rest.next() {
+        let value: u64 = rest.next()
+            .ok_or_else(|| format!("missing value for {}", flag))?
+            .parse()
+            .map_err(|_| format!("invalid value for {}", flag))?;
+        match flag.as_str() {
+            "-games" => options.games = value as usize,
+            "-threads" => options.threads = value as usize,
+            "-depth" => options.depth = Some(value as u32),
+            "-nodes" => {
+                options.nodes = Some(value);
+                options.depth = None;
+            },
+            "-random" => options.random_plies = value as usize,
+            "-hash" => options.hash_mb = value as usize,
+            "-seed" => options.seed = value,
+            _ => return Err(format!("unknown option {}", flag))
+        }
+    }
+
+    let file = fs::File::create(&args[0]).map_err(|err| format!("{}: {}", args[0], err))?;
+    let mut writer = io::BufWriter::new(file);
+    let written = datagen::generate(&options, &mut writer, |games, positions| {
+        if games % 10 == 0 || games == options.games {
+            eprintln!("{} games, {} positions", games, positions);
+        }
+    }).map_err(|err| format!("{}: {}", args[0], err))?;
+    println!("{} positions written", written);
+    Ok(())
+}
+
+// play [-depth N] [-movetime MS] [-black] [-fen FEN] [-tb DIR] [-hash MB] [-threads N] [-nnue FILE] [-disable NAME]...
+// Play against the engine, entering moves in SAN or UCI notation. -disable
+// turns off one of NullMove, LMR, Futility, ReverseFutility, Razoring or
+// CheckExtensions.
+fn play(args: &[String]) -> Result<(), String> {
+    let mut limits = SearchLimits::depth(4);
+    let mut human = Color::White;
+    let mut board = Board::build_starting().map_err(String::from)?;
+    let mut tablebase = None;
+    let mut hash_mb = tt::DEFAULT_HASH_MB;
+    let mut options = EngineOptions::new();
+    let mut network = None;
+    let mut rest = args.iter();
+    while let Some(flag) = rest.next() {
+        let mut value = || rest.next().ok_or_else(|| format!("missing value for {}", flag));
+        match flag.as_str() {
+            "-depth" => limits.depth = Some(value()?.parse().map_err(|_| format!("invalid value for {}", flag))?),
+            "-movetime" => {
+                let millis = value()?.parse().map_err(|_| format!("invalid value for {}", flag))?;
+                limits.depth = None;
+                limits.movetime = Some(Duration::from_millis(millis));
+            },
+            "-black" => human = Color::Black,
+            "-fen" => board = Board::from_fen(value()?).map_err(String::from)?,
+            "-tb" => {
+                let directory = value()?;
+                let opened = Tablebase::open(directory).map_err(|err| format!("{}: {}", directory, err))?;
+                tablebase = Some(Arc::new(opened));
+            },
+            "-hash" => hash_mb = value()?.parse().map_err(|_| format!("invalid value for {}", flag))?,
+            "-threads" => options.threads = value()?.parse().map_err(|_| format!("invalid value for {}", flag))?,
+            "-disable" => {
+                let name = value()?;
+                *options.switch_mut(name).ok_or_else(|| format!("unknown search feature {}", name))? = false;
+            },
+            "-nnue" => {
+                let path = value()?;
+                let loaded = Network::load(path).map_err(|err| format!("{}: {}", path, err))?;
+                network = Some(Arc::new(loaded));
+            },
+            _ => return Err(format!("unknown option {}", flag))
+        }
+    }
+    let mut searcher = Searcher::new();
+    searcher.set_tablebase(tablebase);
+    searcher.set_hash_size(hash_mb);
+    searcher.set_options(options);
+    searcher.set_network(network);
+
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    loop {
+        println!("{}", board);
+        if board.legal_moves().is_empty() {
+            println!("{}", if board.in_check() { "Checkmate" } else { "Stalemate" });
+            return Ok(());
+        }
+        if board.halfmove_clock() >= 100 {
+            println!("Draw by the fifty-move rule");
+            return Ok(());
+        }
+
+        if board.side_to_move() != human {
+            let result = searcher.search(&mut board, &limits);
+            let mv = result.best_move.ok_or("no move found")?;
+            let pv: Vec<String> = result.pv.iter().map(|mv| mv.to_string()).collect();
+            println!("Engine plays {} (score {}, depth {}, {} nodes, pv {})", mv, format_score(result.score), result.depth, result.nodes, pv.join(" "));
+            board.make_move(mv);
+            continue;
+        }
+
+        print!("Your move: ");
+        io::stdout().flush().map_err(|err| err.to_string())?;
+        let line = match lines.next() {
+            Some(line) => line.map_err(|err| err.to_string())?,
+            None => return Ok(())
+        };
+        let input = line.trim();
+        if input == "quit" {
+            return Ok(());
+        }
+        let mv = match Move::from_uci(input) {
+            Ok(mv) => Ok(mv),
+            Err(_) => pgn::parse_san(&mut board, input)
+        };
+        if let Err(err) = mv.and_then(|mv| board.play(mv)) {
+            println!("{}: {}", input, err);
+        }
+    }
+}
+
+fn format_score(score: i32) -> String {
+    match search::mate_in(score) {
+        Some(moves) => format!("mate {}", moves),
+        None => format!("cp {}", score)
+    }
 }
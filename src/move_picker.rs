@@ -0,0 +1,346 @@
+use crate::board::Board;
+use crate::chess_move::Move;
+use crate::file::File;
+use crate::rank::Rank;
+use crate::piece::Color;
+use crate::piece::PieceType;
+use crate::search::MAX_PLY;
+
+// History scores are kept within this bound so recent results can still
+// outweigh old ones
+const MAX_HISTORY: i32 = 16384;
+// Extra score for the quiet move that last refuted the opponent's move
+const COUNTERMOVE_BONUS: i32 = MAX_HISTORY;
+
+fn square_index(file: File, rank: Rank) -> usize {
+    usize::from(file.value()) * 8 + usize::from(rank.value())
+}
+
+// What the search has learned about quiet moves: killers that caused a
+// cutoff at the same ply, history scores for every from and to square,
+// and the countermove that refuted each opponent move
+pub struct Heuristics {
+    killers: Vec<[Option<Move>; 2]>,
+    history: Vec<i32>,
+    countermoves: Vec<Option<Move>>,
+}
+
+impl Default for Heuristics {
+    fn default() -> Heuristics {
+        Heuristics::new()
+    }
+}
+
+impl Heuristics {
+    pub fn new() -> Heuristics {
+        Heuristics{
+            killers: vec![[None; 2]; MAX_PLY + 1],
+            history: vec![0; 2 * 64 * 64],
+            countermoves: vec![None; 64 * 64],
+        }
+    }
+
+    pub fn clear(&mut self) {
+        *self = Heuristics::new();
+    }
+
+    // Killers only make sense within one search; history carries over at
+    // reduced weight
+    pub fn new_search(&mut self) {
+        for killers in self.killers.iter_mut() {
+            *killers = [None; 2];
+        }
+        for score in self.history.iter_mut() {
+            *score /= 2;
+        }
+    }
+
+    pub fn killers(&self, ply: usize) -> [Option<Move>; 2] {
+        self.killers[ply.min(MAX_PLY)]
+    }
+
+    pub fn history(&self, color: Color, mv: Move) -> i32 {
+        self.history[Self::history_index(color, mv)]
+    }
+
+    pub fn countermove(&self, previous: Option<Move>) -> Option<Move> {
+        previous.and_then(|previous| self.countermoves[Self::move_index(previous)])
+    }
+
+    // Reward a quiet move that caused a beta cutoff and punish the quiet
+    // moves searched before it that did not
+    pub fn update(&mut self, color: Color, mv: Move, previous: Option<Move>, tried: &[Move], depth: u32, ply: usize) {
+        let killers = &mut self.killers[ply.min(MAX_PLY)];
+        if killers[0] != Some(mv) {
+            killers[1] = killers[0];
+            killers[0] = Some(mv);
+        }
+        if let Some(previous) = previous {
+            self.countermoves[Self::move_index(previous)] = Some(mv);
+        }
+
+        let bonus = (depth * depth).min(MAX_HISTORY as u32) as i32;
+        self.add_history(color, mv, bonus);
+        for other in tried.iter().filter(|other| **other != mv) {
+            self.add_history(color, *other, -bonus);
+        }
+    }
+
+    // Scores move toward the bound as they grow, so a move that keeps
+    // causing cutoffs cannot crowd out the rest
+    fn add_history(&mut self, color: Color, mv: Move, bonus: i32) {
+        let score = &mut self.history[Self::history_index(color, mv)];
+        *score += bonus - *score * bonus.abs() / MAX_HISTORY;
+    }
+
+    fn move_index(mv: Move) -> usize {
+        square_index(mv.from_file, mv.from_rank) * 64 + square_index(mv.to_file, mv.to_rank)
+    }
+
+    fn history_index(color: Color, mv: Move) -> usize {
+        color.index() * 64 * 64 + Self::move_index(mv)
+    }
+}
+
+// Most valuable victim first, then least valuable attacker. Promotions
+// count as winning the promoted piece.
+pub fn mvv_lva(board: &Board, mv: Move) -> i32 {
+    let victim = match board.piece_at(mv.to_file, mv.to_rank) {
+        Some(piece) => piece.piece_type().value(),
+        None if board.is_capture(mv) => PieceType::Pawn.value(),
+        None => 0
+    };
+    let promotion = mv.promotion.map_or(0, |piece_type| piece_type.value() - PieceType::Pawn.value());
+    let attacker = board.piece_at(mv.from_file, mv.from_rank).map_or(0, |piece| piece.piece_type().value());
+    (victim + promotion) * 16 - attacker / 100
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Stage {
+    TtMove,
+    GenerateCaptures,
+    GoodCaptures,
+    Killers,
+    GenerateQuiets,
+    Quiets,
+    BadCaptures,
+    Done,
+}
+
+// Hands out pseudo-legal moves one stage at a time, best guesses first:
+// the transposition table move, captures that do not lose material by
+// MVV-LVA, killers, quiet moves by history, and finally losing captures.
+// A stage's moves are only generated once the earlier stages are used up,
+// so a cutoff on an early move saves the rest of the work. Legality is
+// left to the caller.
+pub struct MovePicker {
+    stage: Stage,
+    tt_move: Option<Move>,
+    killers: [Option<Move>; 2],
+    countermove: Option<Move>,
+    captures_only: bool,
+    moves: Vec<(Move, i32)>,
+    bad_captures: Vec<Move>,
+    killer_index: usize,
+}
+
+impl MovePicker {
+    pub fn new(tt_move: Option<Move>, killers: [Option<Move>; 2], countermove: Option<Move>) -> MovePicker {
+        MovePicker{
+            stage: Stage::TtMove,
+            tt_move,
+            killers,
+            countermove,
+            captures_only: false,
+            moves: Vec::new(),
+            bad_captures: Vec::new(),
+            killer_index: 0,
+        }
+    }
+
+    // Captures and queen promotions that do not lose material, for the
+    // quiescence search
+    pub fn captures(tt_move: Option<Move>) -> MovePicker {
+        MovePicker{captures_only: true, ..MovePicker::new(tt_move, [None; 2], None)}
+    }
+
+    pub fn next(&mut self, board: &Board, heuristics: &Heuristics) -> Option<Move> {
+        loop {
+            match self.stage {
+                Stage::TtMove => {
+                    self.stage = Stage::GenerateCaptures;
+                    match self.tt_move {
+                        Some(mv) if board.is_pseudo_legal(mv) && (!self.captures_only || (is_noisy(board, mv) && board.see_ge(mv, 0))) => return Some(mv),
+                        _ => self.tt_move = None
+                    }
+                },
+                Stage::GenerateCaptures => {
+                    self.moves = board.pseudo_legal_captures().into_iter()
+                        .filter(|mv| Some(*mv) != self.tt_move)
+                        .map(|mv| (mv, mvv_lva(board, mv)))
+                        .collect();
+                    self.stage = Stage::GoodCaptures;
+                },
+                Stage::GoodCaptures => {
+                    match self.pick_best() {
+                        Some(mv) if board.see_ge(mv, 0) => return Some(mv),
+                        Some(_) if self.captures_only => {},
+                        Some(mv) => self.bad_captures.push(mv),
+                        None => self.stage = if self.captures_only { Stage::Done } else { Stage::Killers }
+                    }
+                },
+                Stage::Killers => {
+                    let killer = self.killers.get(self.killer_index).copied();
+                    self.killer_index += 1;
+                    match killer {
+                        Some(Some(mv)) if Some(mv) != self.tt_move && board.is_pseudo_legal(mv) && !is_noisy(board, mv) => return Some(mv),
+                        Some(_) => {},
+                        None => self.stage = Stage::GenerateQuiets
+                    }
+                },
+                Stage::GenerateQuiets => {
+                    let color = board.side_to_move();
+                    self.moves = board.pseudo_legal_quiets().into_iter()
+                        .filter(|mv| Some(*mv) != self.tt_move && !self.killers.contains(&Some(*mv)))
+                        .map(|mv| {
+                            let bonus = if Some(mv) == self.countermove { COUNTERMOVE_BONUS } else { 0 };
+                            (mv, heuristics.history(color, mv) + bonus)
+                        })
+                        .collect();
+                    self.stage = Stage::Quiets;
+                },
+                Stage::Quiets => {
+                    match self.pick_best() {
+                        Some(mv) => return Some(mv),
+                        None => self.stage = Stage::BadCaptures
+                    }
+                },
+                Stage::BadCaptures => {
+                    // In the order they were found, which is still MVV-LVA
+                    if self.bad_captures.is_empty() {
+                        self.stage = Stage::Done;
+                    } else {
+                        return Some(self.bad_captures.remove(0));
+                    }
+                },
+                Stage::Done => return None
+            }
+        }
+    }
+
+    // Remove and return the highest scoring move left. A full sort would
+    // be wasted on the moves after a cutoff.
+    fn pick_best(&mut self) -> Option<Move> {
+        let best = (0..self.moves.len()).max_by_key(|&index| (self.moves[index].1, std::cmp::Reverse(index)))?;
+        Some(self.moves.swap_remove(best).0)
+    }
+}
+
+// Whether a move belongs to the capture stages rather than the quiet ones
+fn is_noisy(board: &Board, mv: Move) -> bool {
+    board.is_capture(mv) || mv.promotion == Some(PieceType::Queen)
+}
+
+#[cfg(test)]
+mod move_picker_tests {
+    use super::*;
+
+    const KIWIPETE: &str = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+
+    fn mv(uci: &str) -> Move {
+        Move::from_uci(uci).unwrap()
+    }
+
+    fn pick_all(board: &Board, mut picker: MovePicker, heuristics: &Heuristics) -> Vec<Move> {
+        let mut moves = Vec::new();
+        while let Some(mv) = picker.next(board, heuristics) {
+            moves.push(mv);
+        }
+        moves
+    }
+
+    #[test]
+    fn yields_every_move_once() {
+        let board = Board::from_fen(KIWIPETE).unwrap();
+        let heuristics = Heuristics::new();
+        let picker = MovePicker::new(Some(mv("e1g1")), [Some(mv("a2a3")), Some(mv("e5f7"))], Some(mv("a1b1")));
+        let mut picked = pick_all(&board, picker, &heuristics);
+        assert_eq!(picked[0], mv("e1g1"));
+        let mut all = board.pseudo_legal_moves();
+        picked.sort_by_key(|mv| mv.to_string());
+        all.sort_by_key(|mv| mv.to_string());
+        assert_eq!(picked, all);
+
+        // Underpromotions, capturing or not, come with the quiet moves
+        let board = Board::from_fen("r3k3/1P6/8/8/8/8/8/4K3 w - - 0 1").unwrap();
+        let mut picked = pick_all(&board, MovePicker::new(None, [None; 2], None), &heuristics);
+        let mut all = board.pseudo_legal_moves();
+        assert_eq!(all.len(), 13);
+        picked.sort_by_key(|mv| mv.to_string());
+        all.sort_by_key(|mv| mv.to_string());
+        assert_eq!(picked, all);
+    }
+
+    #[test]
+    fn stages_come_in_order() {
+        // Bxa6, gxh3 and dxe6 keep their material; Qxf6, Qxh3 and the
+        // knight captures on d7, f7 and g6 lose the capturing piece
+        let board = Board::from_fen(KIWIPETE).unwrap();
+        let heuristics = Heuristics::new();
+        let picker = MovePicker::new(None, [Some(mv("a2a3")), Some(mv("e5f7"))], None);
+        let picked = pick_all(&board, picker, &heuristics);
+        let position = |uci: &str| picked.iter().position(|picked| *picked == mv(uci)).unwrap();
+
+        // Good captures, biggest victim first
+        assert_eq!(picked[0], mv("e2a6"));
+        assert!(picked[..3].contains(&mv("d5e6")) && picked[..3].contains(&mv("g2h3")));
+        // Then the killer, unless it is really a capture
+        assert_eq!(position("a2a3"), 3);
+        // Losing captures come last, still by MVV-LVA
+        let bad = &picked[picked.len() - 5..];
+        assert_eq!(bad[0], mv("f3f6"));
+        assert!(bad.contains(&mv("e5f7")) && bad.contains(&mv("e5d7")) && bad.contains(&mv("e5g6")));
+        assert_eq!(bad[4], mv("f3h3"));
+    }
+
+    #[test]
+    fn quiets_follow_history_and_countermove() {
+        let board = Board::build_starting().unwrap();
+        let mut heuristics = Heuristics::new();
+        heuristics.update(Color::White, mv("d2d4"), None, &[mv("a2a3")], 4, 0);
+        heuristics.update(Color::White, mv("g1f3"), None, &[], 2, 0);
+        assert!(heuristics.history(Color::White, mv("a2a3")) < 0);
+        assert_eq!(heuristics.killers(0), [Some(mv("g1f3")), Some(mv("d2d4"))]);
+
+        let picked = pick_all(&board, MovePicker::new(None, [None; 2], None), &heuristics);
+        assert_eq!(&picked[..2], &[mv("d2d4"), mv("g1f3")]);
+        assert_eq!(*picked.last().unwrap(), mv("a2a3"));
+
+        let picked = pick_all(&board, MovePicker::new(None, [None; 2], Some(mv("b1c3"))), &heuristics);
+        assert_eq!(picked[0], mv("b1c3"));
+
+        heuristics.new_search();
+        assert_eq!(heuristics.killers(0), [None; 2]);
+        assert_eq!(heuristics.history(Color::White, mv("d2d4")), 8);
+    }
+
+    #[test]
+    fn stale_tt_move_is_skipped() {
+        let board = Board::build_starting().unwrap();
+        let heuristics = Heuristics::new();
+        let picked = pick_all(&board, MovePicker::new(Some(mv("e2e5")), [Some(mv("e7e5")), None], None), &heuristics);
+        assert_eq!(picked.len(), 20);
+        assert!(!picked.contains(&mv("e2e5")) && !picked.contains(&mv("e7e5")));
+    }
+
+    #[test]
+    fn captures_only() {
+        let board = Board::from_fen(KIWIPETE).unwrap();
+        let heuristics = Heuristics::new();
+        let picked = pick_all(&board, MovePicker::captures(Some(mv("a2a3"))), &heuristics);
+        // Bxa6, dxe6 and gxh3; the five losing captures are left out
+        assert_eq!(picked.len(), 3);
+        assert!(picked.iter().all(|picked| board.is_capture(*picked) && board.see_ge(*picked, 0)));
+        assert_eq!(picked[0], mv("e2a6"));
+    }
+}
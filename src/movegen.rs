@@ -0,0 +1,270 @@
+use crate::board::Board;
+use crate::chess_move::Move;
+use crate::file::File;
+use crate::rank::Rank;
+use crate::piece::Color;
+use crate::piece::PieceType;
+use crate::square::Square;
+
+const PROMOTION_TYPES: [PieceType; 4] = [PieceType::Queen, PieceType::Rook, PieceType::Bishop, PieceType::Knight];
+
+impl Board {
+    // Moves for the side to move that follow piece movement rules but may
+    // leave the mover's own king in check
+    pub fn pseudo_legal_moves(&self) -> Vec<Move> {
+        let color = self.side_to_move();
+        let mut res: Vec<Move> = Vec::new();
+
+        let own_squares: Vec<&Square> = self.pieces().filter(|square| square.piece_matches_color(color)).collect();
+        for square in own_squares {
+            self.push_square_moves(&mut res, square);
+        }
+        res
+    }
+
+    // Pseudo-legal moves left out of pseudo_legal_captures: those that are
+    // neither captures nor promotions, and underpromotions, capturing or not
+    pub fn pseudo_legal_quiets(&self) -> Vec<Move> {
+        let color = self.side_to_move();
+        let mut res: Vec<Move> = Vec::new();
+
+        let own_squares: Vec<&Square> = self.pieces().filter(|square| square.piece_matches_color(color)).collect();
+        for square in own_squares {
+            let piece = square.piece.unwrap();
+            for destination in piece.get_moves(self, square) {
+                let last_rank = destination.rank.value() == 0 || destination.rank.value() == 7;
+                if piece.piece_type() == PieceType::Pawn && last_rank {
+                    for promotion in PROMOTION_TYPES.iter().filter(|promotion| **promotion != PieceType::Queen) {
+                        res.push(Move::with_promotion(square.file, square.rank, destination.file, destination.rank, *promotion));
+                    }
+                } else if destination.is_empty() {
+                    res.push(Move::new(square.file, square.rank, destination.file, destination.rank));
+                }
+            }
+            if piece.piece_type() == PieceType::King {
+                self.push_castling(&mut res, square);
+            }
+        }
+        res
+    }
+
+    // Whether a move, e.g. one remembered from another position, is
+    // pseudo-legal here. Only the moves of the piece on its from square are
+    // generated.
+    pub fn is_pseudo_legal(&self, mv: Move) -> bool {
+        let square = self.get_square(mv.from_file, mv.from_rank);
+        if !square.piece_matches_color(self.side_to_move()) {
+            return false;
+        }
+        let mut res: Vec<Move> = Vec::new();
+        self.push_square_moves(&mut res, square);
+        res.contains(&mv)
+    }
+
+    // Captures, en passant and queen promotions for the side to move, which
+    // may leave the mover's own king in check. Quiet promotions are included
+    // since they gain as much material as most captures.
+    pub fn pseudo_legal_captures(&self) -> Vec<Move> {
+        let color = self.side_to_move();
+        let mut res: Vec<Move> = Vec::new();
+
+        let own_squares: Vec<&Square> = self.pieces().filter(|square| square.piece_matches_color(color)).collect();
+        for square in own_squares {
+            let piece = square.piece.unwrap();
+            for destination in piece.get_moves(self, square) {
+                let last_rank = destination.rank.value() == 0 || destination.rank.value() == 7;
+                if piece.piece_type() == PieceType::Pawn && last_rank {
+                    res.push(Move::with_promotion(square.file, square.rank, destination.file, destination.rank, PieceType::Queen));
+                } else if !destination.is_empty() {
+                    res.push(Move::new(square.file, square.rank, destination.file, destination.rank));
+                }
+            }
+            if piece.piece_type() == PieceType::Pawn {
+                self.push_en_passant(&mut res, square);
+            }
+        }
+        res
+    }
+
+    // Moves for the side to move that do not leave its own king in check
+    pub fn legal_moves(&mut self) -> Vec<Move> {
+        self.pseudo_legal_moves().into_iter().filter(|mv| self.is_legal(*mv)).collect()
+    }
+
+    // Whether a pseudo-legal move keeps the mover's king out of check
+    pub fn is_legal(&mut self, mv: Move) -> bool {
+        let color = self.side_to_move();
+        self.make_move(mv);
+        let legal = !self.is_king_attacked(color);
+        self.unmake_move();
+        legal
+    }
+
+    pub fn is_checkmate(&mut self) -> bool {
+        self.in_check() && self.legal_moves().is_empty()
+    }
+
+    pub fn is_stalemate(&mut self) -> bool {
+        !self.in_check() && self.legal_moves().is_empty()
+    }
+
+    // Count leaf nodes of the legal move tree, used to verify move generation
+    pub fn perft(&mut self, depth: u32) -> u64 {
+        if depth == 0 {
+            return 1;
+        }
+        let moves = self.legal_moves();
+        if depth == 1 {
+            return moves.len() as u64;
+        }
+        moves.into_iter().map(|mv| {
+            self.make_move(mv);
+            let nodes = self.perft(depth - 1);
+            self.unmake_move();
+            nodes
+        }).sum()
+    }
+
+    fn push_square_moves(&self, res: &mut Vec<Move>, square: &Square) {
+        let piece = square.piece.unwrap();
+        for destination in piece.get_moves(self, square) {
+            self.push_moves(res, square, destination.file, destination.rank, piece.piece_type());
+        }
+        match piece.piece_type() {
+            PieceType::Pawn => self.push_en_passant(res, square),
+            PieceType::King => self.push_castling(res, square),
+            _ => {}
+        }
+    }
+
+    fn push_moves(&self, res: &mut Vec<Move>, from: &Square, to_file: File, to_rank: Rank, piece_type: PieceType) {
+        let last_rank = to_rank.value() == 0 || to_rank.value() == 7;
+        if piece_type == PieceType::Pawn && last_rank {
+            for promotion in PROMOTION_TYPES.iter() {
+                res.push(Move::with_promotion(from.file, from.rank, to_file, to_rank, *promotion));
+            }
+        } else {
+            res.push(Move::new(from.file, from.rank, to_file, to_rank));
+        }
+    }
+
+    fn push_en_passant(&self, res: &mut Vec<Move>, from: &Square) {
+        let (ep_file, ep_rank) = match self.en_passant() {
+            Some(target) => target,
+            None => return
+        };
+        let forward = match self.side_to_move() {
+            Color::White => 1,
+            Color::Black => -1
+        };
+        if from.rank.offset(forward) == Ok(ep_rank) && from.file.value().abs_diff(ep_file.value()) == 1 {
+            res.push(Move::new(from.file, from.rank, ep_file, ep_rank));
+        }
+    }
+
+    fn push_castling(&self, res: &mut Vec<Move>, from: &Square) {
+        let color = self.side_to_move();
+        let opponent = color.opposite();
+        let rank = from.rank;
+        let file = |value: u8| File::build(value).unwrap();
+        let empty = |values: &[u8]| values.iter().all(|value| self.get_square(file(*value), rank).is_empty());
+        let safe = |values: &[u8]| values.iter().all(|value| !self.is_square_attacked(file(*value), rank, opponent));
+
+        if self.can_castle_kingside(color) && empty(&[5, 6]) && safe(&[4, 5, 6]) {
+            res.push(Move::new(from.file, rank, file(6), rank));
+        }
+        if self.can_castle_queenside(color) && empty(&[1, 2, 3]) && safe(&[4, 3, 2]) {
+            res.push(Move::new(from.file, rank, file(2), rank));
+        }
+    }
+}
+
+#[cfg(test)]
+mod movegen_tests {
+    use super::*;
+
+    const KIWIPETE: &str = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+
+    #[test]
+    fn perft_starting_position() {
+        let mut board = Board::build_starting().unwrap();
+        assert_eq!(board.perft(1), 20);
+        assert_eq!(board.perft(2), 400);
+        assert_eq!(board.perft(3), 8902);
+    }
+
+    #[test]
+    fn perft_kiwipete() {
+        let mut board = Board::from_fen(KIWIPETE).unwrap();
+        assert_eq!(board.perft(1), 48);
+        assert_eq!(board.perft(2), 2039);
+    }
+
+    #[test]
+    fn perft_en_passant_and_pins() {
+        let mut board = Board::from_fen("8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1").unwrap();
+        assert_eq!(board.perft(1), 14);
+        assert_eq!(board.perft(2), 191);
+        assert_eq!(board.perft(3), 2812);
+    }
+
+    #[test]
+    fn perft_promotions() {
+        let mut board = Board::from_fen("r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1").unwrap();
+        assert_eq!(board.perft(1), 6);
+        assert_eq!(board.perft(2), 264);
+        assert_eq!(board.perft(3), 9467);
+    }
+
+    #[test]
+    fn make_unmake_restores_position() {
+        let mut board = Board::from_fen(KIWIPETE).unwrap();
+        let fen = board.to_fen();
+        for mv in board.legal_moves() {
+            board.make_move(mv);
+            board.unmake_move();
+            assert_eq!(board.to_fen(), fen, "after {}", mv);
+        }
+    }
+
+    #[test]
+    fn checkmate_and_stalemate() {
+        let mut mate = Board::from_fen("rnb1kbnr/pppp1ppp/8/4p3/6Pq/5P2/PPPPP2P/RNBQKBNR w KQkq - 1 3").unwrap();
+        assert!(mate.is_checkmate());
+        let mut stalemate = Board::from_fen("7k/5Q2/6K1/8/8/8/8/8 b - - 0 1").unwrap();
+        assert!(stalemate.is_stalemate());
+    }
+
+    #[test]
+    fn captures_include_en_passant_and_queen_promotions() {
+        let board = Board::from_fen("1n2k3/P7/8/3pP3/8/8/8/4K3 w - d6 0 2").unwrap();
+        let mut captures: Vec<String> = board.pseudo_legal_captures().iter().map(|mv| mv.to_string()).collect();
+        captures.sort();
+        assert_eq!(captures, vec!["a7a8q", "a7b8q", "e5d6"]);
+    }
+
+    #[test]
+    fn quiets_and_captures_split_the_moves() {
+        for fen in [KIWIPETE, "r3k3/1P6/8/8/8/8/8/4K3 w - - 0 1"].iter() {
+            let board = Board::from_fen(fen).unwrap();
+            let mut split = board.pseudo_legal_captures();
+            split.extend(board.pseudo_legal_quiets());
+            let mut all = board.pseudo_legal_moves();
+            split.sort_by_key(|mv| mv.to_string());
+            all.sort_by_key(|mv| mv.to_string());
+            assert_eq!(split, all, "{}", fen);
+        }
+    }
+
+    #[test]
+    fn pseudo_legal_check() {
+        let board = Board::from_fen(KIWIPETE).unwrap();
+        for mv in board.pseudo_legal_moves() {
+            assert!(board.is_pseudo_legal(mv), "{}", mv);
+        }
+        for uci in ["e1e2", "a8a7", "e2e4", "d5c6", "c3c4", "a1a8"].iter() {
+            assert!(!board.is_pseudo_legal(Move::from_uci(uci).unwrap()), "{}", uci);
+        }
+        assert!(board.is_pseudo_legal(Move::from_uci("e1g1").unwrap()));
+    }
+}
@@ -0,0 +1,455 @@
+use crate::board::Board;
+use crate::file::File;
+use crate::rank::Rank;
+use crate::piece::Color;
+use crate::piece::Piece;
+use crate::piece::PieceType;
+use crate::random::Random;
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::io::BufReader;
+use std::io::BufWriter;
+use std::io::Read;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Arc;
+
+const MAGIC: &[u8; 4] = b"NNUE";
+const VERSION: u32 = 1;
+// HalfKP: the square of the perspective's own king times each of the ten
+// pieces other than kings on each square
+pub const FEATURES: usize = 64 * 10 * 64;
+// Hidden values are clipped to 0..=QA before the output layer, whose
+// weights are scaled up by QB
+const QA: i32 = 255;
+const QB: i32 = 64;
+// Centipawns per unit of network output
+const SCALE: i32 = 400;
+// The hidden layer size must be a multiple of the i16 lanes in an AVX2 register
+const LANES: usize = 16;
+
+// Weights of a HalfKP network: the sparse input features feed a hidden
+// layer computed separately from each side's point of view, and both
+// halves, the side to move's first, feed a single output
+pub struct Network {
+    hidden: usize,
+    // FEATURES columns of hidden weights each
+    feature_weights: Vec<i16>,
+    feature_biases: Vec<i16>,
+    output_weights: Vec<i16>,
+    output_bias: i32,
+}
+
+impl fmt::Debug for Network {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Network {{ hidden: {} }}", self.hidden)
+    }
+}
+
+fn invalid(message: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn read_i16s<R: Read>(reader: &mut R, count: usize) -> io::Result<Vec<i16>> {
+    let mut bytes = vec![0; count * 2];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes.chunks_exact(2).map(|pair| i16::from_le_bytes([pair[0], pair[1]])).collect())
+}
+
+fn write_i16s<W: Write>(writer: &mut W, values: &[i16]) -> io::Result<()> {
+    for value in values {
+        writer.write_all(&value.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+impl Network {
+    // File layout, little endian: "NNUE", version, hidden size as u32s,
+    // then the feature weights, feature biases and output weights as i16s
+    // and the output bias as an i32
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Network> {
+        Network::read(&mut BufReader::new(fs::File::open(path)?))
+    }
+
+    pub fn read<R: Read>(reader: &mut R) -> io::Result<Network> {
+        let mut header = [0; 12];
+        reader.read_exact(&mut header)?;
+        if &header[0..4] != MAGIC {
+            return Err(invalid("Not an NNUE file"));
+        }
+        if u32::from_le_bytes([header[4], header[5], header[6], header[7]]) != VERSION {
+            return Err(invalid("Unsupported NNUE version"));
+        }
+        let hidden = u32::from_le_bytes([header[8], header[9], header[10], header[11]]) as usize;
+        if hidden == 0 || !hidden.is_multiple_of(LANES) {
+            return Err(invalid("Hidden layer size must be a positive multiple of 16"));
+        }
+        let feature_weights = read_i16s(reader, FEATURES * hidden)?;
+        let feature_biases = read_i16s(reader, hidden)?;
+        let output_weights = read_i16s(reader, 2 * hidden)?;
+        let mut bias = [0; 4];
+        reader.read_exact(&mut bias)?;
+        Ok(Network{hidden, feature_weights, feature_biases, output_weights, output_bias: i32::from_le_bytes(bias)})
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut writer = BufWriter::new(fs::File::create(path)?);
+        self.write(&mut writer)?;
+        writer.flush()
+    }
+
+    pub fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        writer.write_all(&(self.hidden as u32).to_le_bytes())?;
+        write_i16s(writer, &self.feature_weights)?;
+        write_i16s(writer, &self.feature_biases)?;
+        write_i16s(writer, &self.output_weights)?;
+        writer.write_all(&self.output_bias.to_le_bytes())
+    }
+
+    // Small random weights, a starting point for training
+    pub fn random(hidden: usize, seed: u64) -> Result<Network, &'static str> {
+        if hidden == 0 || !hidden.is_multiple_of(LANES) {
+            return Err("Hidden layer size must be a positive multiple of 16");
+        }
+        let mut random = Random::new(seed);
+        let mut weights = |count: usize, range: u64| -> Vec<i16> {
+            (0..count).map(|_| random.below(2 * range + 1) as i16 - range as i16).collect()
+        };
+        Ok(Network{
+            hidden,
+            feature_weights: weights(FEATURES * hidden, 32),
+            feature_biases: weights(hidden, 64),
+            output_weights: weights(2 * hidden, 64),
+            output_bias: 0,
+        })
+    }
+
+    pub fn hidden_size(&self) -> usize {
+        self.hidden
+    }
+
+    fn column(&self, feature: usize) -> &[i16] {
+        &self.feature_weights[feature * self.hidden..(feature + 1) * self.hidden]
+    }
+}
+
+// Squares numbered rank * 8 + file from the perspective's own side, so
+// Black sees the board flipped
+fn orient(perspective: Color, file: File, rank: Rank) -> usize {
+    let rank = match perspective {
+        Color::White => rank.value(),
+        Color::Black => 7 - rank.value()
+    };
+    usize::from(rank * 8 + file.value())
+}
+
+fn feature(perspective: Color, king: usize, piece: &Piece, square: usize) -> usize {
+    let piece_index = match piece.piece_type() {
+        PieceType::Pawn => 0,
+        PieceType::Knight => 1,
+        PieceType::Bishop => 2,
+        PieceType::Rook => 3,
+        PieceType::Queen => 4,
+        PieceType::King => unreachable!("Kings are not features")
+    };
+    let theirs = if piece.get_color() == perspective { 0 } else { 1 };
+    king * 640 + (piece_index * 2 + theirs) * 64 + square
+}
+
+// The hidden layer of a network for a position, from both sides' point of
+// view. Adding or removing a piece updates the columns of its feature;
+// moving a king changes every feature of its side, which is recomputed.
+#[derive(Clone)]
+pub struct Accumulator {
+    network: Arc<Network>,
+    values: [Vec<i16>; 2],
+    // Each side's king square as it sees it, None while it is off the board
+    kings: [Option<usize>; 2],
+}
+
+impl fmt::Debug for Accumulator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Accumulator {{ network: {:?}, kings: {:?} }}", self.network, self.kings)
+    }
+}
+
+impl Accumulator {
+    pub fn new(network: Arc<Network>, board: &Board) -> Accumulator {
+        let biases = network.feature_biases.clone();
+        let mut accumulator = Accumulator{network, values: [biases.clone(), biases], kings: [None; 2]};
+        accumulator.refresh(board, Color::White);
+        accumulator.refresh(board, Color::Black);
+        accumulator
+    }
+
+    pub fn network(&self) -> &Arc<Network> {
+        &self.network
+    }
+
+    // Recompute one side's half from the pieces on the board
+    pub fn refresh(&mut self, board: &Board, perspective: Color) {
+        let side = perspective.index();
+        self.values[side].copy_from_slice(&self.network.feature_biases);
+        self.kings[side] = board.find_king(perspective).map(|(file, rank)| orient(perspective, file, rank));
+        let king = match self.kings[side] {
+            Some(king) => king,
+            None => return
+        };
+        for square in board.pieces() {
+            let piece = square.piece.unwrap();
+            if piece.piece_type() != PieceType::King {
+                let feature = feature(perspective, king, &piece, orient(perspective, square.file, square.rank));
+                simd::add(&mut self.values[side], self.network.column(feature));
+            }
+        }
+    }
+
+    // Called once the piece is on the board
+    pub fn add_piece(&mut self, board: &Board, piece: Piece, file: File, rank: Rank) {
+        if piece.piece_type() == PieceType::King {
+            self.refresh(board, piece.get_color());
+            return;
+        }
+        for perspective in [Color::White, Color::Black].iter() {
+            let side = perspective.index();
+            if let Some(king) = self.kings[side] {
+                let feature = feature(*perspective, king, &piece, orient(*perspective, file, rank));
+                simd::add(&mut self.values[side], self.network.column(feature));
+            }
+        }
+    }
+
+    pub fn remove_piece(&mut self, piece: Piece, file: File, rank: Rank) {
+        if piece.piece_type() == PieceType::King {
+            self.kings[piece.get_color().index()] = None;
+            return;
+        }
+        for perspective in [Color::White, Color::Black].iter() {
+            let side = perspective.index();
+            if let Some(king) = self.kings[side] {
+                let feature = feature(*perspective, king, &piece, orient(*perspective, file, rank));
+                simd::sub(&mut self.values[side], self.network.column(feature));
+            }
+        }
+    }
+
+    // Centipawns from the side to move's point of view, or None without
+    // both kings on the board
+    pub fn evaluate(&self, side_to_move: Color) -> Option<i32> {
+        if self.kings.iter().any(|king| king.is_none()) {
+            return None;
+        }
+        let hidden = self.network.hidden;
+        let us = &self.values[side_to_move.index()];
+        let them = &self.values[side_to_move.opposite().index()];
+        let weights = &self.network.output_weights;
+        let output = self.network.output_bias
+            + simd::output(us, &weights[..hidden])
+            + simd::output(them, &weights[hidden..]);
+        Some(output * SCALE / (QA * QB))
+    }
+}
+
+// Vector arithmetic on i16 lanes, with AVX2 where the CPU has it and plain
+// loops everywhere else
+mod simd {
+    use super::QA;
+    use super::LANES;
+
+    pub fn add(values: &mut [i16], column: &[i16]) {
+        #[cfg(target_arch = "x86_64")]
+        {
+            if is_x86_feature_detected!("avx2") {
+                // The CPU supports AVX2, checked just above
+                unsafe { avx2::add(values, column) };
+                return;
+            }
+        }
+        scalar::add(values, column);
+    }
+
+    pub fn sub(values: &mut [i16], column: &[i16]) {
+        #[cfg(target_arch = "x86_64")]
+        {
+            if is_x86_feature_detected!("avx2") {
+                // The CPU supports AVX2, checked just above
+                unsafe { avx2::sub(values, column) };
+                return;
+            }
+        }
+        scalar::sub(values, column);
+    }
+
+    // Sum of each value clipped to 0..=QA times its weight
+    pub fn output(values: &[i16], weights: &[i16]) -> i32 {
+        #[cfg(target_arch = "x86_64")]
+        {
+            if is_x86_feature_detected!("avx2") {
+                // The CPU supports AVX2, checked just above
+                return unsafe { avx2::output(values, weights) };
+            }
+        }
+        scalar::output(values, weights)
+    }
+
+    pub mod scalar {
+        use super::QA;
+
+        pub fn add(values: &mut [i16], column: &[i16]) {
+            for (value, weight) in values.iter_mut().zip(column) {
+                *value = value.wrapping_add(*weight);
+            }
+        }
+
+        pub fn sub(values: &mut [i16], column: &[i16]) {
+            for (value, weight) in values.iter_mut().zip(column) {
+                *value = value.wrapping_sub(*weight);
+            }
+        }
+
+        pub fn output(values: &[i16], weights: &[i16]) -> i32 {
+            values.iter()
+                .zip(weights)
+                .map(|(value, weight)| i32::from(*value).clamp(0, QA) * i32::from(*weight))
+                .sum()
+        }
+    }
+
+    // Slices are processed LANES values at a time; every slice passed in
+    // is a multiple of LANES long, which Network enforces
+    #[cfg(target_arch = "x86_64")]
+    mod avx2 {
+        use super::QA;
+        use super::LANES;
+        use std::arch::x86_64::*;
+
+        #[target_feature(enable = "avx2")]
+        pub unsafe fn add(values: &mut [i16], column: &[i16]) {
+            for (values, column) in values.chunks_exact_mut(LANES).zip(column.chunks_exact(LANES)) {
+                let sum = _mm256_add_epi16(_mm256_loadu_si256(values.as_ptr() as *const __m256i), _mm256_loadu_si256(column.as_ptr() as *const __m256i));
+                _mm256_storeu_si256(values.as_mut_ptr() as *mut __m256i, sum);
+            }
+        }
+
+        #[target_feature(enable = "avx2")]
+        pub unsafe fn sub(values: &mut [i16], column: &[i16]) {
+            for (values, column) in values.chunks_exact_mut(LANES).zip(column.chunks_exact(LANES)) {
+                let difference = _mm256_sub_epi16(_mm256_loadu_si256(values.as_ptr() as *const __m256i), _mm256_loadu_si256(column.as_ptr() as *const __m256i));
+                _mm256_storeu_si256(values.as_mut_ptr() as *mut __m256i, difference);
+            }
+        }
+
+        #[target_feature(enable = "avx2")]
+        pub unsafe fn output(values: &[i16], weights: &[i16]) -> i32 {
+            let zero = _mm256_setzero_si256();
+            let ceiling = _mm256_set1_epi16(QA as i16);
+            let mut sum = _mm256_setzero_si256();
+            for (values, weights) in values.chunks_exact(LANES).zip(weights.chunks_exact(LANES)) {
+                let clipped = _mm256_max_epi16(_mm256_min_epi16(_mm256_loadu_si256(values.as_ptr() as *const __m256i), ceiling), zero);
+                // Multiplies the lanes and adds neighbouring pairs as i32
+                let products = _mm256_madd_epi16(clipped, _mm256_loadu_si256(weights.as_ptr() as *const __m256i));
+                sum = _mm256_add_epi32(sum, products);
+            }
+            let mut lanes = [0i32; 8];
+            _mm256_storeu_si256(lanes.as_mut_ptr() as *mut __m256i, sum);
+            lanes.iter().sum()
+        }
+    }
+
+    #[cfg(test)]
+    mod simd_tests {
+        use super::*;
+        use crate::random::Random;
+
+        #[test]
+        fn vector_and_scalar_paths_agree() {
+            let mut random = Random::new(7);
+            let mut values: Vec<i16> = (0..4 * LANES).map(|_| random.below(2000) as i16 - 1000).collect();
+            let column: Vec<i16> = (0..4 * LANES).map(|_| random.below(200) as i16 - 100).collect();
+            let mut expected = values.clone();
+
+            add(&mut values, &column);
+            scalar::add(&mut expected, &column);
+            assert_eq!(values, expected);
+            sub(&mut values, &column);
+            sub(&mut values, &column);
+            scalar::sub(&mut expected, &column);
+            scalar::sub(&mut expected, &column);
+            assert_eq!(values, expected);
+
+            assert_eq!(output(&values, &column), scalar::output(&values, &column));
+            assert!(values.iter().any(|value| i32::from(*value) > QA) && values.iter().any(|value| *value < 0));
+        }
+    }
+}
+
+#[cfg(test)]
+mod nnue_tests {
+    use super::*;
+    use crate::chess_move::Move;
+
+    fn network() -> Arc<Network> {
+        Arc::new(Network::random(32, 1).unwrap())
+    }
+
+    #[test]
+    fn round_trips_through_the_file_format() {
+        let network = network();
+        let mut bytes = Vec::new();
+        network.write(&mut bytes).unwrap();
+        assert_eq!(bytes.len(), 12 + 2 * (FEATURES * 32 + 32 + 64) + 4);
+        let read = Arc::new(Network::read(&mut bytes.as_slice()).unwrap());
+        assert_eq!(read.hidden_size(), 32);
+
+        let board = Board::build_starting().unwrap();
+        let score = Accumulator::new(network, &board).evaluate(Color::White);
+        assert_eq!(Accumulator::new(read, &board).evaluate(Color::White), score);
+
+        bytes[0] = b'X';
+        assert!(Network::read(&mut bytes.as_slice()).is_err());
+        assert!(Network::read(&mut &bytes[..100]).is_err());
+        assert!(Network::random(24, 1).is_err());
+    }
+
+    #[test]
+    fn updates_match_a_fresh_accumulator() {
+        let mut board = Board::from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1").unwrap();
+        board.set_network(Some(network()));
+        // Castling, king moves, a capture, en passant and a promotion
+        for uci in ["e1g1", "h3g2", "a2a4", "b4a3", "f3f6", "e8f8", "g1g2", "a3b2", "d2e3", "b2a1q"].iter() {
+            board.play(Move::from_uci(uci).unwrap()).unwrap();
+            let accumulator = board.accumulator().unwrap();
+            let fresh = Accumulator::new(Arc::clone(accumulator.network()), &board);
+            assert_eq!(accumulator.values, fresh.values);
+            assert_eq!(accumulator.kings, fresh.kings);
+        }
+        let mut played = 0;
+        while board.last_move().is_some() {
+            board.unmake_move();
+            played += 1;
+        }
+        assert_eq!(played, 10);
+        let accumulator = board.accumulator().unwrap();
+        assert_eq!(accumulator.values, Accumulator::new(Arc::clone(accumulator.network()), &board).values);
+    }
+
+    #[test]
+    fn evaluation_is_colour_symmetric() {
+        let network = network();
+        let white = Board::from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1").unwrap();
+        let black = Board::from_fen("r3k2r/pppbbppp/2n2q1P/1P2p3/3pn3/BN2PNP1/P1PPQPB1/R3K2R b KQkq - 0 1").unwrap();
+        let white_score = Accumulator::new(Arc::clone(&network), &white).evaluate(Color::White);
+        assert!(white_score.is_some());
+        assert_eq!(Accumulator::new(network, &black).evaluate(Color::Black), white_score);
+    }
+
+    #[test]
+    fn needs_both_kings() {
+        let board = Board::from_fen("8/8/8/8/8/8/8/4K3 w - - 0 1").unwrap();
+        assert_eq!(Accumulator::new(network(), &board).evaluate(Color::White), None);
+    }
+}
@@ -0,0 +1,301 @@
+use crate::attacks::bit;
+use crate::board::Board;
+use crate::eval::Score;
+use crate::file::File;
+use crate::rank::Rank;
+use crate::piece::Color;
+use crate::piece::PieceType;
+
+const PAWN_TABLE_SIZE: usize = 1 << 14;
+
+pub const DOUBLED: Score = Score::new(-10, -25);
+pub const ISOLATED: Score = Score::new(-10, -15);
+pub const BACKWARD: Score = Score::new(-8, -12);
+// By rank counted from the pawn's own side, for a pawn defended by another
+// or standing beside one
+pub const CONNECTED: [i32; 8] = [0, 4, 7, 12, 20, 35, 60, 0];
+pub const PASSED_MG: [i32; 8] = [0, 5, 10, 15, 30, 50, 80, 0];
+pub const PASSED_EG: [i32; 8] = [0, 10, 20, 35, 60, 100, 150, 0];
+// Endgame bonus for a passed pawn with nothing in its way to promotion
+const PASSED_FREE_PATH: [i32; 8] = [0, 0, 5, 10, 20, 35, 60, 0];
+// Per square of distance to the passed pawn's stop square, scaled up the
+// further the pawn has come: the defending king far away is good, our own
+// king far away is bad
+const ENEMY_KING_DISTANCE: i32 = 5;
+const OWN_KING_DISTANCE: i32 = 2;
+
+// The square's bit as attacks::bit gives it, or no bit for a square off the
+// board, so that neighbours can be looked up without checking the edges
+fn bit_or_zero(file: i8, rank: i8) -> u64 {
+    if (0..8).contains(&file) && (0..8).contains(&rank) {
+        1 << (file * 8 + rank)
+    } else {
+        0
+    }
+}
+
+// The rank direction a color's pawns move in
+fn forward(color: Color) -> i8 {
+    match color {
+        Color::White => 1,
+        Color::Black => -1
+    }
+}
+
+fn relative_rank(color: Color, rank: i8) -> usize {
+    match color {
+        Color::White => rank as usize,
+        Color::Black => (7 - rank) as usize
+    }
+}
+
+// How many pawns of one color each structure term applies to, the rank
+// dependent ones by rank counted from the pawns' own side
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PawnTerms {
+    pub doubled: i32,
+    pub isolated: i32,
+    pub backward: i32,
+    pub connected: [i32; 8],
+    pub passed: [i32; 8],
+}
+
+impl PawnTerms {
+    pub fn score(&self) -> Score {
+        let mut score = DOUBLED * self.doubled + ISOLATED * self.isolated + BACKWARD * self.backward;
+        for rank in 0..8 {
+            score += Score::new(CONNECTED[rank], CONNECTED[rank]) * self.connected[rank];
+            score += Score::new(PASSED_MG[rank], PASSED_EG[rank]) * self.passed[rank];
+        }
+        score
+    }
+}
+
+// Structure terms of each color, White first, and their passed pawns
+fn structure(board: &Board) -> ([PawnTerms; 2], [u64; 2]) {
+    let mut pawns = [0u64; 2];
+    for square in board.pieces() {
+        let piece = square.piece.unwrap();
+        if piece.piece_type() == PieceType::Pawn {
+            pawns[piece.get_color().index()] |= bit(square.file, square.rank);
+        }
+    }
+
+    let mut terms = [PawnTerms::default(); 2];
+    let mut passed = [0u64; 2];
+    for color in [Color::White, Color::Black].iter() {
+        let own = pawns[color.index()];
+        let enemy = pawns[color.opposite().index()];
+        let up = forward(*color);
+        let side = &mut terms[color.index()];
+        for index in (0..64).filter(|index| own & (1 << index) != 0) {
+            let (file, rank) = ((index / 8) as i8, (index % 8) as i8);
+            let ahead = |pawns: u64, files: &[i8]| files.iter().any(|&file| {
+                (1..8).any(|step| pawns & bit_or_zero(file, rank + step * up) != 0)
+            });
+            let behind_or_level = |pawns: u64, files: &[i8]| files.iter().any(|&file| {
+                (0..8).any(|step| pawns & bit_or_zero(file, rank - step * up) != 0)
+            });
+            let adjacent = [file - 1, file + 1];
+
+            let doubled = ahead(own, &[file]);
+            if doubled {
+                side.doubled += 1;
+            }
+            let isolated = !ahead(own, &adjacent) && !behind_or_level(own, &adjacent);
+            let supported = own & (bit_or_zero(file - 1, rank - up) | bit_or_zero(file + 1, rank - up)) != 0;
+            let phalanx = own & (bit_or_zero(file - 1, rank) | bit_or_zero(file + 1, rank)) != 0;
+            if isolated {
+                side.isolated += 1;
+            } else if supported || phalanx {
+                side.connected[relative_rank(*color, rank)] += 1;
+            } else if !behind_or_level(own, &adjacent) {
+                // Every neighbour has gone ahead, and an enemy pawn
+                // stops this one from catching up
+                let stop_attacked = enemy & (bit_or_zero(file - 1, rank + 2 * up) | bit_or_zero(file + 1, rank + 2 * up)) != 0;
+                if stop_attacked {
+                    side.backward += 1;
+                }
+            }
+
+            if !doubled && !ahead(enemy, &[file - 1, file, file + 1]) {
+                passed[color.index()] |= bit_or_zero(file, rank);
+                side.passed[relative_rank(*color, rank)] += 1;
+            }
+        }
+    }
+    (terms, passed)
+}
+
+pub fn pawn_terms(board: &Board) -> [PawnTerms; 2] {
+    structure(board).0
+}
+
+// The part of the evaluation that only depends on where the pawns are,
+// which is worth caching since pawns rarely move
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PawnEntry {
+    pub key: u64,
+    // Each color's own structure, White first
+    pub scores: [Score; 2],
+    // Passed pawns of each color, White first, one bit per square
+    pub passed: [u64; 2],
+}
+
+impl PawnEntry {
+    pub fn analyse(board: &Board) -> PawnEntry {
+        let (terms, passed) = structure(board);
+        PawnEntry{key: board.pawn_key(), scores: [terms[0].score(), terms[1].score()], passed}
+    }
+
+    // White minus Black
+    pub fn score(&self) -> Score {
+        self.scores[0] - self.scores[1]
+    }
+}
+
+// Passed pawn terms that depend on more than the pawns: whether the way to
+// promotion is clear and how close each king is
+pub fn passed_pawn_extras(board: &Board, entry: &PawnEntry, color: Color) -> Score {
+    let king = |color: Color| board.find_king(color).map(|(file, rank)| (file.value() as i8, rank.value() as i8));
+    let kings = (king(color), king(color.opposite()));
+    let distance = |(file, rank): (i8, i8), (other_file, other_rank): (i8, i8)| {
+        i32::from((file - other_file).abs().max((rank - other_rank).abs()))
+    };
+
+    let passed = entry.passed[color.index()];
+    let up = forward(color);
+    let mut score = 0;
+    for index in (0..64).filter(|index| passed & (1 << index) != 0) {
+        let (file, rank) = ((index / 8) as i8, (index % 8) as i8);
+        let relative = relative_rank(color, rank);
+        let free = (1..8)
+            .map(|step| rank + step * up)
+            .take_while(|rank| (0..8).contains(rank))
+            .all(|rank| board.piece_at(File::build(file as u8).unwrap(), Rank::build(rank as u8).unwrap()).is_none());
+        if free {
+            score += PASSED_FREE_PATH[relative];
+        }
+        let weight = relative.saturating_sub(2) as i32;
+        let stop = (file, rank + up);
+        if let (Some(own_king), Some(enemy_king)) = kings {
+            score += weight * (ENEMY_KING_DISTANCE * distance(enemy_king, stop) - OWN_KING_DISTANCE * distance(own_king, stop));
+        }
+    }
+    Score::new(0, score)
+}
+
+// Cache of pawn structure analysis keyed by the pawns' Zobrist key
+pub struct PawnTable {
+    entries: Vec<Option<PawnEntry>>,
+}
+
+impl PawnTable {
+    pub fn new() -> PawnTable {
+        PawnTable{entries: vec![None; PAWN_TABLE_SIZE]}
+    }
+
+    pub fn clear(&mut self) {
+        for entry in self.entries.iter_mut() {
+            *entry = None;
+        }
+    }
+
+    pub fn probe(&mut self, board: &Board) -> PawnEntry {
+        let key = board.pawn_key();
+        let slot = &mut self.entries[key as usize % PAWN_TABLE_SIZE];
+        match slot {
+            Some(entry) if entry.key == key => *entry,
+            _ => {
+                let entry = PawnEntry::analyse(board);
+                *slot = Some(entry);
+                entry
+            }
+        }
+    }
+}
+
+impl Default for PawnTable {
+    fn default() -> PawnTable {
+        PawnTable::new()
+    }
+}
+
+#[cfg(test)]
+mod pawns_tests {
+    use super::*;
+
+    fn score(fen: &str) -> Score {
+        PawnEntry::analyse(&Board::from_fen(fen).unwrap()).score()
+    }
+
+    #[test]
+    fn symmetric_structures_cancel_out() {
+        assert_eq!(score("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1"), Score::default());
+        assert_eq!(score("4k3/pp3p1p/2p3p1/8/8/2P3P1/PP3P1P/4K3 w - - 0 1"), Score::default());
+    }
+
+    #[test]
+    fn doubled_and_isolated_pawns() {
+        // Two white pawns alone on the c-file against one black pawn on h7,
+        // which is isolated too but neither doubled nor passed by rank
+        let entry = PawnEntry::analyse(&Board::from_fen("4k3/7p/8/8/8/2P5/2P5/4K3 w - - 0 1").unwrap());
+        let white = DOUBLED + ISOLATED * 2 + Score::new(PASSED_MG[2], PASSED_EG[2]);
+        let black = ISOLATED + Score::new(PASSED_MG[1], PASSED_EG[1]);
+        assert_eq!(entry.scores, [white, black]);
+        // Only the front pawn of the two counts as passed
+        assert_eq!(entry.passed[0], bit_or_zero(2, 2));
+    }
+
+    #[test]
+    fn backward_and_connected_pawns() {
+        // d3 is left behind by c4 and e4, which it defends, and cannot
+        // advance past the pawn on c5
+        let expected = Score::new(CONNECTED[3], CONNECTED[3]) * 2 + BACKWARD + Score::new(PASSED_MG[3], PASSED_EG[3]) - ISOLATED;
+        assert_eq!(score("4k3/8/8/2p5/2P1P3/3P4/8/4K3 w - - 0 1"), expected);
+
+        let phalanx = score("4k3/8/8/8/3PP3/8/8/4K3 w - - 0 1");
+        let apart = score("4k3/8/8/8/2P2P2/8/8/4K3 w - - 0 1");
+        assert_eq!(phalanx - apart, Score::new(2 * CONNECTED[3], 2 * CONNECTED[3]) - ISOLATED * 2);
+    }
+
+    #[test]
+    fn passed_pawns_grow_with_their_rank() {
+        let board = Board::from_fen("4k3/8/1P6/8/8/8/8/4K3 w - - 0 1").unwrap();
+        let entry = PawnEntry::analyse(&board);
+        assert_eq!(entry.passed, [bit_or_zero(1, 5), 0]);
+        let far = Board::from_fen("4k3/8/8/8/8/1P6/8/4K3 w - - 0 1").unwrap();
+        assert!(entry.score().eg > PawnEntry::analyse(&far).score().eg);
+
+        // A blocked pawn is still passed but its path is not free
+        let blocked = Board::from_fen("4k3/1n6/1P6/8/8/8/8/4K3 w - - 0 1").unwrap();
+        let blocked_entry = PawnEntry::analyse(&blocked);
+        assert_eq!(blocked_entry.passed, entry.passed);
+        assert!(passed_pawn_extras(&blocked, &blocked_entry, Color::White).eg < passed_pawn_extras(&board, &entry, Color::White).eg);
+        // An enemy pawn ahead on a neighbouring file stops it being passed
+        assert_eq!(PawnEntry::analyse(&Board::from_fen("4k3/2p5/1P6/8/8/8/8/4K3 w - - 0 1").unwrap()).passed[0], 0);
+    }
+
+    #[test]
+    fn kings_near_the_passed_pawn() {
+        let near = Board::from_fen("8/8/1P6/1K6/8/8/8/7k w - - 0 1").unwrap();
+        let far = Board::from_fen("1k6/8/1P6/8/8/8/8/7K w - - 0 1").unwrap();
+        let extras = |board: &Board| passed_pawn_extras(board, &PawnEntry::analyse(board), Color::White).eg;
+        assert!(extras(&near) > extras(&far));
+    }
+
+    #[test]
+    fn table_caches_by_pawn_key() {
+        let mut table = PawnTable::new();
+        let mut board = Board::from_fen("4k3/7p/8/8/8/2P5/2P5/4K3 w - - 0 1").unwrap();
+        let entry = table.probe(&board);
+        assert_eq!(entry, PawnEntry::analyse(&board));
+        // A king move keeps the pawn key and the cached entry
+        board.play(crate::chess_move::Move::from_uci("e1d1").unwrap()).unwrap();
+        assert_eq!(board.pawn_key(), entry.key);
+        assert_eq!(table.probe(&board), entry);
+        board.play(crate::chess_move::Move::from_uci("h7h5").unwrap()).unwrap();
+        assert_ne!(board.pawn_key(), entry.key);
+        assert_eq!(table.probe(&board), PawnEntry::analyse(&board));
+    }
+}
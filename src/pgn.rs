@@ -0,0 +1,355 @@
+use crate::board::Board;
+use crate::chess_move::Move;
+use crate::file::File;
+use crate::rank::Rank;
+use crate::piece::PieceType;
+
+use std::io;
+use std::io::BufRead;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GameResult {
+    WhiteWins,
+    BlackWins,
+    Draw,
+    Unknown,
+}
+
+impl GameResult {
+    pub fn from_pgn(token: &str) -> Option<GameResult> {
+        match token {
+            "1-0" => Some(GameResult::WhiteWins),
+            "0-1" => Some(GameResult::BlackWins),
+            "1/2-1/2" => Some(GameResult::Draw),
+            "*" => Some(GameResult::Unknown),
+            _ => None
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PgnGame {
+    pub tags: Vec<(String, String)>,
+    // Main line moves in SAN, without move numbers, comments or variations
+    pub moves: Vec<String>,
+    pub result: GameResult,
+}
+
+impl PgnGame {
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags.iter().find(|(tag, _)| tag == name).map(|(_, value)| value.as_str())
+    }
+
+    // Starting position, honouring a FEN tag if the game has one
+    pub fn starting_board(&self) -> Result<Board, &'static str> {
+        match self.tag("FEN") {
+            Some(fen) => Board::from_fen(fen),
+            None => Board::build_starting()
+        }
+    }
+
+    // Parse one game from its tag section and movetext
+    pub fn parse(text: &str) -> PgnGame {
+        let mut tags = Vec::new();
+        let mut movetext = String::new();
+        for line in text.lines() {
+            let line = line.trim();
+            if line.starts_with('[') && line.ends_with(']') {
+                if let Some(tag) = parse_tag(line) {
+                    tags.push(tag);
+                }
+            } else if !line.starts_with('%') {
+                movetext.push_str(line);
+                movetext.push('\n');
+            }
+        }
+
+        let (moves, result_token) = parse_movetext(&movetext);
+        let result = result_token
+            .or_else(|| tags.iter().find(|(tag, _)| tag == "Result").and_then(|(_, value)| GameResult::from_pgn(value)))
+            .unwrap_or(GameResult::Unknown);
+        PgnGame{tags, moves, result}
+    }
+}
+
+fn parse_tag(line: &str) -> Option<(String, String)> {
+    let inner = &line[1..line.len() - 1];
+    let space = inner.find(char::is_whitespace)?;
+    let name = inner[..space].to_string();
+    let value = inner[space..].trim().trim_matches('"').replace("\\\"", "\"");
+    Some((name, value))
+}
+
+// Strip a move number such as "12." or "12..." glued to the move. Digits
+// with no period after them are left alone, so castling written with zeros
+// survives.
+fn strip_move_number(token: &str) -> &str {
+    let after_digits = token.trim_start_matches(|c: char| c.is_ascii_digit());
+    if after_digits.starts_with('.') {
+        after_digits.trim_start_matches('.')
+    } else {
+        token
+    }
+}
+
+// Split movetext into main line SAN moves, skipping move numbers, comments,
+// variations and annotation glyphs
+fn parse_movetext(movetext: &str) -> (Vec<String>, Option<GameResult>) {
+    let mut moves = Vec::new();
+    let mut result = None;
+    let mut variation_depth = 0;
+    let mut chars = movetext.chars().peekable();
+    let mut token = String::new();
+
+    let mut finish_token = |token: &mut String, variation_depth: u32| {
+        if variation_depth == 0 && !token.is_empty() {
+            if let Some(game_result) = GameResult::from_pgn(token) {
+                result = Some(game_result);
+            } else {
+                let san = strip_move_number(token);
+                if !san.is_empty() && !san.starts_with('$') {
+                    moves.push(san.to_string());
+                }
+            }
+        }
+        token.clear();
+    };
+
+    while let Some(c) = chars.next() {
+        match c {
+            '{' => {
+                finish_token(&mut token, variation_depth);
+                for c in chars.by_ref() {
+                    if c == '}' {
+                        break;
+                    }
+                }
+            },
+            ';' => {
+                finish_token(&mut token, variation_depth);
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+            },
+            '(' => {
+                finish_token(&mut token, variation_depth);
+                variation_depth += 1;
+            },
+            ')' => {
+                finish_token(&mut token, variation_depth);
+                variation_depth = variation_depth.saturating_sub(1);
+            },
+            c if c.is_whitespace() => finish_token(&mut token, variation_depth),
+            c => token.push(c)
+        }
+    }
+    finish_token(&mut token, variation_depth);
+    (moves, result)
+}
+
+// Streams games out of a PGN file one at a time
+pub struct PgnReader<R> {
+    reader: R,
+    pending: Option<String>,
+}
+
+impl<R: BufRead> PgnReader<R> {
+    pub fn new(reader: R) -> PgnReader<R> {
+        PgnReader{reader, pending: None}
+    }
+
+    fn next_game(&mut self) -> io::Result<Option<PgnGame>> {
+        let mut text = self.pending.take().unwrap_or_default();
+        let mut seen_movetext = false;
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if self.reader.read_line(&mut line)? == 0 {
+                break;
+            }
+            let trimmed = line.trim();
+            if trimmed.starts_with('[') && seen_movetext {
+                // A tag after movetext starts the next game
+                self.pending = Some(line.clone());
+                break;
+            }
+            if !trimmed.is_empty() && !trimmed.starts_with('[') {
+                seen_movetext = true;
+            }
+            text.push_str(&line);
+        }
+
+        if text.trim().is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(PgnGame::parse(&text)))
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for PgnReader<R> {
+    type Item = io::Result<PgnGame>;
+
+    fn next(&mut self) -> Option<io::Result<PgnGame>> {
+        self.next_game().transpose()
+    }
+}
+
+// Find the legal move written in Standard Algebraic Notation, e.g. "Nbd7",
+// "exd6", "O-O-O" or "e8=Q+"
+pub fn parse_san(board: &mut Board, san: &str) -> Result<Move, &'static str> {
+    let san = san.trim_end_matches(['+', '#', '!', '?']);
+    let legal_moves = board.legal_moves();
+
+    if san == "O-O" || san == "0-0" || san == "O-O-O" || san == "0-0-0" {
+        let king_file = if san.len() == 3 { 6 } else { 2 };
+        return legal_moves.into_iter().find(|mv| {
+            let is_king = board.piece_at(mv.from_file, mv.from_rank).is_some_and(|piece| piece.piece_type() == PieceType::King);
+            is_king && mv.from_file.value() == 4 && mv.to_file.value() == king_file && mv.from_rank == mv.to_rank
+        }).ok_or("Castling is not legal");
+    }
+
+    let (body, promotion) = match san.find('=') {
+        Some(index) => (&san[..index], Some(promotion_type(&san[index + 1..])?)),
+        None => {
+            // Some writers leave out the '=', e.g. "e8Q"
+            match san.chars().last() {
+                Some(c) if "NBRQ".contains(c) && san.len() > 2 && san.as_bytes()[san.len() - 2].is_ascii_digit() => {
+                    (&san[..san.len() - 1], Some(promotion_type(&c.to_string())?))
+                },
+                _ => (san, None)
+            }
+        }
+    };
+
+    let mut chars: Vec<char> = body.chars().filter(|c| *c != 'x' && *c != '-').collect();
+    let piece_type = match chars.first() {
+        Some('N') => PieceType::Knight,
+        Some('B') => PieceType::Bishop,
+        Some('R') => PieceType::Rook,
+        Some('Q') => PieceType::Queen,
+        Some('K') => PieceType::King,
+        Some(_) => PieceType::Pawn,
+        None => return Err("Empty move")
+    };
+    if piece_type != PieceType::Pawn {
+        chars.remove(0);
+    }
+    if chars.len() < 2 {
+        return Err("Move has no destination square");
+    }
+    let to_file = File::from_char(chars[chars.len() - 2])?;
+    let to_rank = Rank::from_char(chars[chars.len() - 1])?;
+
+    // Whatever is left between the piece and the destination disambiguates
+    let mut from_file = None;
+    let mut from_rank = None;
+    for c in chars[..chars.len() - 2].iter() {
+        if let Ok(file) = File::from_char(*c) {
+            from_file = Some(file);
+        } else if let Ok(rank) = Rank::from_char(*c) {
+            from_rank = Some(rank);
+        } else {
+            return Err("Invalid character in move");
+        }
+    }
+
+    let candidates: Vec<Move> = legal_moves.into_iter().filter(|mv| {
+        mv.to_file == to_file
+            && mv.to_rank == to_rank
+            && mv.promotion == promotion
+            && from_file.is_none_or(|file| file == mv.from_file)
+            && from_rank.is_none_or(|rank| rank == mv.from_rank)
+            && board.piece_at(mv.from_file, mv.from_rank).is_some_and(|piece| piece.piece_type() == piece_type)
+    }).collect();
+
+    match candidates.len() {
+        0 => Err("No legal move matches"),
+        1 => Ok(candidates[0]),
+        _ => Err("Ambiguous move")
+    }
+}
+
+fn promotion_type(text: &str) -> Result<PieceType, &'static str> {
+    match text.chars().next() {
+        Some('N') => Ok(PieceType::Knight),
+        Some('B') => Ok(PieceType::Bishop),
+        Some('R') => Ok(PieceType::Rook),
+        Some('Q') => Ok(PieceType::Queen),
+        _ => Err("Invalid promotion piece")
+    }
+}
+
+#[cfg(test)]
+mod pgn_tests {
+    use super::*;
+    use std::io::Cursor;
+
+    const TWO_GAMES: &str = r#"[Event "Casual"]
+[White "A"]
+[Black "B"]
+[Result "1-0"]
+
+1. e4 {best by test} e5 2. Nf3 (2. f4 exf4) Nc6 $1 3. Bb5 a6
+4. Ba4 ; the main line
+Nf6 1-0
+
+[Event "Casual"]
+[Result "1/2-1/2"]
+
+1.d4 d5 2.c4 1/2-1/2
+"#;
+
+    #[test]
+    fn reads_games_from_stream() {
+        let games: Vec<PgnGame> = PgnReader::new(Cursor::new(TWO_GAMES)).map(|game| game.unwrap()).collect();
+        assert_eq!(games.len(), 2);
+
+        assert_eq!(games[0].tag("White"), Some("A"));
+        assert_eq!(games[0].moves, vec!["e4", "e5", "Nf3", "Nc6", "Bb5", "a6", "Ba4", "Nf6"]);
+        assert_eq!(games[0].result, GameResult::WhiteWins);
+
+        assert_eq!(games[1].moves, vec!["d4", "d5", "c4"]);
+        assert_eq!(games[1].result, GameResult::Draw);
+    }
+
+    #[test]
+    fn result_from_tag_when_movetext_has_none() {
+        let game = PgnGame::parse("[Result \"0-1\"]\n\n1. f3 e5 2. g4 Qh4#\n");
+        assert_eq!(game.result, GameResult::BlackWins);
+        assert_eq!(game.moves.len(), 4);
+    }
+
+    #[test]
+    fn castling_with_zeros_keeps_its_zeros() {
+        let game = PgnGame::parse("[Result \"*\"]\n\n1. e4 e5 2.Nf3 Nc6 3... Bc5 4. 0-0 Nf6 5.0-0-0 *\n");
+        assert_eq!(game.moves, vec!["e4", "e5", "Nf3", "Nc6", "Bc5", "0-0", "Nf6", "0-0-0"]);
+    }
+
+    #[test]
+    fn san_pieces_and_disambiguation() {
+        let mut board = Board::from_fen("4k3/8/8/R7/8/5N2/8/RN2K2R w K - 0 1").unwrap();
+        assert_eq!(parse_san(&mut board, "Nc3"), Ok(Move::from_uci("b1c3").unwrap()));
+        assert_eq!(parse_san(&mut board, "Nbd2"), Ok(Move::from_uci("b1d2").unwrap()));
+        assert_eq!(parse_san(&mut board, "Nfxd2"), Ok(Move::from_uci("f3d2").unwrap()));
+        assert_eq!(parse_san(&mut board, "R1a3"), Ok(Move::from_uci("a1a3").unwrap()));
+        assert_eq!(parse_san(&mut board, "Rh7"), Ok(Move::from_uci("h1h7").unwrap()));
+        assert_eq!(parse_san(&mut board, "O-O"), Ok(Move::from_uci("e1g1").unwrap()));
+        assert_eq!(parse_san(&mut board, "Nd2"), Err("Ambiguous move"));
+        assert_eq!(parse_san(&mut board, "Ra3"), Err("Ambiguous move"));
+        assert!(parse_san(&mut board, "O-O-O").is_err());
+        assert!(parse_san(&mut board, "Qd4").is_err());
+    }
+
+    #[test]
+    fn san_pawns() {
+        let mut board = Board::from_fen("2n1k3/1P6/8/3pP3/8/8/8/4K3 w - d6 0 1").unwrap();
+        assert_eq!(parse_san(&mut board, "exd6"), Ok(Move::from_uci("e5d6").unwrap()));
+        assert_eq!(parse_san(&mut board, "e6"), Ok(Move::from_uci("e5e6").unwrap()));
+        assert_eq!(parse_san(&mut board, "bxc8=Q+"), Ok(Move::from_uci("b7c8q").unwrap()));
+        assert_eq!(parse_san(&mut board, "b8N"), Ok(Move::from_uci("b7b8n").unwrap()));
+        assert!(parse_san(&mut board, "b8").is_err());
+    }
+}
@@ -3,13 +3,30 @@ use crate::square::Square;
 use crate::rank::Rank;
 use crate::file::File;
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Color {
     White,
     Black,
 }
 
-#[derive(Debug, Clone, Copy)]
+impl Color {
+    pub fn opposite(&self) -> Color {
+        match self {
+            Color::White => Color::Black,
+            Color::Black => Color::White
+        }
+    }
+
+    // Position in tables with one entry per side, White first
+    pub fn index(&self) -> usize {
+        match self {
+            Color::White => 0,
+            Color::Black => 1
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum PieceType {
     Pawn,
     Knight,
@@ -19,7 +36,26 @@ pub enum PieceType {
     King
 }
 
-#[derive(Debug, Clone, Copy)]
+impl PieceType {
+    // Material value in centipawns, used to weigh exchanges
+    pub fn value(&self) -> i32 {
+        match self {
+            PieceType::Pawn => 100,
+            PieceType::Knight => 320,
+            PieceType::Bishop => 330,
+            PieceType::Rook => 500,
+            PieceType::Queen => 900,
+            PieceType::King => 0
+        }
+    }
+}
+
+pub const BISHOP_DIRECTIONS: [(i8, i8); 4] = [(1, 1), (1, -1), (-1, 1), (-1, -1)];
+pub const ROOK_DIRECTIONS: [(i8, i8); 4] = [(1, 0), (-1, 0), (0, 1), (0, -1)];
+pub const KNIGHT_OFFSETS: [(i8, i8); 8] = [(1, 2), (2, 1), (2, -1), (1, -2), (-1, -2), (-2, -1), (-2, 1), (-1, 2)];
+pub const KING_OFFSETS: [(i8, i8); 8] = [(1, 1), (1, 0), (1, -1), (0, -1), (-1, -1), (-1, 0), (-1, 1), (0, 1)];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Piece {
     color: Color,
     piece_type: PieceType,
@@ -27,8 +63,8 @@ pub struct Piece {
 }
 
 impl Piece {
-    fn new(color: Color, piece_type: PieceType, has_moved: bool) -> Piece {
-        Piece { color: color, has_moved: has_moved, piece_type: piece_type }
+    pub fn new(color: Color, piece_type: PieceType, has_moved: bool) -> Piece {
+        Piece { color, has_moved, piece_type }
     }
 
     pub fn get_color(&self) -> Color { self.color }
@@ -39,6 +75,54 @@ impl Piece {
 
     pub fn piece_type(&self) -> PieceType { self.piece_type }
 
+    // Parse a FEN piece letter, uppercase for white
+    pub fn from_char(c: char) -> Result<Piece, &'static str> {
+        let color = if c.is_ascii_uppercase() { Color::White } else { Color::Black };
+        let piece_type = match c.to_ascii_lowercase() {
+            'p' => PieceType::Pawn,
+            'n' => PieceType::Knight,
+            'b' => PieceType::Bishop,
+            'r' => PieceType::Rook,
+            'q' => PieceType::Queen,
+            'k' => PieceType::King,
+            _ => return Err("Invalid piece character")
+        };
+        Ok(Piece::new(color, piece_type, false))
+    }
+
+    // Letter used for this piece in FEN and ASCII diagrams, uppercase for white
+    pub fn to_char(&self) -> char {
+        let c = match self.piece_type {
+            PieceType::Pawn => 'p',
+            PieceType::Knight => 'n',
+            PieceType::Bishop => 'b',
+            PieceType::Rook => 'r',
+            PieceType::Queen => 'q',
+            PieceType::King => 'k',
+        };
+        match self.color {
+            Color::White => c.to_ascii_uppercase(),
+            Color::Black => c
+        }
+    }
+
+    pub fn to_unicode(&self) -> char {
+        match (self.color, self.piece_type) {
+            (Color::White, PieceType::Pawn) => '\u{2659}',
+            (Color::White, PieceType::Knight) => '\u{2658}',
+            (Color::White, PieceType::Bishop) => '\u{2657}',
+            (Color::White, PieceType::Rook) => '\u{2656}',
+            (Color::White, PieceType::Queen) => '\u{2655}',
+            (Color::White, PieceType::King) => '\u{2654}',
+            (Color::Black, PieceType::Pawn) => '\u{265F}',
+            (Color::Black, PieceType::Knight) => '\u{265E}',
+            (Color::Black, PieceType::Bishop) => '\u{265D}',
+            (Color::Black, PieceType::Rook) => '\u{265C}',
+            (Color::Black, PieceType::Queen) => '\u{265B}',
+            (Color::Black, PieceType::King) => '\u{265A}',
+        }
+    }
+
     pub fn get_moves<'a>(&self, board: &'a Board, square: &Square) -> Vec<&'a Square> {
         match self.piece_type {
             PieceType::Pawn => self.get_pawn_moves(board, square),
@@ -158,37 +242,41 @@ impl Piece {
     }
 
     fn get_bishop_moves<'a>(&self, board: &'a Board, square: &Square) -> Vec<&'a Square> {
-        board.get_diagonals(square).iter().filter_map(|&sq| {
-            if sq.rank != square.rank && sq.file != square.file && !sq.piece_matches_color(self.color) {
-                Some(sq)
-            } else {
-                None
-            }
-        }).collect()
+        self.get_sliding_moves(board, square, &BISHOP_DIRECTIONS)
     }
 
     fn get_rook_moves<'a>(&self, board: &'a Board, square: &Square) -> Vec<&'a Square> {
-        board.get_file(square.file).iter().chain(
-            board.get_rank(square.rank).iter()
-        ).filter(|sq| {
-            (square.rank != sq.rank || square.file != sq.file) && !sq.piece_matches_color(self.color)
-        }).map(|&sq| {
-            sq
-        }).collect()
+        self.get_sliding_moves(board, square, &ROOK_DIRECTIONS)
     }
 
     fn get_queen_moves<'a>(&self, board: &'a Board, square: &Square) -> Vec<&'a Square> {
-        board.get_diagonals(square).iter().chain(
-            board.get_file(square.file).iter()
-        ).chain(
-            board.get_rank(square.rank).iter()
-        ).filter_map(|&sq| {
-            if (sq.rank != square.rank || sq.file != square.file) && !sq.piece_matches_color(self.color) {
-                Some(sq)
-            } else {
-                None
+        let mut res = self.get_sliding_moves(board, square, &BISHOP_DIRECTIONS);
+        res.extend(self.get_sliding_moves(board, square, &ROOK_DIRECTIONS));
+        res
+    }
+
+    // Walk each ray until the edge of the board or the first piece. An enemy
+    // piece blocking the ray can be captured, a friendly one cannot.
+    fn get_sliding_moves<'a>(&self, board: &'a Board, square: &Square, directions: &[(i8, i8)]) -> Vec<&'a Square> {
+        let mut res: Vec<&Square> = Vec::new();
+        for (file_step, rank_step) in directions.iter() {
+            let mut file = square.file;
+            let mut rank = square.rank;
+            while let (Ok(next_file), Ok(next_rank)) = (file.offset(*file_step), rank.offset(*rank_step)) {
+                file = next_file;
+                rank = next_rank;
+                let sq = board.get_square(file, rank);
+                if sq.is_empty() {
+                    res.push(sq);
+                } else {
+                    if !sq.piece_matches_color(self.color) {
+                        res.push(sq);
+                    }
+                    break;
+                }
             }
-        }).collect()
+        }
+        res
     }
 
     fn get_king_moves<'a>(&self, board: &'a Board, square: &Square) -> Vec<&'a Square> {
@@ -208,6 +296,7 @@ impl Piece {
 }
 
 #[cfg(test)]
+#[allow(clippy::useless_vec)]
 mod king_tests {
     use super::*;
 
@@ -296,6 +385,7 @@ mod king_tests {
 }
 
 #[cfg(test)]
+#[allow(clippy::useless_vec, clippy::redundant_field_names)]
 mod knight_tests {
     use super::*;
 
@@ -406,6 +496,7 @@ mod knight_tests {
 }
 
 #[cfg(test)]
+#[allow(clippy::useless_vec)]
 mod bishop_tests {
     use super::*;
 
@@ -649,6 +740,7 @@ mod bishop_tests {
 }
 
 #[cfg(test)]
+#[allow(clippy::redundant_field_names)]
 mod rook_tests {
     use super::*;
 
@@ -784,6 +876,7 @@ mod rook_tests {
 }
 
 #[cfg(test)]
+#[allow(clippy::redundant_field_names)]
 mod queen_tests {
     use super::*;
 
@@ -934,6 +1027,7 @@ mod queen_tests {
 }
 
 #[cfg(test)]
+#[allow(clippy::useless_vec, clippy::needless_borrow)]
 mod pawn_tests {
     use super::*;
 
@@ -0,0 +1,306 @@
+use crate::board::Board;
+use crate::chess_move::Move;
+use crate::file::File;
+use crate::rank::Rank;
+use crate::piece::PieceType;
+use crate::random::Random;
+
+use std::fs;
+use std::io;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::path::Path;
+
+pub const ENTRY_SIZE: u64 = 16;
+
+// One 16 byte record of a Polyglot book, stored big-endian on disk.
+// Entries are sorted by key so all moves for a position are adjacent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BookEntry {
+    pub key: u64,
+    pub raw_move: u16,
+    pub weight: u16,
+    pub learn: u32,
+}
+
+impl BookEntry {
+    pub fn from_bytes(bytes: &[u8; 16]) -> BookEntry {
+        let mut key = [0; 8];
+        key.copy_from_slice(&bytes[0..8]);
+        BookEntry{
+            key: u64::from_be_bytes(key),
+            raw_move: u16::from_be_bytes([bytes[8], bytes[9]]),
+            weight: u16::from_be_bytes([bytes[10], bytes[11]]),
+            learn: u32::from_be_bytes([bytes[12], bytes[13], bytes[14], bytes[15]]),
+        }
+    }
+
+    pub fn to_bytes(&self) -> [u8; 16] {
+        let mut bytes = [0; 16];
+        bytes[0..8].copy_from_slice(&self.key.to_be_bytes());
+        bytes[8..10].copy_from_slice(&self.raw_move.to_be_bytes());
+        bytes[10..12].copy_from_slice(&self.weight.to_be_bytes());
+        bytes[12..16].copy_from_slice(&self.learn.to_be_bytes());
+        bytes
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BookMove {
+    pub mv: Move,
+    pub weight: u16,
+}
+
+// Reader over a Polyglot .bin book. Entries are read on demand with a binary
+// search, so large books are never loaded into memory.
+pub struct PolyglotBook<R> {
+    reader: R,
+    num_entries: u64,
+}
+
+impl PolyglotBook<fs::File> {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<PolyglotBook<fs::File>> {
+        PolyglotBook::from_reader(fs::File::open(path)?)
+    }
+}
+
+impl<R: Read + Seek> PolyglotBook<R> {
+    pub fn from_reader(mut reader: R) -> io::Result<PolyglotBook<R>> {
+        let len = reader.seek(SeekFrom::End(0))?;
+        if len % ENTRY_SIZE != 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Book size is not a multiple of the entry size"));
+        }
+        Ok(PolyglotBook{reader, num_entries: len / ENTRY_SIZE})
+    }
+
+    pub fn num_entries(&self) -> u64 {
+        self.num_entries
+    }
+
+    fn read_entry(&mut self, index: u64) -> io::Result<BookEntry> {
+        let mut bytes = [0; 16];
+        self.reader.seek(SeekFrom::Start(index * ENTRY_SIZE))?;
+        self.reader.read_exact(&mut bytes)?;
+        Ok(BookEntry::from_bytes(&bytes))
+    }
+
+    // All entries stored for a position key, in file order
+    pub fn entries(&mut self, key: u64) -> io::Result<Vec<BookEntry>> {
+        // Find the first entry whose key is not less than the one we want
+        let mut low = 0;
+        let mut high = self.num_entries;
+        while low < high {
+            let mid = low + (high - low) / 2;
+            if self.read_entry(mid)?.key < key {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+
+        let mut res = Vec::new();
+        let mut index = low;
+        while index < self.num_entries {
+            let entry = self.read_entry(index)?;
+            if entry.key != key {
+                break;
+            }
+            res.push(entry);
+            index += 1;
+        }
+        Ok(res)
+    }
+
+    // Legal book moves for the position. Entries that don't decode to a
+    // legal move (e.g. from a key collision) are skipped.
+    pub fn moves(&mut self, board: &mut Board) -> io::Result<Vec<BookMove>> {
+        let entries = self.entries(board.zobrist_key())?;
+        Ok(entries.iter().filter_map(|entry| {
+            decode_move(board, entry.raw_move).map(|mv| BookMove{mv, weight: entry.weight})
+        }).collect())
+    }
+
+    // The book move with the highest weight
+    pub fn best_move(&mut self, board: &mut Board) -> io::Result<Option<Move>> {
+        let moves = self.moves(board)?;
+        // max_by_key returns the last maximum, prefer the first in file order
+        Ok(moves.iter().rev().max_by_key(|book_move| book_move.weight).map(|book_move| book_move.mv))
+    }
+
+    // A book move picked at random with probability proportional to its weight
+    pub fn weighted_move(&mut self, board: &mut Board, random: &mut Random) -> io::Result<Option<Move>> {
+        let moves = self.moves(board)?;
+        let total: u64 = moves.iter().map(|book_move| u64::from(book_move.weight)).sum();
+        if total == 0 {
+            return Ok(moves.first().map(|book_move| book_move.mv));
+        }
+        let mut pick = random.below(total);
+        for book_move in moves.iter() {
+            let weight = u64::from(book_move.weight);
+            if pick < weight {
+                return Ok(Some(book_move.mv));
+            }
+            pick -= weight;
+        }
+        Ok(None)
+    }
+}
+
+// Turn a Polyglot move into a legal move on the board. Polyglot packs
+//   bits 0-2 to file, 3-5 to row, 6-8 from file, 9-11 from row,
+//   12-14 promotion piece (none, knight, bishop, rook, queen)
+// and writes castling as the king capturing its own rook, e.g. e1h1.
+pub fn decode_move(board: &mut Board, raw_move: u16) -> Option<Move> {
+    let field = |shift: u16| ((raw_move >> shift) & 0x7) as u8;
+    let to_file = File::build(field(0)).ok()?;
+    let to_rank = Rank::build(field(3)).ok()?;
+    let from_file = File::build(field(6)).ok()?;
+    let from_rank = Rank::build(field(9)).ok()?;
+    let promotion = match field(12) {
+        0 => None,
+        1 => Some(PieceType::Knight),
+        2 => Some(PieceType::Bishop),
+        3 => Some(PieceType::Rook),
+        4 => Some(PieceType::Queen),
+        _ => return None
+    };
+
+    let mut mv = Move{from_file, from_rank, to_file, to_rank, promotion};
+    let is_king = board.piece_at(from_file, from_rank).is_some_and(|piece| piece.piece_type() == PieceType::King);
+    if is_king && from_file.value() == 4 && from_rank == to_rank {
+        match to_file.value() {
+            7 => mv.to_file = File::build(6).ok()?,
+            0 => mv.to_file = File::build(2).ok()?,
+            _ => {}
+        }
+    }
+
+    if board.legal_moves().contains(&mv) { Some(mv) } else { None }
+}
+
+// Inverse of decode_move, writing castling in Polyglot's king-takes-rook form
+pub fn encode_move(board: &Board, mv: &Move) -> u16 {
+    let mut to_file = mv.to_file.value();
+    let is_king = board.piece_at(mv.from_file, mv.from_rank).is_some_and(|piece| piece.piece_type() == PieceType::King);
+    if is_king && mv.from_file.value() == 4 && mv.from_rank == mv.to_rank {
+        match to_file {
+            6 => to_file = 7,
+            2 => to_file = 0,
+            _ => {}
+        }
+    }
+    let promotion = match mv.promotion {
+        Some(PieceType::Knight) => 1,
+        Some(PieceType::Bishop) => 2,
+        Some(PieceType::Rook) => 3,
+        Some(PieceType::Queen) => 4,
+        _ => 0
+    };
+    u16::from(to_file)
+        | u16::from(mv.to_rank.value()) << 3
+        | u16::from(mv.from_file.value()) << 6
+        | u16::from(mv.from_rank.value()) << 9
+        | promotion << 12
+}
+
+#[cfg(test)]
+mod polyglot_tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn raw_move(uci: &str) -> u16 {
+        let mv = Move::from_uci(uci).unwrap();
+        u16::from(mv.to_file.value())
+            | u16::from(mv.to_rank.value()) << 3
+            | u16::from(mv.from_file.value()) << 6
+            | u16::from(mv.from_rank.value()) << 9
+    }
+
+    fn book(entries: &[BookEntry]) -> PolyglotBook<Cursor<Vec<u8>>> {
+        let mut sorted = entries.to_vec();
+        sorted.sort_by_key(|entry| entry.key);
+        let bytes: Vec<u8> = sorted.iter().flat_map(|entry| entry.to_bytes().to_vec()).collect();
+        PolyglotBook::from_reader(Cursor::new(bytes)).unwrap()
+    }
+
+    fn entry(key: u64, uci: &str, weight: u16) -> BookEntry {
+        BookEntry{key, raw_move: raw_move(uci), weight, learn: 0}
+    }
+
+    #[test]
+    fn entry_round_trip() {
+        let entry = BookEntry{key: 0x463b96181691fc9c, raw_move: 0x031c, weight: 12, learn: 7};
+        let bytes = entry.to_bytes();
+        assert_eq!(bytes[0], 0x46);
+        assert_eq!(BookEntry::from_bytes(&bytes), entry);
+    }
+
+    #[test]
+    fn rejects_truncated_book() {
+        assert!(PolyglotBook::from_reader(Cursor::new(vec![0; 17])).is_err());
+    }
+
+    #[test]
+    fn finds_entries_by_binary_search() {
+        let start = Board::build_starting().unwrap().zobrist_key();
+        let mut book = book(&[
+            entry(1, "a2a3", 1),
+            entry(start, "e2e4", 10),
+            entry(start, "d2d4", 30),
+            entry(start, "g1f3", 5),
+            entry(u64::MAX, "h2h3", 1),
+        ]);
+        assert_eq!(book.num_entries(), 5);
+        assert_eq!(book.entries(start).unwrap().len(), 3);
+        assert!(book.entries(2).unwrap().is_empty());
+
+        let mut board = Board::build_starting().unwrap();
+        assert_eq!(book.best_move(&mut board).unwrap(), Some(Move::from_uci("d2d4").unwrap()));
+    }
+
+    #[test]
+    fn weighted_move_follows_weights() {
+        let start = Board::build_starting().unwrap().zobrist_key();
+        let mut book = book(&[
+            entry(start, "e2e4", 1),
+            entry(start, "d2d4", 0),
+            entry(start, "c2c4", 3),
+        ]);
+        let mut board = Board::build_starting().unwrap();
+        let mut random = Random::new(1);
+        let d2d4 = Move::from_uci("d2d4").unwrap();
+        for _ in 0..50 {
+            let mv = book.weighted_move(&mut board, &mut random).unwrap().unwrap();
+            assert_ne!(mv, d2d4);
+        }
+    }
+
+    #[test]
+    fn decodes_castling_as_king_takes_rook() {
+        let mut board = Board::from_fen("r3k2r/pppppppp/8/8/8/8/PPPPPPPP/R3K2R w KQkq - 0 1").unwrap();
+        assert_eq!(decode_move(&mut board, raw_move("e1h1")), Some(Move::from_uci("e1g1").unwrap()));
+        assert_eq!(decode_move(&mut board, raw_move("e1a1")), Some(Move::from_uci("e1c1").unwrap()));
+        board.make_move(Move::from_uci("a2a3").unwrap());
+        assert_eq!(decode_move(&mut board, raw_move("e8h8")), Some(Move::from_uci("e8g8").unwrap()));
+        assert_eq!(decode_move(&mut board, raw_move("e8a8")), Some(Move::from_uci("e8c8").unwrap()));
+    }
+
+    #[test]
+    fn encode_decode_round_trip() {
+        let mut board = Board::from_fen("r3k2r/pPpppppp/8/8/8/8/PPPPPPPP/R3K2R w KQkq - 0 1").unwrap();
+        assert_eq!(encode_move(&board, &Move::from_uci("e1g1").unwrap()), raw_move("e1h1"));
+        for mv in board.legal_moves() {
+            let raw = encode_move(&board, &mv);
+            assert_eq!(decode_move(&mut board, raw), Some(mv));
+        }
+    }
+
+    #[test]
+    fn decodes_promotions_and_rejects_illegal_moves() {
+        let mut board = Board::from_fen("8/P6k/8/8/8/8/8/K7 w - - 0 1").unwrap();
+        let promote = raw_move("a7a8") | 4 << 12;
+        assert_eq!(decode_move(&mut board, promote), Some(Move::from_uci("a7a8q").unwrap()));
+        assert_eq!(decode_move(&mut board, raw_move("a1a3")), None);
+    }
+}
@@ -0,0 +1,58 @@
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+// Small xorshift64* generator. Not cryptographic, but fast, seedable and
+// good enough for picking book moves and openings.
+#[derive(Debug, Clone)]
+pub struct Random {
+    state: u64,
+}
+
+impl Random {
+    pub fn new(seed: u64) -> Random {
+        // A zero state would only ever produce zeros
+        Random{state: if seed == 0 { 0x9E37_79B9_7F4A_7C15 } else { seed }}
+    }
+
+    // Seed from the system clock
+    pub fn from_time() -> Random {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos() as u64).unwrap_or(0);
+        Random::new(nanos)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    // Uniform value in 0..bound
+    pub fn below(&mut self, bound: u64) -> u64 {
+        if bound == 0 {
+            return 0;
+        }
+        self.next_u64() % bound
+    }
+}
+
+#[cfg(test)]
+mod random_tests {
+    use super::*;
+
+    #[test]
+    fn same_seed_same_sequence() {
+        let mut a = Random::new(42);
+        let mut b = Random::new(42);
+        for _ in 0..10 {
+            assert_eq!(a.next_u64(), b.next_u64());
+        }
+    }
+
+    #[test]
+    fn below_stays_in_range() {
+        let mut random = Random::new(7);
+        assert!((0..1000).all(|_| random.below(6) < 6));
+        assert_eq!(random.below(0), 0);
+    }
+}
@@ -18,19 +18,46 @@ impl Rank {
     }
 
     pub fn iter_ranks(range: impl Iterator<Item = u8>) -> impl Iterator<Item = Rank> {
-        range.filter(|rank| rank < &MAX_NUMBER_OF_RANKS).map(|rank| Rank::new(rank))
+        range.filter(|rank| rank < &MAX_NUMBER_OF_RANKS).map(Rank::new)
     }
 
     pub fn value(&self) -> u8 {
         self.val
     }
 
+    // Parse a rank from its algebraic character ('1'-'8')
+    pub fn from_char(c: char) -> Result<Rank, &'static str> {
+        match (c as u32).checked_sub('1' as u32) {
+            Some(val) if val < u32::from(MAX_NUMBER_OF_RANKS) => Ok(Rank{val: val as u8}),
+            _ => Err("Rank character outside allowable bounds")
+        }
+    }
+
+    pub fn to_char(&self) -> char {
+        (b'1' + self.val) as char
+    }
+
     pub fn next_by(&self, jump: u8) -> Result<Rank, &'static str> {
-        Rank::build(self.val + jump)
+        match self.val.checked_add(jump) {
+            Some(val) => Rank::build(val),
+            None => Err("Rank outside allowable bounds")
+        }
     }
 
     pub fn previous_by(&self, jump: u8) -> Result<Rank, &'static str> {
-        Rank::build(self.val - jump)
+        match self.val.checked_sub(jump) {
+            Some(val) => Rank::build(val),
+            None => Err("Rank outside allowable bounds")
+        }
+    }
+
+    // Step by a signed amount, used when walking rays across the board
+    pub fn offset(&self, delta: i8) -> Result<Rank, &'static str> {
+        if delta >= 0 {
+            self.next_by(delta as u8)
+        } else {
+            self.previous_by(delta.unsigned_abs())
+        }
     }
 }
 
@@ -56,4 +83,19 @@ impl PartialOrd for Rank {
     fn partial_cmp(&self, other: &Rank) -> Option<Ordering> {
         Some(self.cmp(other))
     }
-}
\ No newline at end of file
+}
+
+use std::hash::Hash;
+use std::hash::Hasher;
+impl Hash for Rank {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.val.hash(state);
+    }
+}
+
+use std::fmt;
+impl fmt::Display for Rank {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.to_char())
+    }
+}
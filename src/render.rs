@@ -0,0 +1,163 @@
+use crate::board::Board;
+use crate::chess_move::Move;
+use crate::square::Square;
+use crate::rank;
+use crate::rank::Rank;
+use crate::file;
+use crate::file::File;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PieceStyle {
+    Ascii,
+    Unicode,
+}
+
+#[derive(Debug, Clone)]
+pub struct RenderOptions {
+    pub style: PieceStyle,
+    // Draw the board from black's side, rank 1 at the top
+    pub flipped: bool,
+    pub highlights: Vec<(File, Rank)>,
+}
+
+impl RenderOptions {
+    pub fn new() -> RenderOptions {
+        RenderOptions{
+            style: PieceStyle::Ascii,
+            flipped: false,
+            highlights: Vec::new()
+        }
+    }
+
+    pub fn highlight_square(&mut self, file: File, rank: Rank) {
+        if !self.is_highlighted(file, rank) {
+            self.highlights.push((file, rank));
+        }
+    }
+
+    pub fn highlight_move(&mut self, mv: &Move) {
+        self.highlight_square(mv.from_file, mv.from_rank);
+        self.highlight_square(mv.to_file, mv.to_rank);
+    }
+
+    fn is_highlighted(&self, file: File, rank: Rank) -> bool {
+        self.highlights.contains(&(file, rank))
+    }
+}
+
+impl Default for RenderOptions {
+    fn default() -> RenderOptions {
+        RenderOptions::new()
+    }
+}
+
+// Render the board as an 8x8 text diagram with rank and file labels.
+// Highlighted squares are drawn in brackets, e.g. [P].
+pub fn render_text(board: &Board, options: &RenderOptions) -> String {
+    let mut ranks: Vec<Rank> = Rank::iter_ranks(0..rank::MAX_NUMBER_OF_RANKS).collect();
+    let mut files: Vec<File> = File::iter_files(0..file::MAX_NUMBER_OF_FILES).collect();
+    if options.flipped {
+        files.reverse();
+    } else {
+        ranks.reverse();
+    }
+
+    let mut res = String::new();
+    for rank in ranks.iter() {
+        let mut line = format!("{} ", rank);
+        for file in files.iter() {
+            let symbol = square_symbol(board.get_square(*file, *rank), options.style);
+            if options.is_highlighted(*file, *rank) {
+                line.push('[');
+                line.push(symbol);
+                line.push(']');
+            } else {
+                line.push(' ');
+                line.push(symbol);
+                line.push(' ');
+            }
+        }
+        res.push_str(line.trim_end());
+        res.push('\n');
+    }
+
+    let mut footer = String::from("  ");
+    for file in files.iter() {
+        footer.push(' ');
+        footer.push(file.to_char());
+        footer.push(' ');
+    }
+    res.push_str(footer.trim_end());
+    res.push('\n');
+    res
+}
+
+fn square_symbol(square: &Square, style: PieceStyle) -> char {
+    match (&square.piece, style) {
+        (Some(piece), PieceStyle::Ascii) => piece.to_char(),
+        (Some(piece), PieceStyle::Unicode) => piece.to_unicode(),
+        (None, PieceStyle::Ascii) => '.',
+        (None, PieceStyle::Unicode) => '\u{00B7}',
+    }
+}
+
+#[cfg(test)]
+mod render_tests {
+    use super::*;
+
+    #[test]
+    fn starting_position_ascii() {
+        let board = Board::build_starting().unwrap();
+        let expected = "\
+8  r  n  b  q  k  b  n  r
+7  p  p  p  p  p  p  p  p
+6  .  .  .  .  .  .  .  .
+5  .  .  .  .  .  .  .  .
+4  .  .  .  .  .  .  .  .
+3  .  .  .  .  .  .  .  .
+2  P  P  P  P  P  P  P  P
+1  R  N  B  Q  K  B  N  R
+   a  b  c  d  e  f  g  h
+";
+        assert_eq!(render_text(&board, &RenderOptions::new()), expected);
+        assert_eq!(board.to_string(), expected);
+    }
+
+    #[test]
+    fn starting_position_flipped() {
+        let board = Board::build_starting().unwrap();
+        let mut options = RenderOptions::new();
+        options.flipped = true;
+        let rendered = render_text(&board, &options);
+        let lines: Vec<&str> = rendered.lines().collect();
+
+        assert_eq!(lines[0], "1  R  N  B  K  Q  B  N  R");
+        assert_eq!(lines[7], "8  r  n  b  k  q  b  n  r");
+        assert_eq!(lines[8], "   h  g  f  e  d  c  b  a");
+    }
+
+    #[test]
+    fn starting_position_unicode() {
+        let board = Board::build_starting().unwrap();
+        let mut options = RenderOptions::new();
+        options.style = PieceStyle::Unicode;
+        let rendered = render_text(&board, &options);
+        let lines: Vec<&str> = rendered.lines().collect();
+
+        assert_eq!(lines[0], "8  \u{265C}  \u{265E}  \u{265D}  \u{265B}  \u{265A}  \u{265D}  \u{265E}  \u{265C}");
+        assert_eq!(lines[4], "4  \u{00B7}  \u{00B7}  \u{00B7}  \u{00B7}  \u{00B7}  \u{00B7}  \u{00B7}  \u{00B7}");
+        assert_eq!(lines[6], "2  \u{2659}  \u{2659}  \u{2659}  \u{2659}  \u{2659}  \u{2659}  \u{2659}  \u{2659}");
+    }
+
+    #[test]
+    fn highlighted_move() {
+        let board = Board::build_starting().unwrap();
+        let mut options = RenderOptions::new();
+        options.highlight_move(&Move::from_uci("e2e4").unwrap());
+        let rendered = render_text(&board, &options);
+        let lines: Vec<&str> = rendered.lines().collect();
+
+        assert_eq!(lines[4], "4  .  .  .  . [.] .  .  .");
+        assert_eq!(lines[6], "2  P  P  P  P [P] P  P  P");
+    }
+}
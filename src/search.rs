@@ -0,0 +1,1453 @@
+use crate::board::Board;
+use crate::chess_move::Move;
+use crate::eval::Evaluator;
+use crate::nnue::Network;
+use crate::piece::PieceType;
+use crate::syzygy::Tablebase;
+use crate::syzygy::TablebaseMove;
+use crate::syzygy::Wdl;
+use crate::move_picker::Heuristics;
+use crate::move_picker::MovePicker;
+use crate::time_manager::TimeManager;
+use crate::tt;
+use crate::tt::Bound;
+use crate::tt::TranspositionTable;
+
+use std::cmp::Reverse;
+use std::sync::Arc;
+use std::sync::OnceLock;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::thread;
+use std::time::Duration;
+
+pub const MAX_PLY: usize = 128;
+pub const MATE_SCORE: i32 = 32000;
+pub const INFINITY: i32 = MATE_SCORE + 1;
+// Scores at least this far from zero are mates found by the search
+pub const MATE_BOUND: i32 = MATE_SCORE - MAX_PLY as i32;
+// A tablebase win ranks below any mate the search can see
+pub const TB_WIN_SCORE: i32 = MATE_BOUND - 1;
+// Allowance for positional gains when deciding a capture cannot raise alpha
+const DELTA_MARGIN: i32 = 200;
+// Per ply of remaining depth, how far the static evaluation may be off
+// before a quiet move (futility) or the whole node (reverse futility) is
+// considered hopeless
+const FUTILITY_MARGIN: i32 = 150;
+const REVERSE_FUTILITY_MARGIN: i32 = 120;
+const RAZOR_MARGIN: i32 = 300;
+const FUTILITY_DEPTH: u32 = 3;
+const REVERSE_FUTILITY_DEPTH: u32 = 6;
+const RAZOR_DEPTH: u32 = 2;
+const NULL_MOVE_DEPTH: u32 = 3;
+// From this depth a null-move cutoff is confirmed by a reduced search
+// without null moves, in case the position is zugzwang
+const NULL_MOVE_VERIFY_DEPTH: u32 = 10;
+const LMR_DEPTH: u32 = 3;
+// Iterations from this depth on start with a window this far either side
+// of the previous score, doubling it on each fail high or fail low
+const ASPIRATION_DEPTH: u32 = 4;
+const ASPIRATION_WINDOW: i32 = 25;
+// Helper threads skip some iterations, each on its own pattern, so that
+// at any time the threads are spread over neighbouring depths rather than
+// all searching the same one
+const SKIP_SIZE: [u32; 20] = [1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 3, 3, 4, 4, 4, 4, 4, 4, 4, 4];
+const SKIP_PHASE: [u32; 20] = [0, 1, 0, 1, 2, 3, 0, 1, 2, 3, 4, 5, 0, 1, 2, 3, 4, 5, 6, 7];
+
+pub fn is_mate_score(score: i32) -> bool {
+    score.abs() >= MATE_BOUND
+}
+
+// Full moves to mate, positive if the side to move mates and negative if
+// it gets mated. None for scores that are not mates.
+pub fn mate_in(score: i32) -> Option<i32> {
+    if !is_mate_score(score) {
+        None
+    } else if score > 0 {
+        Some((MATE_SCORE - score + 1) / 2)
+    } else {
+        Some(-(MATE_SCORE + score) / 2)
+    }
+}
+
+// Limits on a search. With none of depth, nodes, movetime, mate or a clock
+// set the search runs until stopped.
+#[derive(Debug, Clone)]
+pub struct SearchLimits {
+    pub depth: Option<u32>,
+    pub nodes: Option<u64>,
+    pub movetime: Option<Duration>,
+    // Stop once a mate in at most this many moves is found
+    pub mate: Option<u32>,
+    // Clock of the side to move
+    pub time_left: Option<Duration>,
+    pub increment: Duration,
+    pub moves_to_go: Option<u32>,
+    // Search the position after the predicted reply while the opponent
+    // thinks. The time limits only apply once the ponderhit flag is set;
+    // a miss is handled by setting the stop flag and searching again.
+    pub ponder: bool,
+    // Searched until stopped. Nothing changes in the search itself; the
+    // front end holds the result until the stop comes.
+    pub infinite: bool,
+    // Only these root moves are searched, every move when empty
+    pub search_moves: Vec<Move>,
+}
+
+impl SearchLimits {
+    pub fn new() -> SearchLimits {
+        SearchLimits{
+            depth: None,
+            nodes: None,
+            movetime: None,
+            mate: None,
+            time_left: None,
+            increment: Duration::ZERO,
+            moves_to_go: None,
+            ponder: false,
+            infinite: false,
+            search_moves: Vec::new(),
+        }
+    }
+
+    pub fn depth(depth: u32) -> SearchLimits {
+        SearchLimits{depth: Some(depth), ..SearchLimits::new()}
+    }
+}
+
+impl Default for SearchLimits {
+    fn default() -> SearchLimits {
+        SearchLimits::new()
+    }
+}
+
+// Search settings: switches for the selective parts of the search, the
+// number of lines to report and the number of threads
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EngineOptions {
+    // Threads searching together through the shared transposition table,
+    // counting the one that reports
+    pub threads: usize,
+    // Search the best multi_pv root moves each to an exact score rather
+    // than just the best one
+    pub multi_pv: usize,
+    pub null_move_pruning: bool,
+    pub late_move_reductions: bool,
+    pub futility_pruning: bool,
+    pub reverse_futility_pruning: bool,
+    pub razoring: bool,
+    pub check_extensions: bool,
+}
+
+impl EngineOptions {
+    pub fn new() -> EngineOptions {
+        EngineOptions{
+            threads: 1,
+            multi_pv: 1,
+            null_move_pruning: true,
+            late_move_reductions: true,
+            futility_pruning: true,
+            reverse_futility_pruning: true,
+            razoring: true,
+            check_extensions: true,
+        }
+    }
+
+    // A plain alpha-beta search of every move to full depth
+    pub fn exhaustive() -> EngineOptions {
+        EngineOptions{
+            threads: 1,
+            multi_pv: 1,
+            null_move_pruning: false,
+            late_move_reductions: false,
+            futility_pruning: false,
+            reverse_futility_pruning: false,
+            razoring: false,
+            check_extensions: false,
+        }
+    }
+
+    // The switches by the names they go by as engine options, matched
+    // without regard to case
+    pub fn switch_mut(&mut self, name: &str) -> Option<&mut bool> {
+        match name.to_lowercase().as_str() {
+            "nullmove" => Some(&mut self.null_move_pruning),
+            "lmr" => Some(&mut self.late_move_reductions),
+            "futility" => Some(&mut self.futility_pruning),
+            "reversefutility" => Some(&mut self.reverse_futility_pruning),
+            "razoring" => Some(&mut self.razoring),
+            "checkextensions" => Some(&mut self.check_extensions),
+            _ => None
+        }
+    }
+}
+
+// Names of the EngineOptions switches as engine options
+pub const SWITCHES: [&str; 6] = ["NullMove", "LMR", "Futility", "ReverseFutility", "Razoring", "CheckExtensions"];
+
+impl Default for EngineOptions {
+    fn default() -> EngineOptions {
+        EngineOptions::new()
+    }
+}
+
+// One line of analysis: a root move, its score and the play expected after it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PvLine {
+    pub score: i32,
+    pub pv: Vec<Move>,
+    pub bound: Bound,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchResult {
+    // None when the side to move has no legal moves
+    pub best_move: Option<Move>,
+    // Centipawns from the side to move's point of view, or a mate score
+    pub score: i32,
+    // Expected line of play starting with best_move
+    pub pv: Vec<Move>,
+    // Deepest completed iteration
+    pub depth: u32,
+    pub nodes: u64,
+    pub time: Duration,
+    // Permille of the transposition table in use
+    pub hashfull: u32,
+    // Lower after a fail high and Upper after a fail low of an aspiration
+    // window, when score is only a bound and the iteration is searched again
+    pub bound: Bound,
+    // The best multi_pv lines, best first; the first is the one above.
+    // While an iteration is in progress only the lines searched so far.
+    pub lines: Vec<PvLine>,
+}
+
+pub struct Searcher {
+    tablebase: Option<Arc<Tablebase>>,
+    stop: Arc<AtomicBool>,
+    ponderhit: Arc<AtomicBool>,
+    // Set once a limit or the stop flag interrupts the current search
+    stopped: bool,
+    nodes: u64,
+    max_nodes: Option<u64>,
+    time: TimeManager,
+    tt: Arc<TranspositionTable>,
+    heuristics: Heuristics,
+    evaluator: Evaluator,
+    // Evaluates with this network instead of the classical evaluation when set
+    network: Option<Arc<Network>>,
+    options: EngineOptions,
+    // Null moves are not tried before this ply while verifying a null-move cutoff
+    null_move_min_ply: usize,
+    // 0 for the searcher that reports, from 1 for its helper threads
+    thread_index: usize,
+    // Nodes searched so far by the helper threads, added in batches
+    helper_nodes: Arc<AtomicU64>,
+}
+
+impl Default for Searcher {
+    fn default() -> Searcher {
+        Searcher::new()
+    }
+}
+
+impl Searcher {
+    pub fn new() -> Searcher {
+        Searcher{
+            tablebase: None,
+            stop: Arc::new(AtomicBool::new(false)),
+            ponderhit: Arc::new(AtomicBool::new(false)),
+            stopped: false,
+            nodes: 0,
+            max_nodes: None,
+            time: TimeManager::new(&SearchLimits::new()),
+            tt: Arc::new(TranspositionTable::default()),
+            heuristics: Heuristics::new(),
+            evaluator: Evaluator::new(),
+            network: None,
+            options: EngineOptions::new(),
+            null_move_min_ply: 0,
+            thread_index: 0,
+            helper_nodes: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    // A searcher for a helper thread, sharing the table and node count but
+    // stopped through its own flag. Helpers only look for the best move.
+    fn helper(&self, thread_index: usize, stop: &Arc<AtomicBool>) -> Searcher {
+        Searcher{
+            tablebase: self.tablebase.clone(),
+            stop: Arc::clone(stop),
+            ponderhit: Arc::new(AtomicBool::new(false)),
+            stopped: false,
+            nodes: 0,
+            max_nodes: None,
+            time: TimeManager::new(&SearchLimits::new()),
+            tt: Arc::clone(&self.tt),
+            heuristics: Heuristics::new(),
+            evaluator: Evaluator::new(),
+            network: self.network.clone(),
+            options: EngineOptions{multi_pv: 1, threads: 1, ..self.options.clone()},
+            null_move_min_ply: 0,
+            thread_index,
+            helper_nodes: Arc::clone(&self.helper_nodes),
+        }
+    }
+
+    pub fn set_options(&mut self, options: EngineOptions) {
+        self.options = options;
+    }
+
+    pub fn options(&self) -> &EngineOptions {
+        &self.options
+    }
+
+    // Resize the transposition table, which also empties it
+    pub fn set_hash_size(&mut self, mb: usize) {
+        self.tt = Arc::new(TranspositionTable::new(mb));
+    }
+
+    // Forget everything learned in earlier searches, e.g. for a new game
+    pub fn clear_hash(&mut self) {
+        self.tt.clear();
+        self.heuristics.clear();
+    }
+
+    pub fn hashfull(&self) -> u32 {
+        self.tt.hashfull()
+    }
+
+    pub fn set_network(&mut self, network: Option<Arc<Network>>) {
+        self.network = network;
+    }
+
+    // Tables used to pick root moves in positions they cover
+    pub fn set_tablebase(&mut self, tablebase: Option<Arc<Tablebase>>) {
+        self.tablebase = tablebase;
+    }
+
+    // Setting the flag from another thread ends a running search, which then
+    // returns the result of its last completed iteration
+    pub fn stop_flag(&self) -> Arc<AtomicBool> {
+        Arc::clone(&self.stop)
+    }
+
+    // Setting the flag during a ponder search means the predicted move was
+    // played; the search goes on as a timed one
+    pub fn ponderhit_flag(&self) -> Arc<AtomicBool> {
+        Arc::clone(&self.ponderhit)
+    }
+
+    pub fn search(&mut self, board: &mut Board, limits: &SearchLimits) -> SearchResult {
+        self.search_with_info(board, limits, |_| {})
+    }
+
+    // Iterative deepening: search depth 1, 2, ... until a limit is reached,
+    // calling info with the result of every completed iteration and of
+    // every aspiration window that failed. With more than one thread the
+    // helpers search the same position until this thread is done (Lazy
+    // SMP), sharing what they find through the transposition table.
+    pub fn search_with_info<F: FnMut(&SearchResult)>(&mut self, board: &mut Board, limits: &SearchLimits, info: F) -> SearchResult {
+        // The board keeps the network's accumulator for this search only
+        if self.network.is_none() {
+            return self.search_threads(board, limits, info);
+        }
+        board.set_network(self.network.clone());
+        let result = self.search_threads(board, limits, info);
+        board.set_network(None);
+        result
+    }
+
+    fn search_threads<F: FnMut(&SearchResult)>(&mut self, board: &mut Board, limits: &SearchLimits, info: F) -> SearchResult {
+        self.stop.store(false, Ordering::Relaxed);
+        self.ponderhit.store(false, Ordering::Relaxed);
+        self.tt.new_search();
+        self.helper_nodes.store(0, Ordering::Relaxed);
+        if self.options.threads <= 1 {
+            return self.iterate(board, limits, info);
+        }
+
+        let helper_stop = Arc::new(AtomicBool::new(false));
+        let helper_limits = SearchLimits{depth: limits.depth, mate: limits.mate, ..SearchLimits::new()};
+        let helpers: Vec<(Searcher, Board)> = (1..self.options.threads)
+            .map(|thread_index| (self.helper(thread_index, &helper_stop), board.clone()))
+            .collect();
+        thread::scope(|scope| {
+            let handles: Vec<_> = helpers.into_iter()
+                .map(|(mut helper, mut board)| {
+                    let limits = &helper_limits;
+                    scope.spawn(move || helper.iterate(&mut board, limits, |_| {}))
+                })
+                .collect();
+            let mut result = self.iterate(board, limits, info);
+            helper_stop.store(true, Ordering::Relaxed);
+
+            let mut nodes = self.nodes;
+            for handle in handles {
+                let helper_result = handle.join().expect("search thread panicked");
+                nodes += helper_result.nodes;
+                // A helper that completed a deeper iteration knows better
+                if helper_result.depth > result.depth && self.options.multi_pv == 1 {
+                    result = SearchResult{time: result.time, hashfull: result.hashfull, ..helper_result};
+                }
+            }
+            result.nodes = nodes;
+            result
+        })
+    }
+
+    // The iterative deepening loop of one thread
+    fn iterate<F: FnMut(&SearchResult)>(&mut self, board: &mut Board, limits: &SearchLimits, mut info: F) -> SearchResult {
+        self.stopped = false;
+        self.nodes = 0;
+        self.max_nodes = limits.nodes;
+        self.time = TimeManager::new(limits);
+        self.heuristics.new_search();
+        self.null_move_min_ply = 0;
+
+        let (mut root_moves, tb_score) = self.root_moves(board, &limits.search_moves);
+        let mut result = SearchResult{
+            best_move: root_moves.first().copied(),
+            score: if root_moves.is_empty() && board.in_check() { -MATE_SCORE } else { 0 },
+            pv: root_moves.first().copied().into_iter().collect(),
+            depth: 0,
+            nodes: 0,
+            time: Duration::ZERO,
+            hashfull: 0,
+            bound: Bound::Exact,
+            lines: Vec::new(),
+        };
+        if root_moves.is_empty() {
+            return result;
+        }
+
+        let multi_pv = self.options.multi_pv.clamp(1, root_moves.len());
+        let max_depth = limits.depth.unwrap_or(MAX_PLY as u32 - 1).clamp(1, MAX_PLY as u32 - 1);
+        for depth in 1..=max_depth {
+            if self.skips_depth(depth) {
+                continue;
+            }
+            // Each line searches the root moves not already heading an
+            // earlier line, which are kept at the front of root_moves
+            let mut lines: Vec<PvLine> = Vec::with_capacity(multi_pv);
+            for pv_index in 0..multi_pv {
+                let (score, mut pv) = self.aspiration_search(board, &mut root_moves, depth, &result, &lines, &mut info);
+                if self.stopped {
+                    break;
+                }
+                self.extend_pv(board, &mut pv, depth as usize);
+                move_to(&mut root_moves, pv[0], pv_index);
+                let score = match tb_score {
+                    Some(tb_score) if !is_mate_score(score) => tb_score,
+                    _ => score
+                };
+                lines.push(PvLine{score, pv, bound: Bound::Exact});
+            }
+            if self.stopped {
+                break;
+            }
+
+            // An unstable search can score a later line higher; rank them
+            // again and search them in that order next iteration
+            lines.sort_by_key(|line| Reverse(line.score));
+            for (index, line) in lines.iter().enumerate() {
+                move_to(&mut root_moves, line.pv[0], index);
+            }
+            let score = lines[0].score;
+            result = self.report(depth, lines);
+            info(&result);
+
+            let mate_found = limits.mate.is_some_and(|moves| {
+                mate_in(score).is_some_and(|mate| mate > 0 && mate <= moves as i32)
+            });
+            self.check_ponderhit();
+            if mate_found || !self.time.can_start_iteration() || self.stop.load(Ordering::Relaxed) {
+                break;
+            }
+        }
+        result.nodes = self.nodes;
+        result.time = self.time.elapsed();
+        result.hashfull = self.tt.hashfull();
+        result
+    }
+
+    // Search the root moves not heading one of the lines found so far, with
+    // a window around the score this line had in the previous iteration,
+    // widening it on whichever side the score falls outside and reporting
+    // each fail
+    fn aspiration_search<F: FnMut(&SearchResult)>(&mut self, board: &mut Board, root_moves: &mut [Move], depth: u32,
+            previous: &SearchResult, lines: &[PvLine], info: &mut F) -> (i32, Vec<Move>) {
+        let pv_index = lines.len();
+        let previous_line = previous.lines.get(pv_index);
+        let mut delta = ASPIRATION_WINDOW;
+        let (mut alpha, mut beta) = match previous_line {
+            Some(line) if depth >= ASPIRATION_DEPTH && !is_mate_score(line.score) => (line.score - delta, line.score + delta),
+            _ => (-INFINITY, INFINITY)
+        };
+        loop {
+            let (score, pv) = self.search_root(board, &root_moves[pv_index..], depth, alpha, beta);
+            if self.stopped {
+                return (score, pv);
+            }
+            let bound = if score <= alpha {
+                Bound::Upper
+            } else if score >= beta {
+                Bound::Lower
+            } else {
+                return (score, pv);
+            };
+
+            delta *= 2;
+            if bound == Bound::Upper {
+                alpha = (score - delta).max(-INFINITY);
+            } else {
+                beta = (score + delta).min(INFINITY);
+                // Search the move that failed high first
+                move_to(root_moves, pv[0], pv_index);
+            }
+            // A fail low finds no line, so show the one from before
+            let pv = match previous_line {
+                Some(line) if pv.is_empty() => line.pv.clone(),
+                _ if pv.is_empty() => vec![root_moves[pv_index]],
+                _ => pv
+            };
+            let mut shown = lines.to_vec();
+            shown.push(PvLine{score, pv, bound});
+            info(&self.report(depth, shown));
+        }
+    }
+
+    // A result whose best move, score and bound are those of the first line
+    fn report(&self, depth: u32, lines: Vec<PvLine>) -> SearchResult {
+        SearchResult{
+            best_move: lines[0].pv.first().copied(),
+            score: lines[0].score,
+            pv: lines[0].pv.clone(),
+            depth,
+            nodes: self.total_nodes(),
+            time: self.time.elapsed(),
+            hashfull: self.tt.hashfull(),
+            bound: lines[0].bound,
+            lines,
+        }
+    }
+
+    // A cutoff on a table entry leaves the line it came from short, so
+    // follow the stored best moves until the line is as long as the search
+    fn extend_pv(&self, board: &mut Board, pv: &mut Vec<Move>, depth: usize) {
+        for mv in pv.iter() {
+            board.make_move(*mv);
+        }
+        let mut played = pv.len();
+        while pv.len() < depth {
+            let mv = match self.tt.probe(board.zobrist_key()).and_then(|entry| entry.best_move) {
+                Some(mv) if board.legal_moves().contains(&mv) => mv,
+                _ => break
+            };
+            board.make_move(mv);
+            played += 1;
+            pv.push(mv);
+            if board.is_repetition() {
+                break;
+            }
+        }
+        for _ in 0..played {
+            board.unmake_move();
+        }
+    }
+
+    // Principal variation search over the root moves: the first move gets
+    // the full window, the rest only need to prove they are no better,
+    // which a null window does cheaply. Returns a fail-soft score and an
+    // empty line when no move reached alpha.
+    fn search_root(&mut self, board: &mut Board, root_moves: &[Move], depth: u32, alpha: i32, beta: i32) -> (i32, Vec<Move>) {
+        let mut alpha = alpha;
+        let mut best = -INFINITY;
+        let mut pv = Vec::new();
+        for (index, mv) in root_moves.iter().enumerate() {
+            board.make_move(*mv);
+            let extension = if self.options.check_extensions && board.in_check() { 1 } else { 0 };
+            let new_depth = depth - 1 + extension;
+            let mut child_pv = Vec::new();
+            let mut score = if index == 0 {
+                -self.negamax(board, new_depth, -beta, -alpha, 1, &mut child_pv)
+            } else {
+                -self.negamax(board, new_depth, -alpha - 1, -alpha, 1, &mut child_pv)
+            };
+            if index > 0 && score > alpha && score < beta {
+                score = -self.negamax(board, new_depth, -beta, -alpha, 1, &mut child_pv);
+            }
+            board.unmake_move();
+            if self.stopped {
+                break;
+            }
+            if score > best {
+                best = score;
+                if score > alpha {
+                    alpha = score;
+                    pv = vec![*mv];
+                    pv.append(&mut child_pv);
+                    if alpha >= beta {
+                        break;
+                    }
+                }
+            }
+        }
+        (best, pv)
+    }
+
+    fn skips_depth(&self, depth: u32) -> bool {
+        if self.thread_index == 0 {
+            return false;
+        }
+        let pattern = (self.thread_index - 1) % SKIP_SIZE.len();
+        ((depth + SKIP_PHASE[pattern]) / SKIP_SIZE[pattern]) % 2 == 1
+    }
+
+    fn check_ponderhit(&mut self) {
+        if self.time.is_pondering() && self.ponderhit.load(Ordering::Relaxed) {
+            self.time.ponderhit();
+        }
+    }
+
+    // The reply expected to the best move, to ponder on while the opponent
+    // thinks: the second move of the line, or failing that the table's
+    // best move after the first
+    pub fn ponder_move(&self, board: &mut Board, result: &SearchResult) -> Option<Move> {
+        if let Some(mv) = result.pv.get(1) {
+            return Some(*mv);
+        }
+        board.make_move(result.best_move?);
+        let mv = self.tt.probe(board.zobrist_key())
+            .and_then(|entry| entry.best_move)
+            .filter(|mv| board.legal_moves().contains(mv));
+        board.unmake_move();
+        mv
+    }
+
+    // Nodes searched by this thread and, as far as they have told, its helpers
+    fn total_nodes(&self) -> u64 {
+        self.nodes + self.helper_nodes.load(Ordering::Relaxed)
+    }
+
+    // Check the stop flag and limits, polling the clock every so often
+    fn should_stop(&mut self) -> bool {
+        let node_limit = self.max_nodes.is_some_and(|max_nodes| self.total_nodes() >= max_nodes);
+        let poll = self.nodes.is_multiple_of(1024);
+        if poll && self.thread_index > 0 {
+            self.helper_nodes.fetch_add(1024, Ordering::Relaxed);
+        }
+        if poll {
+            self.check_ponderhit();
+        }
+        if node_limit || (poll && (self.stop.load(Ordering::Relaxed) || self.time.out_of_time())) {
+            self.stopped = true;
+        }
+        self.stopped
+    }
+
+    // Legal root moves, narrowed down by the tablebase when it covers the
+    // position. Winning moves all share the best rank, so among them take
+    // the quickest to convert; when losing take the slowest. Also returns
+    // the tablebase's verdict as a score.
+    fn root_moves(&self, board: &mut Board, search_moves: &[Move]) -> (Vec<Move>, Option<i32>) {
+        let allowed = |mv: &Move| search_moves.is_empty() || search_moves.contains(mv);
+        // The best ranked of the moves allowed
+        let mut tb_moves: Vec<TablebaseMove> = match &self.tablebase {
+            Some(tablebase) => board.tablebase_root_moves(tablebase).unwrap_or_default(),
+            None => Vec::new()
+        };
+        tb_moves.retain(|tb_move| allowed(&tb_move.mv));
+        if let Some(best) = tb_moves.first().map(|tb_move| tb_move.rank) {
+            tb_moves.retain(|tb_move| tb_move.rank == best);
+        } else {
+            return (board.legal_moves().into_iter().filter(allowed).collect(), None);
+        }
+
+        let wdl = tb_moves[0].wdl;
+        let min_dtz = tb_moves.iter().map(|tb_move| tb_move.dtz).min();
+        let moves = tb_moves.iter()
+            .filter(|tb_move| wdl == Wdl::Draw || Some(tb_move.dtz) == min_dtz)
+            .map(|tb_move| tb_move.mv)
+            .collect();
+        let score = match wdl {
+            Wdl::Win => TB_WIN_SCORE,
+            Wdl::Loss => -TB_WIN_SCORE,
+            _ => 0
+        };
+        (moves, Some(score))
+    }
+
+    // Fail-soft alpha-beta from the side to move's point of view. Mates are
+    // scored by their distance from the root so shorter mates score higher.
+    fn negamax(&mut self, board: &mut Board, depth: u32, alpha: i32, beta: i32, ply: usize, pv: &mut Vec<Move>) -> i32 {
+        pv.clear();
+        if depth == 0 {
+            return self.quiescence(board, alpha, beta, ply);
+        }
+        self.nodes += 1;
+        if self.stopped || self.should_stop() {
+            return 0;
+        }
+        if board.halfmove_clock() >= 100 || board.is_repetition() {
+            return 0;
+        }
+        if ply >= MAX_PLY {
+            return self.evaluator.evaluate(board);
+        }
+
+        let key = board.zobrist_key();
+        let tt_entry = self.tt.probe(key);
+        if let Some(entry) = tt_entry {
+            if u32::from(entry.depth) >= depth {
+                let score = tt::score_from_tt(entry.score, ply);
+                let cutoff = match entry.bound {
+                    Bound::Exact => true,
+                    Bound::Lower => score >= beta,
+                    Bound::Upper => score <= alpha
+                };
+                if cutoff {
+                    return score;
+                }
+            }
+        }
+
+        // Nodes searched with a null window only need to prove a bound, so
+        // they can be pruned more aggressively than those on the PV
+        let pv_node = beta - alpha > 1;
+        let in_check = board.in_check();
+        let static_eval = if in_check { -INFINITY } else { self.evaluator.evaluate(board) };
+
+        if !pv_node && !in_check {
+            // So far ahead that even a margin per ply cannot bring the score below beta
+            if self.options.reverse_futility_pruning && depth <= REVERSE_FUTILITY_DEPTH
+                && static_eval - REVERSE_FUTILITY_MARGIN * depth as i32 >= beta && static_eval < MATE_BOUND {
+                return static_eval;
+            }
+
+            // So far behind that only captures could help: let the
+            // quiescence search decide
+            if self.options.razoring && depth <= RAZOR_DEPTH && static_eval + RAZOR_MARGIN * depth as i32 <= alpha {
+                let score = self.quiescence(board, alpha, alpha + 1, ply);
+                if score <= alpha {
+                    return score;
+                }
+            }
+
+            // If passing the move still fails high, a real move almost
+            // certainly will. Not in pawn endings, where zugzwang is common.
+            if self.options.null_move_pruning && depth >= NULL_MOVE_DEPTH && static_eval >= beta
+                && ply >= self.null_move_min_ply && !board.after_null_move() && has_non_pawn_material(board) {
+                let reduction = 3 + depth / 4;
+                board.make_null_move();
+                let mut child_pv = Vec::new();
+                let score = -self.negamax(board, depth.saturating_sub(1 + reduction), -beta, -beta + 1, ply + 1, &mut child_pv);
+                board.unmake_null_move();
+                if self.stopped {
+                    return 0;
+                }
+                if score >= beta {
+                    // A mate found after passing is not a proven mate
+                    let score = if score >= MATE_BOUND { beta } else { score };
+                    // Verify only deep nodes, and only outside another
+                    // verification, whose ply limit must hold until it ends
+                    if depth < NULL_MOVE_VERIFY_DEPTH || self.null_move_min_ply != 0 {
+                        return score;
+                    }
+                    self.null_move_min_ply = ply + 3 * (depth - reduction) as usize / 4;
+                    let verified = self.negamax(board, depth - reduction, beta - 1, beta, ply, &mut child_pv);
+                    self.null_move_min_ply = 0;
+                    if verified >= beta {
+                        return score;
+                    }
+                }
+            }
+        }
+
+        let color = board.side_to_move();
+        let previous = board.last_move();
+        let tt_move = tt_entry.and_then(|entry| entry.best_move);
+        let mut picker = MovePicker::new(tt_move, self.heuristics.killers(ply), self.heuristics.countermove(previous));
+
+        let original_alpha = alpha;
+        let mut alpha = alpha;
+        let mut best = -INFINITY;
+        let mut best_move = None;
+        let mut legal_moves = 0;
+        let mut quiets_tried = Vec::new();
+        let mut child_pv = Vec::new();
+        while let Some(mv) = picker.next(board, &self.heuristics) {
+            let quiet = !board.is_capture(mv) && mv.promotion.is_none();
+            if !make_legal_move(board, mv) {
+                continue;
+            }
+            legal_moves += 1;
+            let gives_check = board.in_check();
+
+            // A quiet move this late cannot make up the gap to alpha
+            if self.options.futility_pruning && !pv_node && !in_check && !gives_check && quiet
+                && depth <= FUTILITY_DEPTH && best > -MATE_BOUND
+                && static_eval + FUTILITY_MARGIN * depth as i32 <= alpha {
+                board.unmake_move();
+                quiets_tried.push(mv);
+                continue;
+            }
+
+            let extension = if self.options.check_extensions && gives_check { 1 } else { 0 };
+            let new_depth = depth - 1 + extension;
+
+            // Search late quiet moves with reduced depth, and again at full
+            // depth only if they turn out to beat alpha
+            let mut reduction = 0;
+            if self.options.late_move_reductions && depth >= LMR_DEPTH && legal_moves > if pv_node { 3 } else { 1 }
+                && quiet && !in_check && !gives_check {
+                reduction = lmr_reduction(depth, legal_moves);
+                if pv_node {
+                    reduction = reduction.saturating_sub(1);
+                }
+                reduction = reduction.min(new_depth - 1);
+            }
+            // Principal variation search: after the first move, a null window
+            // is enough to show a move is no better, and only moves that
+            // beat alpha at a PV node are searched again with the full window
+            let mut score = if legal_moves == 1 {
+                -self.negamax(board, new_depth, -beta, -alpha, ply + 1, &mut child_pv)
+            } else {
+                let mut score = -self.negamax(board, new_depth - reduction, -alpha - 1, -alpha, ply + 1, &mut child_pv);
+                if score > alpha && reduction > 0 {
+                    score = -self.negamax(board, new_depth, -alpha - 1, -alpha, ply + 1, &mut child_pv);
+                }
+                score
+            };
+            if legal_moves > 1 && score > alpha && score < beta {
+                score = -self.negamax(board, new_depth, -beta, -alpha, ply + 1, &mut child_pv);
+            }
+            board.unmake_move();
+            if self.stopped {
+                return 0;
+            }
+            if score > best {
+                best = score;
+                if score > alpha {
+                    alpha = score;
+                    best_move = Some(mv);
+                    pv.clear();
+                    pv.push(mv);
+                    pv.extend_from_slice(&child_pv);
+                    if alpha >= beta {
+                        if quiet {
+                            self.heuristics.update(color, mv, previous, &quiets_tried, depth, ply);
+                        }
+                        break;
+                    }
+                }
+            }
+            if quiet {
+                quiets_tried.push(mv);
+            }
+        }
+        if legal_moves == 0 {
+            return if in_check { -MATE_SCORE + ply as i32 } else { 0 };
+        }
+
+        let bound = if best >= beta {
+            Bound::Lower
+        } else if best > original_alpha {
+            Bound::Exact
+        } else {
+            Bound::Upper
+        };
+        self.tt.store(key, best_move, tt::score_to_tt(best, ply), depth, bound);
+        best
+    }
+
+    // Search captures until the position is quiet so the evaluation is not
+    // taken in the middle of an exchange. The side to move may stand pat
+    // on the static evaluation instead of capturing, except when in check,
+    // where every evasion is searched.
+    fn quiescence(&mut self, board: &mut Board, alpha: i32, beta: i32, ply: usize) -> i32 {
+        self.nodes += 1;
+        if self.stopped || self.should_stop() {
+            return 0;
+        }
+        if board.halfmove_clock() >= 100 || board.is_repetition() {
+            return 0;
+        }
+        if ply >= MAX_PLY {
+            return self.evaluator.evaluate(board);
+        }
+
+        let in_check = board.in_check();
+        let mut alpha = alpha;
+        let mut best = -INFINITY;
+        let stand_pat = self.evaluator.evaluate(board);
+        if !in_check {
+            if stand_pat >= beta {
+                return stand_pat;
+            }
+            best = stand_pat;
+            alpha = alpha.max(stand_pat);
+        }
+
+        // Every evasion when in check, otherwise captures that do not lose
+        // material
+        let mut picker = if in_check {
+            MovePicker::new(None, [None; 2], None)
+        } else {
+            MovePicker::captures(None)
+        };
+        let mut legal_moves = 0;
+        while let Some(mv) = picker.next(board, &self.heuristics) {
+            if !in_check {
+                // Even winning the piece outright would leave us below alpha
+                if mv.promotion.is_none() && stand_pat + gain(board, mv) + DELTA_MARGIN <= alpha {
+                    continue;
+                }
+            }
+            if !make_legal_move(board, mv) {
+                continue;
+            }
+            legal_moves += 1;
+            let score = -self.quiescence(board, -beta, -alpha, ply + 1);
+            board.unmake_move();
+            if self.stopped {
+                return 0;
+            }
+            if score > best {
+                best = score;
+                if score > alpha {
+                    alpha = score;
+                    if alpha >= beta {
+                        break;
+                    }
+                }
+            }
+        }
+        if in_check && legal_moves == 0 {
+            return -MATE_SCORE + ply as i32;
+        }
+        best
+    }
+}
+
+// Move a root move to the given index, keeping the order of the others
+fn move_to(moves: &mut [Move], mv: Move, index: usize) {
+    if let Some(from) = moves.iter().position(|other| *other == mv) {
+        if from > index {
+            moves[index..=from].rotate_right(1);
+        }
+    }
+}
+
+// Late move reductions grow with the logarithm of both the depth and the
+// number of moves already searched
+fn lmr_reduction(depth: u32, move_number: usize) -> u32 {
+    static TABLE: OnceLock<Vec<[u32; 64]>> = OnceLock::new();
+    let table = TABLE.get_or_init(|| {
+        (0..64).map(|depth| {
+            let mut row = [0; 64];
+            for (move_number, reduction) in row.iter_mut().enumerate().skip(1) {
+                if depth > 0 {
+                    *reduction = (0.75 + (depth as f64).ln() * (move_number as f64).ln() / 2.25) as u32;
+                }
+            }
+            row
+        }).collect()
+    });
+    table[(depth as usize).min(63)][move_number.min(63)]
+}
+
+// Whether the side to move has anything besides pawns and the king
+fn has_non_pawn_material(board: &Board) -> bool {
+    let color = board.side_to_move();
+    board.pieces().filter_map(|square| square.piece).any(|piece| {
+        piece.get_color() == color && piece.piece_type() != PieceType::Pawn && piece.piece_type() != PieceType::King
+    })
+}
+
+// Play a pseudo-legal move, taking it back again if it leaves the mover's
+// king in check
+fn make_legal_move(board: &mut Board, mv: Move) -> bool {
+    let color = board.side_to_move();
+    board.make_move(mv);
+    if board.is_king_attacked(color) {
+        board.unmake_move();
+        return false;
+    }
+    true
+}
+
+// Material won by a move: the captured piece plus any promotion
+fn gain(board: &Board, mv: Move) -> i32 {
+    let captured = match board.piece_at(mv.to_file, mv.to_rank) {
+        Some(piece) => piece.piece_type().value(),
+        None if board.is_capture(mv) => PieceType::Pawn.value(),
+        None => 0
+    };
+    let promotion = mv.promotion.map_or(0, |piece_type| piece_type.value() - PieceType::Pawn.value());
+    captured + promotion
+}
+
+#[cfg(test)]
+mod search_tests {
+    use super::*;
+
+    fn search(fen: &str, depth: u32) -> SearchResult {
+        let mut board = Board::from_fen(fen).unwrap();
+        Searcher::new().search(&mut board, &SearchLimits::depth(depth))
+    }
+
+    // Search on another thread, returning once it has reached depth. The
+    // search clears its stop and ponderhit flags as it starts, so they are
+    // only set after that.
+    fn search_in_background(mut searcher: Searcher, mut board: Board, limits: SearchLimits, depth: u32) -> std::thread::JoinHandle<(Searcher, Board, SearchResult)> {
+        let (reached, running) = std::sync::mpsc::channel();
+        let handle = std::thread::spawn(move || {
+            let result = searcher.search_with_info(&mut board, &limits, |result| {
+                if result.depth >= depth {
+                    let _ = reached.send(());
+                }
+            });
+            (searcher, board, result)
+        });
+        running.recv().unwrap();
+        handle
+    }
+
+    #[test]
+    fn finds_mate_in_one() {
+        let result = search("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1", 2);
+        assert_eq!(result.best_move, Some(Move::from_uci("a1a8").unwrap()));
+        assert_eq!(result.score, MATE_SCORE - 1);
+        assert_eq!(mate_in(result.score), Some(1));
+    }
+
+    #[test]
+    fn prefers_the_shorter_mate() {
+        // Qh7+ Kf8 Qh8# mates in two, but Qg7# mates at once
+        let result = search("6k1/8/5KQ1/8/8/8/8/8 w - - 0 1", 3);
+        assert_eq!(mate_in(result.score), Some(1));
+        assert_eq!(result.pv.len(), 1);
+    }
+
+    #[test]
+    fn sees_being_mated() {
+        let result = search("R5k1/5ppp/8/8/8/8/8/6K1 b - - 0 1", 2);
+        assert_eq!(result.best_move, None);
+        assert_eq!(result.score, -MATE_SCORE);
+        assert_eq!(mate_in(result.score), Some(0));
+    }
+
+    #[test]
+    fn stalemate_scores_zero() {
+        let result = search("7k/5Q2/6K1/8/8/8/8/8 b - - 0 1", 2);
+        assert_eq!(result.best_move, None);
+        assert_eq!(result.score, 0);
+    }
+
+    #[test]
+    fn wins_hanging_material() {
+        let result = search("4k3/8/8/3q4/8/8/8/3RK3 w - - 0 1", 2);
+        assert_eq!(result.best_move, Some(Move::from_uci("d1d5").unwrap()));
+        // A rook up, give or take where the pieces stand
+        assert!(result.score > 400 && result.score < 600);
+    }
+
+    #[test]
+    fn principal_variation_is_playable() {
+        let mut board = Board::build_starting().unwrap();
+        let result = Searcher::new().search(&mut board, &SearchLimits::depth(3));
+        assert_eq!(result.pv.len(), 3);
+        assert!(result.nodes > 20);
+        for mv in result.pv {
+            board.play(mv).unwrap();
+        }
+    }
+
+    #[test]
+    fn mate_scores() {
+        assert!(is_mate_score(MATE_SCORE - 5));
+        assert!(!is_mate_score(TB_WIN_SCORE));
+        assert_eq!(mate_in(MATE_SCORE - 3), Some(2));
+        assert_eq!(mate_in(-MATE_SCORE + 4), Some(-2));
+        assert_eq!(mate_in(150), None);
+    }
+
+    #[test]
+    fn deepens_one_iteration_at_a_time() {
+        let mut board = Board::build_starting().unwrap();
+        let mut depths = Vec::new();
+        let result = Searcher::new().search_with_info(&mut board, &SearchLimits::depth(3), |info| depths.push(info.depth));
+        assert_eq!(depths, vec![1, 2, 3]);
+        assert_eq!(result.depth, 3);
+        assert_eq!(result.pv.len(), 3);
+    }
+
+    #[test]
+    fn node_limit_keeps_the_last_completed_iteration() {
+        let mut board = Board::build_starting().unwrap();
+        let mut limits = SearchLimits::new();
+        limits.nodes = Some(500);
+        let result = Searcher::new().search(&mut board, &limits);
+        assert!(result.depth >= 1 && result.depth < 5);
+        assert!(result.best_move.is_some());
+        assert_eq!(result.nodes, 500);
+    }
+
+    #[test]
+    fn stops_on_mate_limit_and_movetime() {
+        let mut board = Board::from_fen("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1").unwrap();
+        let mut limits = SearchLimits::new();
+        limits.mate = Some(1);
+        let result = Searcher::new().search(&mut board, &limits);
+        assert_eq!(mate_in(result.score), Some(1));
+        assert!(result.depth <= 2);
+
+        let mut limits = SearchLimits::new();
+        limits.movetime = Some(Duration::from_millis(50));
+        let mut board = Board::build_starting().unwrap();
+        let result = Searcher::new().search(&mut board, &limits);
+        assert!(result.best_move.is_some());
+        assert!(result.time < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn stop_flag_ends_an_infinite_search() {
+        let searcher = Searcher::new();
+        let stop = searcher.stop_flag();
+        let handle = search_in_background(searcher, Board::build_starting().unwrap(), SearchLimits::new(), 1);
+        stop.store(true, Ordering::Relaxed);
+        let (_, _, result) = handle.join().unwrap();
+        assert!(result.best_move.is_some());
+        assert!(result.depth >= 1);
+    }
+
+    #[test]
+    fn transposition_table_carries_over_between_searches() {
+        let mut board = Board::from_fen("r1bqkbnr/pppp1ppp/2n5/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 2 3").unwrap();
+        let mut searcher = Searcher::new();
+        searcher.set_hash_size(1);
+        let first = searcher.search(&mut board, &SearchLimits::depth(4));
+        assert!(first.hashfull > 0);
+        let second = searcher.search(&mut board, &SearchLimits::depth(4));
+        assert!(second.nodes < first.nodes);
+        assert_eq!(second.score, first.score);
+        assert_eq!(second.pv.len(), 4);
+
+        searcher.clear_hash();
+        assert_eq!(searcher.hashfull(), 0);
+        let cleared = searcher.search(&mut board, &SearchLimits::depth(4));
+        assert_eq!(cleared.nodes, first.nodes);
+    }
+
+    #[test]
+    fn repetition_is_a_draw() {
+        // Down a queen, black's only hope is to repeat the position
+        let mut board = Board::from_fen("4k3/8/8/8/8/8/8/QK6 b - - 0 1").unwrap();
+        for uci in ["e8d8", "a1a2", "d8e8", "a2a1"].iter() {
+            board.play(Move::from_uci(uci).unwrap()).unwrap();
+        }
+        let result = Searcher::new().search(&mut board, &SearchLimits::depth(3));
+        assert_eq!(result.best_move, Some(Move::from_uci("e8d8").unwrap()));
+        assert_eq!(result.score, 0);
+    }
+
+    #[test]
+    fn quiescence_sees_the_recapture() {
+        // At depth one Qxd5 wins a knight, until exd5 takes the queen back
+        let result = search("4k3/8/4p3/3n4/8/8/8/3QK3 w - - 0 1", 1);
+        assert_ne!(result.best_move, Some(Move::from_uci("d1d5").unwrap()));
+        let defended = result.score;
+        assert!(defended > 400);
+
+        // Undefended, the knight is simply won
+        let result = search("4k3/8/8/3n4/8/8/8/3QK3 w - - 0 1", 1);
+        assert!(result.score > defended + 200);
+    }
+
+    #[test]
+    fn quiescence_searches_check_evasions() {
+        // No standing pat on -500 while in check: the king takes the rook
+        let mut searcher = Searcher::new();
+        let mut board = Board::from_fen("4k3/8/8/8/8/8/4r3/4K3 w - - 0 1").unwrap();
+        let score = searcher.quiescence(&mut board, -INFINITY, INFINITY, 0);
+        board.make_move(Move::from_uci("e1e2").unwrap());
+        assert_eq!(score, -crate::eval::evaluate(&board));
+
+        // In check with no way out is mate even at the horizon
+        let mut board = Board::from_fen("R5k1/5ppp/8/8/8/8/8/6K1 b - - 0 1").unwrap();
+        let score = searcher.quiescence(&mut board, -INFINITY, INFINITY, 3);
+        assert_eq!(score, -MATE_SCORE + 3);
+    }
+
+    #[test]
+    fn selectivity_searches_fewer_nodes() {
+        let fen = "r1bqkb1r/pppp1ppp/2n2n2/4p3/2B1P3/5N2/PPPP1PPP/RNBQK2R w KQkq - 4 4";
+        let mut board = Board::from_fen(fen).unwrap();
+        let mut searcher = Searcher::new();
+        searcher.set_options(EngineOptions::exhaustive());
+        let exhaustive = searcher.search(&mut board, &SearchLimits::depth(5));
+        let mut searcher = Searcher::new();
+        assert_eq!(*searcher.options(), EngineOptions::new());
+        let selective = searcher.search(&mut board, &SearchLimits::depth(5));
+        assert!(selective.nodes < exhaustive.nodes, "{} vs {}", selective.nodes, exhaustive.nodes);
+        assert_eq!(board.to_fen(), fen);
+    }
+
+    #[test]
+    fn each_option_alone_keeps_the_tactics() {
+        let enable: [fn(&mut EngineOptions); 6] = [
+            |options| options.null_move_pruning = true,
+            |options| options.late_move_reductions = true,
+            |options| options.futility_pruning = true,
+            |options| options.reverse_futility_pruning = true,
+            |options| options.razoring = true,
+            |options| options.check_extensions = true,
+        ];
+        for set in enable.iter() {
+            let mut options = EngineOptions::exhaustive();
+            set(&mut options);
+            let run = |fen: &str, depth: u32| {
+                let mut board = Board::from_fen(fen).unwrap();
+                let mut searcher = Searcher::new();
+                searcher.set_options(options.clone());
+                searcher.search(&mut board, &SearchLimits::depth(depth))
+            };
+            let result = run("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1", 4);
+            assert_eq!(result.score, MATE_SCORE - 1, "{:?}", options);
+            let result = run("4k3/8/8/3q4/8/8/8/3RK3 w - - 0 1", 4);
+            assert_eq!(result.best_move, Some(Move::from_uci("d1d5").unwrap()), "{:?}", options);
+        }
+    }
+
+    #[test]
+    fn check_extensions_see_deeper_mates() {
+        // Qh7+ Kf8 Qh8# takes three plies; with the check extended a
+        // two-ply search is enough to find it
+        let fen = "5k2/8/5K2/6Q1/8/8/8/8 w - - 0 1";
+        let mut board = Board::from_fen(fen).unwrap();
+        let mut searcher = Searcher::new();
+        searcher.set_options(EngineOptions::exhaustive());
+        let plain = searcher.search(&mut board, &SearchLimits::depth(2));
+        assert!(!is_mate_score(plain.score));
+
+        let mut options = EngineOptions::exhaustive();
+        options.check_extensions = true;
+        searcher.set_options(options);
+        searcher.clear_hash();
+        let extended = searcher.search(&mut board, &SearchLimits::depth(2));
+        assert!(mate_in(extended.score).is_some_and(|mate| mate > 0));
+    }
+
+    #[test]
+    fn null_moves_need_pieces() {
+        assert!(!has_non_pawn_material(&Board::from_fen("4k3/4p3/8/8/8/8/3P4/4K2n w - - 0 1").unwrap()));
+        assert!(has_non_pawn_material(&Board::from_fen("4k3/4p3/8/8/8/8/3P4/4K2n b - - 0 1").unwrap()));
+    }
+
+    #[test]
+    fn null_move_verification_keeps_its_ply_limit() {
+        // A queen up, passing still fails high at a depth that is verified
+        let mut board = Board::from_fen("4k3/8/8/8/8/8/8/Q3K3 w - - 0 1").unwrap();
+        let mut searcher = Searcher::new();
+        let mut pv = Vec::new();
+        assert!(searcher.negamax(&mut board, NULL_MOVE_VERIFY_DEPTH, -1, 0, 0, &mut pv) >= 0);
+        assert_eq!(searcher.null_move_min_ply, 0);
+
+        // Inside a verification the same node is not verified again, so
+        // the outer limit is still in place afterwards
+        searcher.null_move_min_ply = 2;
+        assert!(searcher.negamax(&mut board, NULL_MOVE_VERIFY_DEPTH, -1, 0, 2, &mut pv) >= 0);
+        assert_eq!(searcher.null_move_min_ply, 2);
+    }
+
+    #[test]
+    fn aspiration_windows_report_fail_highs() {
+        // Winning the a7 pawn and then more only shows up at depth 4
+        let mut board = Board::from_fen("7k/p7/1R5K/6r1/6p1/6P1/8/8 w - - 0 1").unwrap();
+        let mut infos = Vec::new();
+        let result = Searcher::new().search_with_info(&mut board, &SearchLimits::depth(5), |info| infos.push(info.clone()));
+        assert!(infos.iter().any(|info| info.bound == Bound::Lower && info.depth >= ASPIRATION_DEPTH));
+        // Every iteration ends with an exact score
+        for (info, next) in infos.iter().zip(infos.iter().skip(1)) {
+            if next.depth > info.depth {
+                assert_eq!(info.bound, Bound::Exact);
+            }
+        }
+        assert_eq!(result.bound, Bound::Exact);
+        assert_eq!(result.depth, 5);
+        let last = infos.last().unwrap();
+        assert_eq!((result.score, &result.pv), (last.score, &last.pv));
+    }
+
+    #[test]
+    fn root_search_fails_soft_outside_the_window() {
+        let mut board = Board::from_fen("4k3/8/8/3q4/8/8/8/3RK3 w - - 0 1").unwrap();
+        let mut searcher = Searcher::new();
+        let moves = board.legal_moves();
+        let (exact, pv) = searcher.search_root(&mut board, &moves, 2, -INFINITY, INFINITY);
+        assert_eq!(exact, search("4k3/8/8/3q4/8/8/8/3RK3 w - - 0 1", 2).score);
+        assert_eq!(pv.first(), Some(&Move::from_uci("d1d5").unwrap()));
+
+        let (score, pv) = searcher.search_root(&mut board, &moves, 2, -100, -50);
+        assert!(score >= -50);
+        assert_eq!(pv.first(), Some(&Move::from_uci("d1d5").unwrap()));
+
+        searcher.clear_hash();
+        let (score, pv) = searcher.search_root(&mut board, &moves, 2, 600, 650);
+        assert!(score <= 600);
+        assert!(pv.is_empty());
+    }
+
+    #[test]
+    fn multi_pv_ranks_distinct_lines() {
+        // Rxd5 wins the queen, Rd1 moves along the rank lose nothing
+        let fen = "4k3/8/8/3q4/8/8/8/3RK3 w - - 0 1";
+        let mut board = Board::from_fen(fen).unwrap();
+        let mut searcher = Searcher::new();
+        let mut options = EngineOptions::new();
+        options.multi_pv = 3;
+        searcher.set_options(options);
+        let mut iterations = Vec::new();
+        let result = searcher.search_with_info(&mut board, &SearchLimits::depth(4), |info| {
+            if info.bound == Bound::Exact {
+                iterations.push(info.lines.len());
+            }
+        });
+        assert_eq!(iterations, vec![3, 3, 3, 3]);
+
+        assert_eq!(result.lines.len(), 3);
+        assert_eq!(result.lines[0].pv, result.pv);
+        assert_eq!(result.lines[0].score, result.score);
+        assert_eq!(result.best_move, Some(Move::from_uci("d1d5").unwrap()));
+        let first_moves: Vec<Move> = result.lines.iter().map(|line| line.pv[0]).collect();
+        assert!(first_moves[1] != first_moves[0] && first_moves[2] != first_moves[0] && first_moves[2] != first_moves[1]);
+        for (line, next) in result.lines.iter().zip(result.lines.iter().skip(1)) {
+            assert!(line.score >= next.score);
+        }
+        assert!(result.lines[1].score < 500);
+        for line in result.lines.iter() {
+            for mv in line.pv.iter() {
+                board.play(*mv).unwrap();
+            }
+            for _ in line.pv.iter() {
+                board.unmake_move();
+            }
+        }
+        assert_eq!(board.to_fen(), fen);
+    }
+
+    #[test]
+    fn multi_pv_is_limited_by_the_legal_moves() {
+        // Only two king moves
+        let mut board = Board::from_fen("k7/8/8/2Q5/8/8/8/7K b - - 0 1").unwrap();
+        let mut searcher = Searcher::new();
+        let mut options = EngineOptions::new();
+        options.multi_pv = 5;
+        searcher.set_options(options);
+        let result = searcher.search(&mut board, &SearchLimits::depth(3));
+        assert_eq!(result.lines.len(), 2);
+        assert_eq!(Searcher::new().search(&mut board, &SearchLimits::depth(3)).lines.len(), 1);
+    }
+
+    #[test]
+    fn helper_threads_spread_over_depths() {
+        let stop = Arc::new(AtomicBool::new(false));
+        let searcher = Searcher::new();
+        assert!((1..=8).all(|depth| !searcher.skips_depth(depth)));
+        let patterns: Vec<Vec<bool>> = (1..=6)
+            .map(|thread_index| {
+                let helper = searcher.helper(thread_index, &stop);
+                (1..=8).map(|depth| helper.skips_depth(depth)).collect()
+            })
+            .collect();
+        for (index, pattern) in patterns.iter().enumerate() {
+            assert!(pattern.contains(&true) && pattern.contains(&false));
+            assert!(!patterns[..index].contains(pattern));
+        }
+    }
+
+    #[test]
+    fn threads_search_together() {
+        let mut board = Board::from_fen("4k3/8/8/3q4/8/8/8/3RK3 w - - 0 1").unwrap();
+        let mut searcher = Searcher::new();
+        let mut options = EngineOptions::new();
+        options.threads = 4;
+        searcher.set_options(options);
+        let result = searcher.search(&mut board, &SearchLimits::depth(6));
+        assert_eq!(result.best_move, Some(Move::from_uci("d1d5").unwrap()));
+        assert_eq!(result.depth, 6);
+        let single = Searcher::new().search(&mut board, &SearchLimits::depth(6));
+        assert!(result.nodes > single.nodes);
+
+        // The helpers stop with the main thread
+        let stop = searcher.stop_flag();
+        let handle = search_in_background(searcher, Board::build_starting().unwrap(), SearchLimits::new(), 1);
+        stop.store(true, Ordering::Relaxed);
+        let (_, _, result) = handle.join().unwrap();
+        assert!(result.best_move.is_some());
+    }
+
+    #[test]
+    fn searches_with_a_network() {
+        let mut board = Board::from_fen("4k3/8/8/3q4/8/8/8/3RK3 w - - 0 1").unwrap();
+        let mut searcher = Searcher::new();
+        searcher.set_network(Some(Arc::new(Network::random(32, 3).unwrap())));
+        let result = searcher.search(&mut board, &SearchLimits::depth(3));
+        assert!(result.best_move.is_some());
+        assert_eq!(result.depth, 3);
+        // The accumulator goes with the search
+        assert!(board.accumulator().is_none());
+    }
+
+    #[test]
+    fn pondering_runs_until_ponderhit() {
+        let mut board = Board::build_starting().unwrap();
+        let mut searcher = Searcher::new();
+        let result = searcher.search(&mut board, &SearchLimits::depth(4));
+        let ponder_move = searcher.ponder_move(&mut board, &result).unwrap();
+        assert_eq!(Some(ponder_move), result.pv.get(1).copied());
+        board.make_move(result.best_move.unwrap());
+        board.make_move(ponder_move);
+
+        let mut limits = SearchLimits::new();
+        limits.movetime = Some(Duration::from_millis(20));
+        limits.ponder = true;
+        let ponderhit = searcher.ponderhit_flag();
+        let handle = search_in_background(searcher, board, limits, 1);
+        // Give the clock something to count if it ran from the start
+        std::thread::sleep(Duration::from_millis(50));
+        assert!(!handle.is_finished());
+        let hit = std::time::Instant::now();
+        ponderhit.store(true, Ordering::Relaxed);
+        let (_, _, result) = handle.join().unwrap();
+        assert!(result.best_move.is_some());
+        // The movetime runs from the ponderhit
+        assert!(result.time <= hit.elapsed());
+    }
+
+    #[test]
+    fn a_ponder_miss_keeps_the_table_warm() {
+        let mut board = Board::build_starting().unwrap();
+        board.make_move(Move::from_uci("e2e4").unwrap());
+        board.make_move(Move::from_uci("e7e5").unwrap());
+        let searcher = Searcher::new();
+        let stop = searcher.stop_flag();
+        let mut limits = SearchLimits::new();
+        limits.ponder = true;
+        let handle = search_in_background(searcher, board, limits, 4);
+        stop.store(true, Ordering::Relaxed);
+        let (mut searcher, mut board, result) = handle.join().unwrap();
+        assert!(result.depth >= 1);
+
+        // The opponent played something else; what was found after e7e5
+        // is still there for later
+        board.make_move(result.best_move.unwrap());
+        let key = board.zobrist_key();
+        board.unmake_move();
+        board.unmake_move();
+        board.make_move(Move::from_uci("c7c5").unwrap());
+        searcher.search(&mut board, &SearchLimits::depth(2));
+        assert!(searcher.tt.probe(key).is_some());
+    }
+}
@@ -0,0 +1,185 @@
+use crate::attacks::bit;
+use crate::board::Board;
+use crate::chess_move::Move;
+use crate::file::File;
+use crate::rank::Rank;
+use crate::piece::Color;
+use crate::piece::PieceType;
+use crate::piece::BISHOP_DIRECTIONS;
+use crate::piece::ROOK_DIRECTIONS;
+use crate::piece::KNIGHT_OFFSETS;
+use crate::piece::KING_OFFSETS;
+
+impl Board {
+    // Static exchange evaluation: the material the side to move gains by
+    // playing mv and then letting both sides recapture on the target square
+    // with their least valuable piece for as long as that pays. Sliders lined
+    // up behind the pieces that capture join in as the exchange uncovers
+    // them. Pins are not taken into account.
+    pub fn see(&self, mv: Move) -> i32 {
+        let (to_file, to_rank) = mv.to();
+        let mover = match self.piece_at(mv.from_file, mv.from_rank) {
+            Some(piece) => piece,
+            None => return 0
+        };
+
+        let mut occupied = self.pieces().fold(0, |occupied, square| occupied | bit(square.file, square.rank));
+        occupied &= !bit(mv.from_file, mv.from_rank);
+        let mut gains = Vec::with_capacity(32);
+        let captured = match self.piece_at(to_file, to_rank) {
+            Some(piece) => piece.piece_type().value(),
+            None if self.is_capture(mv) => {
+                // En passant: the captured pawn stands beside the target square
+                occupied &= !bit(to_file, mv.from_rank);
+                PieceType::Pawn.value()
+            },
+            None => 0
+        };
+        let promotion = mv.promotion.map_or(0, |piece_type| piece_type.value() - PieceType::Pawn.value());
+        gains.push(captured + promotion);
+
+        // Value of the piece standing on the target square, next to be taken
+        let mut on_target = match mv.promotion {
+            Some(piece_type) => piece_type,
+            None => mover.piece_type()
+        };
+        let mut side = mover.get_color().opposite();
+        while let Some((file, rank, piece_type)) = self.least_valuable_attacker(to_file, to_rank, side, occupied) {
+            // The king may only recapture if nothing can take it back
+            if piece_type == PieceType::King && self.least_valuable_attacker(to_file, to_rank, side.opposite(), occupied & !bit(file, rank)).is_some() {
+                break;
+            }
+            let previous = *gains.last().unwrap();
+            gains.push(on_target.value() - previous);
+            on_target = piece_type;
+            occupied &= !bit(file, rank);
+            side = side.opposite();
+        }
+
+        // Either side may stop capturing whenever continuing would lose more
+        while gains.len() > 1 {
+            let last = gains.pop().unwrap();
+            let previous = gains.last_mut().unwrap();
+            *previous = -(-*previous).max(last);
+        }
+        gains[0]
+    }
+
+    // Whether the exchange started by mv gains at least threshold
+    pub fn see_ge(&self, mv: Move, threshold: i32) -> bool {
+        self.see(mv) >= threshold
+    }
+
+    // The cheapest piece of the given color among the occupied squares that
+    // attacks the square
+    fn least_valuable_attacker(&self, file: File, rank: Rank, color: Color, occupied: u64) -> Option<(File, Rank, PieceType)> {
+        let mut best: Option<(File, Rank, PieceType)> = None;
+        let mut consider = |(file, rank): (File, Rank), piece_types: &[PieceType]| {
+            if occupied & bit(file, rank) == 0 {
+                return;
+            }
+            if let Some(piece) = self.piece_at(file, rank) {
+                let piece_type = piece.piece_type();
+                let cheaper = best.is_none_or(|(_, _, best_type)| piece_type.value() < best_type.value());
+                if piece.get_color() == color && piece_types.contains(&piece_type) && cheaper {
+                    best = Some((file, rank, piece_type));
+                }
+            }
+        };
+        let step = |(file_step, rank_step): (i8, i8)| match (file.offset(file_step), rank.offset(rank_step)) {
+            (Ok(file), Ok(rank)) => Some((file, rank)),
+            _ => None
+        };
+        // The first occupied square along a ray, looking through captured pieces
+        let ray = |(file_step, rank_step): (i8, i8)| {
+            let mut file = file;
+            let mut rank = rank;
+            while let (Ok(next_file), Ok(next_rank)) = (file.offset(file_step), rank.offset(rank_step)) {
+                file = next_file;
+                rank = next_rank;
+                if occupied & bit(file, rank) != 0 {
+                    return Some((file, rank));
+                }
+            }
+            None
+        };
+
+        // A pawn attacks diagonally forward, so look one rank behind the square
+        let pawn_step = match color {
+            Color::White => -1,
+            Color::Black => 1
+        };
+        for square in [step((-1, pawn_step)), step((1, pawn_step))].iter().flatten() {
+            consider(*square, &[PieceType::Pawn]);
+        }
+        for square in KNIGHT_OFFSETS.iter().filter_map(|offset| step(*offset)) {
+            consider(square, &[PieceType::Knight]);
+        }
+        for square in BISHOP_DIRECTIONS.iter().filter_map(|direction| ray(*direction)) {
+            consider(square, &[PieceType::Bishop, PieceType::Queen]);
+        }
+        for square in ROOK_DIRECTIONS.iter().filter_map(|direction| ray(*direction)) {
+            consider(square, &[PieceType::Rook, PieceType::Queen]);
+        }
+        for square in KING_OFFSETS.iter().filter_map(|offset| step(*offset)) {
+            consider(square, &[PieceType::King]);
+        }
+        best
+    }
+}
+
+#[cfg(test)]
+mod see_tests {
+    use super::*;
+
+    fn see(fen: &str, uci: &str) -> i32 {
+        let board = Board::from_fen(fen).unwrap();
+        board.see(Move::from_uci(uci).unwrap())
+    }
+
+    #[test]
+    fn undefended_and_defended_captures() {
+        assert_eq!(see("1k1r4/1pp4p/p7/4p3/8/P5P1/1PP4P/2K1R3 w - - 0 1", "e1e5"), 100);
+        assert_eq!(see("4k3/8/4p3/3n4/8/8/8/3QK3 w - - 0 1", "d1d5"), 320 - 900);
+        assert_eq!(see("4k3/8/4p3/3n4/8/8/8/3RK3 w - - 0 1", "d1d5"), 320 - 500);
+        // Pawn takes knight, knight takes pawn
+        assert_eq!(see("4k3/8/4p3/3n4/4P3/8/8/4K3 w - - 0 1", "e4d5"), 220);
+    }
+
+    #[test]
+    fn x_ray_attackers_join_the_exchange() {
+        // The rook behind the first one wins the pawn: Rxd5 Rxd5 Rxd5
+        assert_eq!(see("3rk3/8/8/3p4/8/8/3R4/3RK3 w - - 0 1", "d2d5"), 100);
+        // With black's rooks doubled as well the first capture loses a rook
+        assert_eq!(see("3rk3/3r4/8/3p4/8/8/3R4/3RK3 w - - 0 1", "d2d5"), 100 - 500);
+        // Bishop behind queen on the diagonal
+        assert_eq!(see("4k3/8/2p5/3p4/8/5Q2/6B1/4K3 w - - 0 1", "f3d5"), 100 - 900 + 100);
+        assert_eq!(see("4k3/8/8/3p4/8/5Q2/6B1/4K3 w - - 0 1", "f3d5"), 100);
+    }
+
+    #[test]
+    fn king_cannot_recapture_into_an_attack() {
+        // Rxd7 Kxd7 is fine, but not with the bishop covering d7
+        assert_eq!(see("8/3nk3/8/8/8/8/8/3RK3 w - - 0 1", "d1d7"), 320 - 500);
+        assert_eq!(see("8/3nk3/8/1B6/8/8/8/3RK3 w - - 0 1", "d1d7"), 320);
+    }
+
+    #[test]
+    fn quiet_moves_promotions_and_en_passant() {
+        // A quiet move to an attacked square loses the piece
+        assert_eq!(see("4k3/8/4p3/8/8/8/8/3QK3 w - - 0 1", "d1d5"), -900);
+        assert_eq!(see("4k3/8/8/8/8/8/8/3QK3 w - - 0 1", "d1d5"), 0);
+        assert_eq!(see("4k3/P7/8/8/8/8/8/4K3 w - - 0 1", "a7a8q"), 800);
+        assert_eq!(see("r3k3/1P6/8/8/8/8/8/4K3 w - - 0 1", "b7a8q"), 500 + 800);
+        assert_eq!(see("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1", "e5d6"), 100);
+    }
+
+    #[test]
+    fn see_ge_thresholds() {
+        let board = Board::from_fen("4k3/8/4p3/3n4/4P3/8/8/4K3 w - - 0 1").unwrap();
+        let mv = Move::from_uci("e4d5").unwrap();
+        assert!(board.see_ge(mv, 0));
+        assert!(board.see_ge(mv, 220));
+        assert!(!board.see_ge(mv, 221));
+    }
+}
@@ -4,7 +4,7 @@ use crate::file::File;
 use crate::piece::Color;
 use crate::piece::Piece;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub struct Square {
     pub file: File,
     pub rank: Rank,
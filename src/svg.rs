@@ -0,0 +1,397 @@
+use crate::board::Board;
+use crate::chess_move::Move;
+use crate::piece::Color;
+use crate::piece::Piece;
+use crate::piece::PieceType;
+use crate::rank;
+use crate::rank::Rank;
+use crate::file;
+use crate::file::File;
+
+use std::fmt::Write;
+
+// Width of one square in SVG user units; piece artwork is drawn on the same grid
+const SQUARE_SIZE: f32 = 45.0;
+const MARGIN: f32 = 20.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Arrow {
+    pub from: (File, Rank),
+    pub to: (File, Rank),
+}
+
+#[derive(Debug, Clone)]
+pub struct SvgOptions {
+    // Rendered width and height in pixels
+    pub size: u32,
+    pub coordinates: bool,
+    // Draw the board from black's side
+    pub flipped: bool,
+    pub last_move: Option<Move>,
+    pub arrows: Vec<Arrow>,
+    pub circles: Vec<(File, Rank)>,
+
+    pub light_color: String,
+    pub dark_color: String,
+    pub highlight_color: String,
+    pub annotation_color: String,
+}
+
+impl SvgOptions {
+    pub fn new() -> SvgOptions {
+        SvgOptions{
+            size: 400,
+            coordinates: true,
+            flipped: false,
+            last_move: None,
+            arrows: Vec::new(),
+            circles: Vec::new(),
+            light_color: String::from("#f0d9b5"),
+            dark_color: String::from("#b58863"),
+            highlight_color: String::from("#cdd26a"),
+            annotation_color: String::from("#15781b"),
+        }
+    }
+
+    pub fn add_arrow(&mut self, from: (File, Rank), to: (File, Rank)) {
+        self.arrows.push(Arrow{from, to});
+    }
+
+    pub fn add_circle(&mut self, file: File, rank: Rank) {
+        self.circles.push((file, rank));
+    }
+}
+
+impl Default for SvgOptions {
+    fn default() -> SvgOptions {
+        SvgOptions::new()
+    }
+}
+
+// Render a self-contained SVG diagram of the board. All piece artwork is
+// embedded in a <defs> block, so the output needs no external files.
+pub fn render_svg(board: &Board, options: &SvgOptions) -> String {
+    let margin = if options.coordinates { MARGIN } else { 0.0 };
+    let extent = 8.0 * SQUARE_SIZE + 2.0 * margin;
+
+    let mut svg = String::new();
+    let _ = writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" xmlns:xlink="http://www.w3.org/1999/xlink" version="1.1" width="{}" height="{}" viewBox="0 0 {} {}">"#,
+        options.size, options.size, extent, extent
+    );
+    svg.push_str(&piece_definitions());
+
+    // Squares
+    for rank in Rank::iter_ranks(0..rank::MAX_NUMBER_OF_RANKS) {
+        for file in File::iter_files(0..file::MAX_NUMBER_OF_FILES) {
+            let (x, y) = square_origin(file, rank, options.flipped, margin);
+            let color = if (file.value() + rank.value()) % 2 == 0 {
+                &options.dark_color
+            } else {
+                &options.light_color
+            };
+            let _ = writeln!(
+                svg,
+                r#"<rect class="square {}{}" x="{}" y="{}" width="{}" height="{}" fill="{}"/>"#,
+                file, rank, x, y, SQUARE_SIZE, SQUARE_SIZE, color
+            );
+        }
+    }
+
+    // Last move highlighting sits between the squares and the pieces
+    if let Some(mv) = &options.last_move {
+        for (file, rank) in [mv.from(), mv.to()].iter() {
+            let (x, y) = square_origin(*file, *rank, options.flipped, margin);
+            let _ = writeln!(
+                svg,
+                r#"<rect class="lastmove" x="{}" y="{}" width="{}" height="{}" fill="{}" fill-opacity="0.6"/>"#,
+                x, y, SQUARE_SIZE, SQUARE_SIZE, options.highlight_color
+            );
+        }
+    }
+
+    if options.coordinates {
+        svg.push_str(&coordinate_labels(options.flipped, margin));
+    }
+
+    // Pieces
+    for rank in Rank::iter_ranks(0..rank::MAX_NUMBER_OF_RANKS) {
+        for file in File::iter_files(0..file::MAX_NUMBER_OF_FILES) {
+            if let Some(piece) = &board.get_square(file, rank).piece {
+                let (x, y) = square_origin(file, rank, options.flipped, margin);
+                let _ = writeln!(
+                    svg,
+                    r##"<use xlink:href="#{}" href="#{}" transform="translate({}, {})"/>"##,
+                    piece_id(piece), piece_id(piece), x, y
+                );
+            }
+        }
+    }
+
+    // Annotations are drawn on top of everything
+    for (file, rank) in options.circles.iter() {
+        let (x, y) = square_origin(*file, *rank, options.flipped, margin);
+        let _ = writeln!(
+            svg,
+            r#"<circle class="circle" cx="{}" cy="{}" r="{}" fill="none" stroke="{}" stroke-width="3" stroke-opacity="0.8"/>"#,
+            x + SQUARE_SIZE / 2.0, y + SQUARE_SIZE / 2.0, SQUARE_SIZE / 2.0 - 2.5, options.annotation_color
+        );
+    }
+    for arrow in options.arrows.iter() {
+        svg.push_str(&arrow_element(arrow, options, margin));
+    }
+
+    svg.push_str("</svg>\n");
+    svg
+}
+
+// Top-left corner of a square in SVG user units
+fn square_origin(file: File, rank: Rank, flipped: bool, margin: f32) -> (f32, f32) {
+    let (column, row) = if flipped {
+        (7 - file.value(), rank.value())
+    } else {
+        (file.value(), 7 - rank.value())
+    };
+    (margin + f32::from(column) * SQUARE_SIZE, margin + f32::from(row) * SQUARE_SIZE)
+}
+
+fn coordinate_labels(flipped: bool, margin: f32) -> String {
+    let mut res = String::new();
+    let far_edge = margin + 8.0 * SQUARE_SIZE;
+    for file in File::iter_files(0..file::MAX_NUMBER_OF_FILES) {
+        let (x, _) = square_origin(file, Rank::build(0).unwrap(), flipped, margin);
+        let x = x + SQUARE_SIZE / 2.0;
+        for y in [margin / 2.0, far_edge + margin / 2.0].iter() {
+            let _ = writeln!(
+                res,
+                r##"<text class="coordinate" x="{}" y="{}" font-family="sans-serif" font-size="14" text-anchor="middle" dominant-baseline="central" fill="#333">{}</text>"##,
+                x, y, file
+            );
+        }
+    }
+    for rank in Rank::iter_ranks(0..rank::MAX_NUMBER_OF_RANKS) {
+        let (_, y) = square_origin(File::build(0).unwrap(), rank, flipped, margin);
+        let y = y + SQUARE_SIZE / 2.0;
+        for x in [margin / 2.0, far_edge + margin / 2.0].iter() {
+            let _ = writeln!(
+                res,
+                r##"<text class="coordinate" x="{}" y="{}" font-family="sans-serif" font-size="14" text-anchor="middle" dominant-baseline="central" fill="#333">{}</text>"##,
+                x, y, rank
+            );
+        }
+    }
+    res
+}
+
+fn arrow_element(arrow: &Arrow, options: &SvgOptions, margin: f32) -> String {
+    let (from_x, from_y) = square_origin(arrow.from.0, arrow.from.1, options.flipped, margin);
+    let (to_x, to_y) = square_origin(arrow.to.0, arrow.to.1, options.flipped, margin);
+    let (from_x, from_y) = (from_x + SQUARE_SIZE / 2.0, from_y + SQUARE_SIZE / 2.0);
+    let (to_x, to_y) = (to_x + SQUARE_SIZE / 2.0, to_y + SQUARE_SIZE / 2.0);
+
+    let (dx, dy) = (to_x - from_x, to_y - from_y);
+    let length = (dx * dx + dy * dy).sqrt();
+    if length == 0.0 {
+        return String::new();
+    }
+    let (ux, uy) = (dx / length, dy / length);
+
+    // The shaft stops where the head begins so the two don't overlap
+    let head_length = SQUARE_SIZE * 0.45;
+    let head_width = SQUARE_SIZE * 0.5;
+    let tip_x = to_x - ux * SQUARE_SIZE * 0.2;
+    let tip_y = to_y - uy * SQUARE_SIZE * 0.2;
+    let base_x = tip_x - ux * head_length;
+    let base_y = tip_y - uy * head_length;
+
+    let mut res = String::new();
+    let _ = writeln!(
+        res,
+        r#"<g class="arrow" fill="{}" stroke="{}" opacity="0.8">"#,
+        options.annotation_color, options.annotation_color
+    );
+    let _ = writeln!(
+        res,
+        r#"<line x1="{:.1}" y1="{:.1}" x2="{:.1}" y2="{:.1}" stroke-width="{}" stroke-linecap="round"/>"#,
+        from_x, from_y, base_x, base_y, SQUARE_SIZE * 0.2
+    );
+    let _ = writeln!(
+        res,
+        r#"<polygon points="{:.1},{:.1} {:.1},{:.1} {:.1},{:.1}" stroke="none"/>"#,
+        tip_x, tip_y,
+        base_x - uy * head_width / 2.0, base_y + ux * head_width / 2.0,
+        base_x + uy * head_width / 2.0, base_y - ux * head_width / 2.0
+    );
+    res.push_str("</g>\n");
+    res
+}
+
+fn piece_id(piece: &Piece) -> String {
+    let color = match piece.get_color() {
+        Color::White => 'w',
+        Color::Black => 'b',
+    };
+    format!("{}{}", color, piece.to_char().to_ascii_uppercase())
+}
+
+// Piece artwork on a 45x45 grid, one <g> per piece and color
+fn piece_definitions() -> String {
+    let piece_types = [
+        PieceType::Pawn,
+        PieceType::Knight,
+        PieceType::Bishop,
+        PieceType::Rook,
+        PieceType::Queen,
+        PieceType::King
+    ];
+
+    let mut res = String::from("<defs>\n");
+    for color in [Color::White, Color::Black].iter() {
+        let (fill, detail) = match color {
+            Color::White => ("#ffffff", "#000000"),
+            Color::Black => ("#000000", "#ffffff"),
+        };
+        for piece_type in piece_types.iter() {
+            let piece = Piece::new(*color, *piece_type, false);
+            let _ = writeln!(
+                res,
+                r##"<g id="{}" fill="{}" stroke="#000000" stroke-width="1.5" stroke-linecap="round" stroke-linejoin="round">"##,
+                piece_id(&piece), fill
+            );
+            res.push_str(&piece_artwork(*piece_type, detail));
+            res.push_str("</g>\n");
+        }
+    }
+    res.push_str("</defs>\n");
+    res
+}
+
+fn piece_artwork(piece_type: PieceType, detail: &str) -> String {
+    let base = r#"<path d="M 11,39 L 34,39 L 34,36 L 11,36 Z"/>"#;
+    match piece_type {
+        PieceType::Pawn => format!(
+            concat!(
+                r#"<path d="M 14,36 L 31,36 C 31,30 27,27 26,23 L 19,23 C 18,27 14,30 14,36 Z"/>"#, "\n",
+                r#"<ellipse cx="22.5" cy="22" rx="6" ry="2"/>"#, "\n",
+                r#"<circle cx="22.5" cy="14" r="5.5"/>"#, "\n",
+                "{}\n"
+            ),
+            base
+        ),
+        PieceType::Knight => format!(
+            concat!(
+                r#"<path d="M 13,36 L 33,36 C 33,27 31,16 22,11 L 21,6 L 18,10.5 C 14,13 10,19 9,25 C 9,28 12,29.5 14,27.5 C 16,25.5 19,24.5 21,23 C 17,28 14,31 13,36 Z"/>"#, "\n",
+                r#"<circle cx="16" cy="17" r="1.2" fill="{}" stroke="{}"/>"#, "\n",
+                r#"<path d="M 25,14 C 29,19 30,26 30,34" fill="none" stroke="{}"/>"#, "\n",
+                "{}\n"
+            ),
+            detail, detail, detail, base
+        ),
+        PieceType::Bishop => format!(
+            concat!(
+                r#"<path d="M 15,36 L 30,36 C 30,30 32,24 22.5,13 C 13,24 15,30 15,36 Z"/>"#, "\n",
+                r#"<circle cx="22.5" cy="9.5" r="2.5"/>"#, "\n",
+                r#"<path d="M 22.5,19 L 22.5,28 M 18.5,23.5 L 26.5,23.5" fill="none" stroke="{}"/>"#, "\n",
+                "{}\n"
+            ),
+            detail, base
+        ),
+        PieceType::Rook => format!(
+            concat!(
+                r#"<path d="M 14,36 L 31,36 L 29,17 L 16,17 Z"/>"#, "\n",
+                r#"<path d="M 12,17 L 33,17 L 33,9 L 29,9 L 29,12 L 25,12 L 25,9 L 20,9 L 20,12 L 16,12 L 16,9 L 12,9 Z"/>"#, "\n",
+                r#"<path d="M 16,20 L 29,20" fill="none" stroke="{}" stroke-width="1"/>"#, "\n",
+                "{}\n"
+            ),
+            detail, base
+        ),
+        PieceType::Queen => format!(
+            concat!(
+                r#"<path d="M 11,36 L 34,36 L 36,14 L 30,25 L 29.5,11 L 25,24 L 22.5,9 L 20,24 L 15.5,11 L 15,25 L 9,14 Z"/>"#, "\n",
+                r#"<circle cx="9" cy="13" r="2"/>"#, "\n",
+                r#"<circle cx="15.5" cy="10" r="2"/>"#, "\n",
+                r#"<circle cx="22.5" cy="8" r="2"/>"#, "\n",
+                r#"<circle cx="29.5" cy="10" r="2"/>"#, "\n",
+                r#"<circle cx="36" cy="13" r="2"/>"#, "\n",
+                r#"<path d="M 12.5,31 C 19,29 26,29 32.5,31" fill="none" stroke="{}" stroke-width="1"/>"#, "\n",
+                "{}\n"
+            ),
+            detail, base
+        ),
+        PieceType::King => format!(
+            concat!(
+                r##"<path d="M 22.5,5.5 L 22.5,15 M 18.5,9.5 L 26.5,9.5" fill="none" stroke="#000000" stroke-width="2"/>"##, "\n",
+                r#"<path d="M 12,36 L 33,36 C 35,28 38,20 30,18 C 26,17 23.5,21 22.5,24 C 21.5,21 19,17 15,18 C 7,20 10,28 12,36 Z"/>"#, "\n",
+                r#"<path d="M 22.5,24 L 22.5,33 M 12.5,31 C 19,29 26,29 32.5,31" fill="none" stroke="{}" stroke-width="1"/>"#, "\n",
+                "{}\n"
+            ),
+            detail, base
+        ),
+    }
+}
+
+#[cfg(test)]
+mod svg_tests {
+    use super::*;
+
+    fn square(file: u8, rank: u8) -> (File, Rank) {
+        (File::build(file).unwrap(), Rank::build(rank).unwrap())
+    }
+
+    #[test]
+    fn starting_position_is_self_contained() {
+        let board = Board::build_starting().unwrap();
+        let svg = render_svg(&board, &SvgOptions::new());
+
+        assert!(svg.starts_with("<svg "));
+        assert!(svg.trim_end().ends_with("</svg>"));
+        assert_eq!(svg.matches("<use ").count(), 32);
+        assert_eq!(svg.matches(r#"<rect class="square"#).count(), 64);
+        assert_eq!(svg.matches("<g id=").count(), 12);
+        // Everything referenced is defined in the document itself
+        assert!(!svg.contains("xlink:href=\"http"));
+        assert!(!svg.contains("<image"));
+    }
+
+    #[test]
+    fn coordinates_can_be_disabled() {
+        let board = Board::build_empty().unwrap();
+        let mut options = SvgOptions::new();
+        assert_eq!(render_svg(&board, &options).matches("<text ").count(), 32);
+
+        options.coordinates = false;
+        let svg = render_svg(&board, &options);
+        assert_eq!(svg.matches("<text ").count(), 0);
+        assert!(svg.contains(r#"viewBox="0 0 360 360""#));
+    }
+
+    #[test]
+    fn flipped_board_mirrors_squares() {
+        let (a1_file, a1_rank) = square(0, 0);
+        assert_eq!(square_origin(a1_file, a1_rank, false, 0.0), (0.0, 315.0));
+        assert_eq!(square_origin(a1_file, a1_rank, true, 0.0), (315.0, 0.0));
+
+        let board = Board::build_starting().unwrap();
+        let mut options = SvgOptions::new();
+        options.coordinates = false;
+        options.flipped = true;
+        let svg = render_svg(&board, &options);
+        // White king on e1 is drawn at the top of a flipped board
+        assert!(svg.contains(r##"href="#wK" transform="translate(135, 0)""##));
+    }
+
+    #[test]
+    fn annotations() {
+        let board = Board::build_starting().unwrap();
+        let mut options = SvgOptions::new();
+        options.last_move = Some(Move::from_uci("e2e4").unwrap());
+        options.add_arrow(square(6, 0), square(5, 2));
+        options.add_circle(square(3, 4).0, square(3, 4).1);
+        let svg = render_svg(&board, &options);
+
+        assert_eq!(svg.matches(r#"class="lastmove""#).count(), 2);
+        assert_eq!(svg.matches(r#"class="arrow""#).count(), 1);
+        assert_eq!(svg.matches(r#"class="circle""#).count(), 1);
+    }
+}
@@ -0,0 +1,1436 @@
+use crate::board::Board;
+use crate::chess_move::Move;
+use crate::piece::Color;
+use crate::piece::Piece;
+use crate::piece::PieceType;
+
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::convert::TryInto;
+use std::fs;
+use std::io;
+use std::ops::Neg;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::OnceLock;
+
+// Syzygy tables come in pairs: .rtbw files hold win/draw/loss for both sides
+// to move, .rtbz files hold distance to the next capture or pawn move (DTZ)
+// for one side only. File names spell the material with the stronger side
+// first, e.g. KQvKR.rtbw.
+const WDL_MAGIC: [u8; 4] = [0x71, 0xe8, 0x23, 0x5d];
+const DTZ_MAGIC: [u8; 4] = [0xd7, 0x66, 0x0c, 0xa5];
+
+const WDL_SUFFIX: &str = "rtbw";
+const DTZ_SUFFIX: &str = "rtbz";
+
+// Largest DTZ a position can have, used to rank root moves
+const MAX_DTZ: i32 = 1 << 18;
+
+// Per table flags stored in the header of each PairsData block
+const FLAG_STM: u8 = 1;
+const FLAG_MAPPED: u8 = 2;
+const FLAG_WIN_PLIES: u8 = 4;
+const FLAG_LOSS_PLIES: u8 = 8;
+const FLAG_WIDE: u8 = 16;
+const FLAG_SINGLE_VALUE: u8 = 128;
+
+// Order of piece letters in table names
+const NAME_ORDER: [PieceType; 6] = [PieceType::King, PieceType::Queen, PieceType::Rook, PieceType::Bishop, PieceType::Knight, PieceType::Pawn];
+
+// Win/draw/loss from the side to move's point of view. A cursed win is a win
+// that the fifty-move rule turns into a draw, a blessed loss the reverse.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Wdl {
+    Loss = -2,
+    BlessedLoss = -1,
+    Draw = 0,
+    CursedWin = 1,
+    Win = 2,
+}
+
+impl Wdl {
+    fn from_value(value: i32) -> Wdl {
+        match value {
+            v if v <= -2 => Wdl::Loss,
+            -1 => Wdl::BlessedLoss,
+            0 => Wdl::Draw,
+            1 => Wdl::CursedWin,
+            _ => Wdl::Win
+        }
+    }
+
+    fn sign(self) -> i32 {
+        (self as i32).signum()
+    }
+}
+
+impl Neg for Wdl {
+    type Output = Wdl;
+
+    fn neg(self) -> Wdl {
+        Wdl::from_value(-(self as i32))
+    }
+}
+
+// A legal root move with its tablebase verdict. Moves with a higher rank are
+// better; all wins that are safe from the fifty-move rule share one rank.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TablebaseMove {
+    pub mv: Move,
+    pub wdl: Wdl,
+    pub dtz: i32,
+    pub rank: i32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TableKind {
+    Wdl,
+    Dtz,
+}
+
+impl TableKind {
+    fn suffix(self) -> &'static str {
+        match self {
+            TableKind::Wdl => WDL_SUFFIX,
+            TableKind::Dtz => DTZ_SUFFIX
+        }
+    }
+}
+
+// Lookup tables used to turn piece placements into table indices
+struct Encoding {
+    // binomial[k][n] ways to choose k squares out of n
+    binomial: [[u64; 64]; 7],
+    // Squares a2-h7 numbered so that the leading pawn has the highest value
+    map_pawns: [usize; 64],
+    lead_pawn_idx: [[u64; 64]; 6],
+    lead_pawns_size: [[u64; 4]; 6],
+    // Squares below the a1-h8 diagonal to 0..27
+    map_b1h1h7: [u64; 64],
+    // The a1-d1-d4 triangle to 0..9, diagonal squares last
+    map_a1d1d4: [usize; 64],
+    // The 462 placements of two kings with the first in the a1-d1-d4 triangle
+    map_kk: [[u64; 64]; 10],
+}
+
+fn file_of(sq: usize) -> usize {
+    sq & 7
+}
+
+fn rank_of(sq: usize) -> usize {
+    sq >> 3
+}
+
+// Positive above the a1-h8 diagonal, negative below it
+fn off_diagonal(sq: usize) -> i32 {
+    rank_of(sq) as i32 - file_of(sq) as i32
+}
+
+fn flip_diagonal(sq: usize) -> usize {
+    ((sq >> 3) | (sq << 3)) & 63
+}
+
+fn encoding() -> &'static Encoding {
+    static ENCODING: OnceLock<Encoding> = OnceLock::new();
+    ENCODING.get_or_init(Encoding::new)
+}
+
+impl Encoding {
+    fn new() -> Encoding {
+        let mut res = Encoding{
+            binomial: [[0; 64]; 7],
+            map_pawns: [0; 64],
+            lead_pawn_idx: [[0; 64]; 6],
+            lead_pawns_size: [[0; 4]; 6],
+            map_b1h1h7: [0; 64],
+            map_a1d1d4: [0; 64],
+            map_kk: [[0; 64]; 10],
+        };
+
+        let mut code = 0;
+        for sq in 0..64 {
+            if off_diagonal(sq) < 0 {
+                res.map_b1h1h7[sq] = code;
+                code += 1;
+            }
+        }
+
+        let mut diagonal = Vec::new();
+        let mut code = 0;
+        for sq in 0..28 {
+            if off_diagonal(sq) < 0 && file_of(sq) <= 3 {
+                res.map_a1d1d4[sq] = code;
+                code += 1;
+            } else if off_diagonal(sq) == 0 && file_of(sq) <= 3 {
+                diagonal.push(sq);
+            }
+        }
+        for sq in diagonal {
+            res.map_a1d1d4[sq] = code;
+            code += 1;
+        }
+
+        // When the first king is on the diagonal the second must not be above
+        // it, and placements with both kings on the diagonal come last
+        let mut both_on_diagonal = Vec::new();
+        let mut code = 0;
+        for idx in 0..10 {
+            // b1 is the only square of the triangle mapped to 0
+            let firsts: Vec<usize> = (0..28).filter(|s1| res.map_a1d1d4[*s1] == idx && (idx != 0 || *s1 == 1)).collect();
+            for s1 in firsts {
+                for s2 in 0..64 {
+                    let touching = file_of(s1).abs_diff(file_of(s2)) <= 1 && rank_of(s1).abs_diff(rank_of(s2)) <= 1;
+                    if touching || (off_diagonal(s1) == 0 && off_diagonal(s2) > 0) {
+                        continue;
+                    }
+                    if off_diagonal(s1) == 0 && off_diagonal(s2) == 0 {
+                        both_on_diagonal.push((idx, s2));
+                    } else {
+                        res.map_kk[idx][s2] = code;
+                        code += 1;
+                    }
+                }
+            }
+        }
+        for (idx, s2) in both_on_diagonal {
+            res.map_kk[idx][s2] = code;
+            code += 1;
+        }
+
+        res.binomial[0][0] = 1;
+        for n in 1..64 {
+            for k in 0..7 {
+                if k > n {
+                    break;
+                }
+                let with = if k > 0 { res.binomial[k - 1][n - 1] } else { 0 };
+                let without = if k < n { res.binomial[k][n - 1] } else { 0 };
+                res.binomial[k][n] = with + without;
+            }
+        }
+
+        // A leading pawn on a2 leaves 47 squares for the others, and each
+        // step up or towards the centre takes away the squares behind it
+        let mut available = 47;
+        for lead_count in 1..6 {
+            for file in 0..4 {
+                let mut idx = 0;
+                for rank in 1..7 {
+                    let sq = rank * 8 + file;
+                    if lead_count == 1 {
+                        res.map_pawns[sq] = available;
+                        res.map_pawns[sq ^ 7] = available.saturating_sub(1);
+                        available = available.saturating_sub(2);
+                    }
+                    res.lead_pawn_idx[lead_count][sq] = idx;
+                    idx += res.binomial[lead_count - 1][res.map_pawns[sq]];
+                }
+                res.lead_pawns_size[lead_count][file] = idx;
+            }
+        }
+        res
+    }
+}
+
+// Material of a table, with white being the first side in its name
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Material {
+    white: Vec<PieceType>,
+    black: Vec<PieceType>,
+}
+
+fn piece_letter(piece_type: PieceType) -> char {
+    Piece::new(Color::White, piece_type, false).to_char()
+}
+
+fn side_name(pieces: &[PieceType]) -> String {
+    NAME_ORDER.iter().flat_map(|piece_type| {
+        pieces.iter().filter(move |p| *p == piece_type).map(|p| piece_letter(*p))
+    }).collect()
+}
+
+impl Material {
+    // Parse a table name such as KRPvKR
+    fn from_name(name: &str) -> Option<Material> {
+        let (white, black) = name.split_once('v')?;
+        let parse = |side: &str| -> Option<Vec<PieceType>> {
+            let pieces: Vec<PieceType> = side.chars().map(|c| Piece::from_char(c).ok().map(|piece| piece.piece_type())).collect::<Option<_>>()?;
+            if pieces.iter().filter(|p| **p == PieceType::King).count() == 1 { Some(pieces) } else { None }
+        };
+        Some(Material{white: parse(white)?, black: parse(black)?})
+    }
+
+    fn from_board(board: &Board) -> Material {
+        let side = |color: Color| board.pieces()
+            .filter_map(|square| square.piece)
+            .filter(|piece| piece.get_color() == color)
+            .map(|piece| piece.piece_type())
+            .collect();
+        Material{white: side(Color::White), black: side(Color::Black)}
+    }
+
+    fn name(&self) -> String {
+        format!("{}v{}", side_name(&self.white), side_name(&self.black))
+    }
+
+    fn swapped_name(&self) -> String {
+        format!("{}v{}", side_name(&self.black), side_name(&self.white))
+    }
+
+    fn piece_count(&self) -> usize {
+        self.white.len() + self.black.len()
+    }
+
+    fn count(pieces: &[PieceType], piece_type: PieceType) -> usize {
+        pieces.iter().filter(|p| **p == piece_type).count()
+    }
+
+    fn has_pawns(&self) -> bool {
+        self.white.contains(&PieceType::Pawn) || self.black.contains(&PieceType::Pawn)
+    }
+
+    fn is_symmetric(&self) -> bool {
+        self.name() == self.swapped_name()
+    }
+
+    // Whether some side has exactly one piece of a type other than king
+    fn has_unique_pieces(&self) -> bool {
+        NAME_ORDER[1..].iter().any(|piece_type| {
+            Material::count(&self.white, *piece_type) == 1 || Material::count(&self.black, *piece_type) == 1
+        })
+    }
+
+    // Pawns of the leading color first. The side with fewer pawns leads,
+    // white if neither has fewer.
+    fn pawn_count(&self) -> [usize; 2] {
+        let white = Material::count(&self.white, PieceType::Pawn);
+        let black = Material::count(&self.black, PieceType::Pawn);
+        if black == 0 || (white > 0 && black >= white) { [white, black] } else { [black, white] }
+    }
+}
+
+// Reads from table bytes, returning None past the end of a truncated file
+fn read_u8(data: &[u8], pos: usize) -> Option<u8> {
+    data.get(pos).copied()
+}
+
+fn read_u16_le(data: &[u8], pos: usize) -> Option<u16> {
+    Some(u16::from_le_bytes([read_u8(data, pos)?, read_u8(data, pos + 1)?]))
+}
+
+fn read_u32_le(data: &[u8], pos: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(data.get(pos..pos + 4)?.try_into().ok()?))
+}
+
+fn read_u32_be(data: &[u8], pos: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(data.get(pos..pos + 4)?.try_into().ok()?))
+}
+
+fn read_u64_be(data: &[u8], pos: usize) -> Option<u64> {
+    Some(u64::from_be_bytes(data.get(pos..pos + 8)?.try_into().ok()?))
+}
+
+// One compressed sub-table: values for one side to move and, in pawn
+// tables, one file of the leading pawn. Offsets point into the table bytes.
+#[derive(Debug, Clone, Default)]
+struct PairsData {
+    flags: u8,
+    // Piece codes in encoding order: 1-6 pawn to king, plus 8 for black
+    pieces: [u8; 7],
+    // Zero-terminated sizes of the groups pieces are encoded in
+    group_len: [usize; 8],
+    group_idx: [u64; 8],
+    block_size: u64,
+    span: u64,
+    sparse_index: usize,
+    sparse_index_size: u64,
+    block_length: usize,
+    block_length_size: u64,
+    blocks: u64,
+    data: usize,
+    min_sym_len: u8,
+    lowest_sym: usize,
+    base64: Vec<u64>,
+    symlen: Vec<u8>,
+    btree: usize,
+    // Offsets of the DTZ value maps for loss, win, cursed win and blessed loss
+    map_idx: [usize; 4],
+}
+
+impl PairsData {
+    fn table_size(&self) -> u64 {
+        let groups = self.group_len.iter().position(|len| *len == 0).unwrap_or(7);
+        self.group_idx[groups]
+    }
+
+    // Read the Huffman and pairing parameters, returning the offset after them
+    fn set_sizes(&mut self, data: &[u8], pos: usize) -> Option<usize> {
+        let mut pos = pos;
+        self.flags = read_u8(data, pos)?;
+        pos += 1;
+        if self.flags & FLAG_SINGLE_VALUE != 0 {
+            // The single value every position of the table has
+            self.min_sym_len = read_u8(data, pos)?;
+            return Some(pos + 1);
+        }
+
+        self.block_size = 1u64.checked_shl(u32::from(read_u8(data, pos)?))?;
+        self.span = 1u64.checked_shl(u32::from(read_u8(data, pos + 1)?))?;
+        self.sparse_index_size = self.table_size().div_ceil(self.span);
+        let padding = u64::from(read_u8(data, pos + 2)?);
+        self.blocks = u64::from(read_u32_le(data, pos + 3)?);
+        self.block_length_size = self.blocks + padding;
+        let max_sym_len = read_u8(data, pos + 7)?;
+        self.min_sym_len = read_u8(data, pos + 8)?;
+        pos += 9;
+        self.lowest_sym = pos;
+        if max_sym_len < self.min_sym_len {
+            return None;
+        }
+
+        // Canonical Huffman codes: longer codes have lower values, so base64[i]
+        // is the smallest 64 bit left-aligned code of length min_sym_len + i
+        let lengths = usize::from(max_sym_len - self.min_sym_len) + 1;
+        let lowest_sym = self.lowest_sym;
+        let lowest = |i: usize| read_u16_le(data, lowest_sym + 2 * i).map(u64::from);
+        self.base64 = vec![0; lengths];
+        for i in (0..lengths - 1).rev() {
+            self.base64[i] = (self.base64[i + 1] + lowest(i)?).checked_sub(lowest(i + 1)?)? / 2;
+        }
+        for (i, base) in self.base64.iter_mut().enumerate() {
+            let shift = 64u32.checked_sub(i as u32 + u32::from(self.min_sym_len))?;
+            *base = base.checked_shl(shift).unwrap_or(0);
+        }
+        pos += lengths * 2;
+
+        let symbols = usize::from(read_u16_le(data, pos)?);
+        pos += 2;
+        self.btree = pos;
+        if data.len() < self.btree + symbols * 3 {
+            return None;
+        }
+        self.symlen = vec![0; symbols];
+        let mut visited = vec![false; symbols];
+        for sym in 0..symbols {
+            if !visited[sym] {
+                self.symlen[sym] = self.set_symlen(data, sym, &mut visited)?;
+            }
+        }
+        Some(pos + symbols * 3 + (symbols & 1))
+    }
+
+    // Each symbol is a pair of smaller symbols; count the values it expands to
+    fn set_symlen(&mut self, data: &[u8], sym: usize, visited: &mut [bool]) -> Option<u8> {
+        visited[sym] = true;
+        let right = self.right(data, sym);
+        if right == 0xfff {
+            return Some(0);
+        }
+        let left = self.left(data, sym);
+        for child in [left, right] {
+            if child >= visited.len() {
+                return None;
+            }
+            if !visited[child] {
+                self.symlen[child] = self.set_symlen(data, child, visited)?;
+            }
+        }
+        Some(self.symlen[left].wrapping_add(self.symlen[right]).wrapping_add(1))
+    }
+
+    fn left(&self, data: &[u8], sym: usize) -> usize {
+        let lr = &data[self.btree + sym * 3..];
+        usize::from(lr[1] & 0xf) << 8 | usize::from(lr[0])
+    }
+
+    fn right(&self, data: &[u8], sym: usize) -> usize {
+        let lr = &data[self.btree + sym * 3..];
+        usize::from(lr[2]) << 4 | usize::from(lr[1] >> 4)
+    }
+
+    // The value stored at a table index
+    fn decompress(&self, data: &[u8], idx: u64) -> Option<i32> {
+        if self.flags & FLAG_SINGLE_VALUE != 0 {
+            return Some(i32::from(self.min_sym_len));
+        }
+
+        // The sparse index points at a block and offset for every span
+        // values; walk from there to the block holding idx
+        let k = (idx / self.span) as usize;
+        let mut block = u64::from(read_u32_le(data, self.sparse_index + k * 6)?);
+        let mut offset = i64::from(read_u16_le(data, self.sparse_index + k * 6 + 4)?);
+        offset += (idx % self.span) as i64 - (self.span / 2) as i64;
+
+        let block_length = |block: u64| -> Option<i64> {
+            if block >= self.block_length_size {
+                return None;
+            }
+            read_u16_le(data, self.block_length + 2 * block as usize).map(i64::from)
+        };
+        while offset < 0 {
+            block = block.checked_sub(1)?;
+            offset += block_length(block)? + 1;
+        }
+        while offset > block_length(block)? {
+            offset -= block_length(block)? + 1;
+            block += 1;
+        }
+
+        // Decode Huffman symbols from the start of the block until the one
+        // whose expansion covers our offset
+        let mut ptr = self.data + (block * self.block_size) as usize;
+        let mut buf64 = read_u64_be(data, ptr)?;
+        ptr += 8;
+        let mut buf64_size = 64;
+        let min_sym_len = u32::from(self.min_sym_len);
+        let mut sym;
+        loop {
+            let mut len = 0;
+            while len + 1 < self.base64.len() && buf64 < self.base64[len] {
+                len += 1;
+            }
+            let shift = 64 - len as u32 - min_sym_len;
+            sym = buf64.checked_sub(self.base64[len])?.checked_shr(shift).unwrap_or(0) as usize;
+            sym += usize::from(read_u16_le(data, self.lowest_sym + 2 * len)?);
+            let expansion = i64::from(*self.symlen.get(sym)?) + 1;
+            if offset < expansion {
+                break;
+            }
+            offset -= expansion;
+            let consumed = len as u32 + min_sym_len;
+            buf64 = buf64.checked_shl(consumed).unwrap_or(0);
+            buf64_size -= consumed;
+            if buf64_size <= 32 {
+                buf64_size += 32;
+                buf64 |= u64::from(read_u32_be(data, ptr)?) << (64 - buf64_size);
+                ptr += 4;
+            }
+        }
+
+        // Walk down the pair tree to the single value at our offset
+        while self.symlen[sym] != 0 {
+            let left = self.left(data, sym);
+            let left_expansion = i64::from(*self.symlen.get(left)?) + 1;
+            if offset < left_expansion {
+                sym = left;
+            } else {
+                offset -= left_expansion;
+                sym = self.right(data, sym);
+            }
+        }
+        Some(self.left(data, sym) as i32)
+    }
+}
+
+// A loaded .rtbw or .rtbz file
+struct Table {
+    kind: TableKind,
+    data: Vec<u8>,
+    material: Material,
+    // Indexed by side to move, then by the file of the leading pawn
+    pairs: Vec<Vec<PairsData>>,
+    // Start of the DTZ value maps
+    map: usize,
+}
+
+impl Table {
+    fn parse(data: Vec<u8>, kind: TableKind, material: Material) -> Result<Table, &'static str> {
+        let magic = match kind {
+            TableKind::Wdl => WDL_MAGIC,
+            TableKind::Dtz => DTZ_MAGIC
+        };
+        if data.len() < 5 || data[0..4] != magic {
+            return Err("Not a Syzygy table");
+        }
+        let has_pawns = material.has_pawns();
+        if (data[4] & 2 != 0) != has_pawns {
+            return Err("Table does not match its material");
+        }
+        if material.piece_count() > 7 {
+            return Err("Too many pieces for a Syzygy table");
+        }
+
+        let mut table = Table{kind, data: Vec::new(), material, pairs: Vec::new(), map: 0};
+        let sides = if kind == TableKind::Wdl && !table.material.is_symmetric() { 2 } else { 1 };
+        let files = if has_pawns { 4 } else { 1 };
+        let both_pawns = has_pawns && table.material.pawn_count()[1] > 0;
+        let truncated = "Truncated Syzygy table";
+        let mut pairs = vec![vec![PairsData::default(); files]; sides];
+
+        let mut pos = 5;
+        for file in 0..files {
+            let first = read_u8(&data, pos).ok_or(truncated)?;
+            let second = if both_pawns { read_u8(&data, pos + 1).ok_or(truncated)? } else { 0xff };
+            let order = [[first & 0xf, second & 0xf], [first >> 4, second >> 4]];
+            pos += if both_pawns { 2 } else { 1 };
+            for k in 0..table.material.piece_count() {
+                let codes = read_u8(&data, pos).ok_or(truncated)?;
+                for (side, side_pairs) in pairs.iter_mut().enumerate() {
+                    side_pairs[file].pieces[k] = if side == 1 { codes >> 4 } else { codes & 0xf };
+                }
+                pos += 1;
+            }
+            for (side, side_pairs) in pairs.iter_mut().enumerate() {
+                table.set_groups(&mut side_pairs[file], order[side], file);
+            }
+        }
+        pos += pos & 1;
+
+        for file in 0..files {
+            for side_pairs in pairs.iter_mut() {
+                pos = side_pairs[file].set_sizes(&data, pos).ok_or(truncated)?;
+            }
+        }
+
+        if kind == TableKind::Dtz {
+            table.map = pos;
+            for pairs_data in pairs[0].iter_mut() {
+                if pairs_data.flags & FLAG_MAPPED == 0 {
+                    continue;
+                }
+                if pairs_data.flags & FLAG_WIDE != 0 {
+                    pos += pos & 1;
+                    for i in 0..4 {
+                        pairs_data.map_idx[i] = (pos - table.map) / 2 + 1;
+                        pos += 2 * usize::from(read_u16_le(&data, pos).ok_or(truncated)?) + 2;
+                    }
+                } else {
+                    for i in 0..4 {
+                        pairs_data.map_idx[i] = pos - table.map + 1;
+                        pos += usize::from(read_u8(&data, pos).ok_or(truncated)?) + 1;
+                    }
+                }
+            }
+            pos += pos & 1;
+        }
+
+        for file in 0..files {
+            for side_pairs in pairs.iter_mut() {
+                side_pairs[file].sparse_index = pos;
+                pos += side_pairs[file].sparse_index_size as usize * 6;
+            }
+        }
+        for file in 0..files {
+            for side_pairs in pairs.iter_mut() {
+                side_pairs[file].block_length = pos;
+                pos += side_pairs[file].block_length_size as usize * 2;
+            }
+        }
+        for file in 0..files {
+            for side_pairs in pairs.iter_mut() {
+                pos = (pos + 0x3f) & !0x3f;
+                side_pairs[file].data = pos;
+                pos += (side_pairs[file].blocks * side_pairs[file].block_size) as usize;
+            }
+        }
+        if pos > data.len() + 0x3f {
+            return Err(truncated);
+        }
+
+        table.data = data;
+        table.pairs = pairs;
+        Ok(table)
+    }
+
+    // Split the pieces into groups and work out the multiplier of each group.
+    // Leading pieces (or pawns) are encoded together; identical pieces after
+    // them share a group. The group order comes from the table header.
+    fn set_groups(&self, d: &mut PairsData, order: [u8; 2], file: usize) {
+        let enc = encoding();
+        let has_pawns = self.material.has_pawns();
+        let unique = self.material.has_unique_pieces();
+        let mut n = 0;
+        let mut first_len: i32 = if has_pawns { 0 } else if unique { 3 } else { 2 };
+        d.group_len[0] = 1;
+        for i in 1..self.material.piece_count() {
+            first_len -= 1;
+            if first_len > 0 || d.pieces[i] == d.pieces[i - 1] {
+                d.group_len[n] += 1;
+            } else {
+                n += 1;
+                d.group_len[n] = 1;
+            }
+        }
+        n += 1;
+        d.group_len[n] = 0;
+
+        let both_pawns = has_pawns && self.material.pawn_count()[1] > 0;
+        let mut next = if both_pawns { 2 } else { 1 };
+        let mut free_squares = 64 - d.group_len[0] - if both_pawns { d.group_len[1] } else { 0 };
+        let mut idx: u64 = 1;
+        let mut k = 0;
+        while next < n || k == order[0] || k == order[1] {
+            if k == order[0] {
+                d.group_idx[0] = idx;
+                idx *= if has_pawns {
+                    enc.lead_pawns_size[d.group_len[0]][file]
+                } else if unique {
+                    31332
+                } else {
+                    462
+                };
+            } else if k == order[1] {
+                d.group_idx[1] = idx;
+                idx *= enc.binomial[d.group_len[1]][48 - d.group_len[0]];
+            } else {
+                d.group_idx[next] = idx;
+                idx *= enc.binomial[d.group_len[next]][free_squares];
+                free_squares -= d.group_len[next];
+                next += 1;
+            }
+            k += 1;
+        }
+        d.group_idx[n] = idx;
+    }
+
+    fn pairs(&self, stm: usize, file: usize) -> &PairsData {
+        let side = if self.pairs.len() == 1 { 0 } else { stm };
+        &self.pairs[side][if self.material.has_pawns() { file } else { 0 }]
+    }
+
+    // Turn piece squares (already flipped to the table's colors) into the
+    // table index. Lead pawns come first, everything else is sorted into the
+    // order of d.pieces. Squares and pieces are reordered in place.
+    fn encode(&self, d: &PairsData, squares: &mut [usize], pieces: &mut [u8], lead_pawns: usize) -> u64 {
+        let enc = encoding();
+        let size = squares.len();
+        for i in lead_pawns..size.saturating_sub(1) {
+            for j in i + 1..size {
+                if d.pieces[i] == pieces[j] {
+                    pieces.swap(i, j);
+                    squares.swap(i, j);
+                    break;
+                }
+            }
+        }
+
+        // Mirror so the leading piece is on files a-d
+        if file_of(squares[0]) > 3 {
+            for sq in squares.iter_mut() {
+                *sq ^= 7;
+            }
+        }
+
+        let mut idx;
+        if self.material.has_pawns() {
+            idx = enc.lead_pawn_idx[lead_pawns][squares[0]];
+            squares[1..lead_pawns].sort_by_key(|sq| enc.map_pawns[*sq]);
+            for (i, sq) in squares.iter().enumerate().take(lead_pawns).skip(1) {
+                idx += enc.binomial[i][enc.map_pawns[*sq]];
+            }
+        } else {
+            // Without pawns also mirror into ranks 1-4 and below the diagonal
+            if rank_of(squares[0]) > 3 {
+                for sq in squares.iter_mut() {
+                    *sq ^= 56;
+                }
+            }
+            for i in 0..d.group_len[0] {
+                let off = off_diagonal(squares[i]);
+                if off == 0 {
+                    continue;
+                }
+                if off > 0 {
+                    for sq in squares[i..].iter_mut() {
+                        *sq = flip_diagonal(*sq);
+                    }
+                }
+                break;
+            }
+
+            if self.material.has_unique_pieces() {
+                let adjust1 = (squares[1] > squares[0]) as u64;
+                let adjust2 = (squares[2] > squares[0]) as u64 + (squares[2] > squares[1]) as u64;
+                let (s1, s2) = (squares[1] as u64, squares[2] as u64);
+                let (r0, r1, r2) = (rank_of(squares[0]) as u64, rank_of(squares[1]) as u64, rank_of(squares[2]) as u64);
+                idx = if off_diagonal(squares[0]) != 0 {
+                    (enc.map_a1d1d4[squares[0]] as u64 * 63 + (s1 - adjust1)) * 62 + s2 - adjust2
+                } else if off_diagonal(squares[1]) != 0 {
+                    (6 * 63 + r0 * 28 + enc.map_b1h1h7[squares[1]]) * 62 + s2 - adjust2
+                } else if off_diagonal(squares[2]) != 0 {
+                    6 * 63 * 62 + 4 * 28 * 62 + r0 * 7 * 28 + (r1 - adjust1) * 28 + enc.map_b1h1h7[squares[2]]
+                } else {
+                    6 * 63 * 62 + 4 * 28 * 62 + 4 * 7 * 28 + r0 * 7 * 6 + (r1 - adjust1) * 6 + (r2 - adjust2)
+                };
+            } else {
+                idx = enc.map_kk[enc.map_a1d1d4[squares[0]]][squares[1]];
+            }
+        }
+
+        idx *= d.group_idx[0];
+        let mut start = d.group_len[0];
+        let mut remaining_pawns = self.material.has_pawns() && self.material.pawn_count()[1] > 0;
+        let mut next = 1;
+        while d.group_len[next] != 0 {
+            let len = d.group_len[next];
+            squares[start..start + len].sort_unstable();
+            let mut n = 0;
+            for i in 0..len {
+                // Skip over the squares taken by earlier groups
+                let sq = squares[start + i];
+                let adjust = squares[..start].iter().filter(|earlier| sq > **earlier).count();
+                n += enc.binomial[i + 1][sq - adjust - if remaining_pawns { 8 } else { 0 }];
+            }
+            remaining_pawns = false;
+            idx += n * d.group_idx[next];
+            start += len;
+            next += 1;
+        }
+        idx
+    }
+
+    // Probe the position, with colors flipped if the table's white pieces are
+    // black's on the board. Returns None for DTZ tables that only store the
+    // other side to move.
+    fn probe(&self, board: &Board, flip: bool, wdl: Wdl) -> Option<Option<i32>> {
+        let enc = encoding();
+        let flip_color = if flip { 8 } else { 0 };
+        let flip_squares = if flip { 56 } else { 0 };
+        let stm = (flip != (board.side_to_move() == Color::Black)) as usize;
+
+        let mut placed: Vec<(usize, u8)> = board.pieces().map(|square| {
+            let sq = usize::from(square.rank.value()) * 8 + usize::from(square.file.value());
+            (sq, piece_code(square.piece.unwrap()))
+        }).collect();
+
+        let mut squares = Vec::with_capacity(placed.len());
+        let mut pieces = Vec::with_capacity(placed.len());
+        let mut lead_pawns = 0;
+        let mut file = 0;
+        if self.material.has_pawns() {
+            // The first piece of a pawn table is a pawn of the leading color
+            let lead = self.pairs[0][0].pieces[0] ^ flip_color;
+            for (sq, code) in placed.iter().filter(|(_, code)| *code == lead) {
+                squares.push(sq ^ flip_squares);
+                pieces.push(code ^ flip_color);
+            }
+            placed.retain(|(_, code)| *code != lead);
+            lead_pawns = squares.len();
+            let mut best = 0;
+            for i in 1..lead_pawns {
+                if enc.map_pawns[squares[i]] > enc.map_pawns[squares[best]] {
+                    best = i;
+                }
+            }
+            squares.swap(0, best);
+            file = file_of(squares[0]).min(7 - file_of(squares[0]));
+        }
+
+        if self.kind == TableKind::Dtz {
+            let stored = usize::from(self.pairs(stm, file).flags & FLAG_STM);
+            // Symmetric pawnless tables serve both sides to move
+            let both_sides = self.material.is_symmetric() && !self.material.has_pawns();
+            if stored != stm && !both_sides {
+                return Some(None);
+            }
+        }
+
+        for (sq, code) in placed {
+            squares.push(sq ^ flip_squares);
+            pieces.push(code ^ flip_color);
+        }
+        let d = self.pairs(stm, file);
+        let idx = self.encode(d, &mut squares, &mut pieces, lead_pawns);
+        let value = d.decompress(&self.data, idx)?;
+        match self.kind {
+            TableKind::Wdl => Some(Some(value - 2)),
+            TableKind::Dtz => self.map_score(d, value, wdl).map(Some)
+        }
+    }
+
+    // Turn a stored DTZ value into plies
+    fn map_score(&self, d: &PairsData, value: i32, wdl: Wdl) -> Option<i32> {
+        let mut value = value;
+        if d.flags & FLAG_MAPPED != 0 {
+            let map = match wdl {
+                Wdl::Loss => 1,
+                Wdl::BlessedLoss => 3,
+                Wdl::CursedWin => 2,
+                _ => 0
+            };
+            let idx = d.map_idx[map] + value as usize;
+            value = if d.flags & FLAG_WIDE != 0 {
+                i32::from(read_u16_le(&self.data, self.map + 2 * idx)?)
+            } else {
+                i32::from(read_u8(&self.data, self.map + idx)?)
+            };
+        }
+
+        let in_moves = match wdl {
+            Wdl::Win => d.flags & FLAG_WIN_PLIES == 0,
+            Wdl::Loss => d.flags & FLAG_LOSS_PLIES == 0,
+            Wdl::CursedWin | Wdl::BlessedLoss => true,
+            Wdl::Draw => false
+        };
+        if in_moves {
+            value *= 2;
+        }
+        Some(value + 1)
+    }
+}
+
+// Piece codes used inside table files
+fn piece_code(piece: Piece) -> u8 {
+    let code = match piece.piece_type() {
+        PieceType::Pawn => 1,
+        PieceType::Knight => 2,
+        PieceType::Bishop => 3,
+        PieceType::Rook => 4,
+        PieceType::Queen => 5,
+        PieceType::King => 6
+    };
+    match piece.get_color() {
+        Color::White => code,
+        Color::Black => code | 8
+    }
+}
+
+fn is_zeroing(board: &Board, mv: Move) -> bool {
+    board.is_capture(mv) || board.piece_at(mv.from_file, mv.from_rank).is_some_and(|piece| piece.piece_type() == PieceType::Pawn)
+}
+
+// DTZ of the move before a capture or pawn move with the given result
+fn dtz_before_zeroing(wdl: Wdl) -> i32 {
+    match wdl {
+        Wdl::Win => 1,
+        Wdl::CursedWin => 101,
+        Wdl::BlessedLoss => -101,
+        Wdl::Loss => -1,
+        Wdl::Draw => 0
+    }
+}
+
+// The tables found in a directory. Files are read into memory the first
+// time a position with their material is probed.
+pub struct Tablebase {
+    directory: PathBuf,
+    available: HashSet<String>,
+    max_pieces: usize,
+    tables: Mutex<HashMap<String, Option<Arc<Table>>>>,
+}
+
+impl Tablebase {
+    pub fn open<P: AsRef<Path>>(directory: P) -> io::Result<Tablebase> {
+        let directory = directory.as_ref().to_path_buf();
+        let mut available = HashSet::new();
+        let mut max_pieces = 0;
+        for entry in fs::read_dir(&directory)? {
+            let path = entry?.path();
+            let (stem, suffix) = match (path.file_stem().and_then(|s| s.to_str()), path.extension().and_then(|s| s.to_str())) {
+                (Some(stem), Some(suffix)) if suffix == WDL_SUFFIX || suffix == DTZ_SUFFIX => (stem, suffix),
+                _ => continue
+            };
+            if let Some(material) = Material::from_name(stem) {
+                if suffix == WDL_SUFFIX {
+                    max_pieces = max_pieces.max(material.piece_count());
+                }
+                available.insert(format!("{}.{}", stem, suffix));
+            }
+        }
+        Ok(Tablebase{directory, available, max_pieces, tables: Mutex::new(HashMap::new())})
+    }
+
+    // Most pieces of any WDL table found, 0 if there are none
+    pub fn max_pieces(&self) -> usize {
+        self.max_pieces
+    }
+
+    // Tables apply to positions without castling rights and no more pieces
+    // than the largest table
+    pub fn can_probe(&self, board: &Board) -> bool {
+        let pieces = board.pieces().count();
+        let castling = [Color::White, Color::Black].iter().any(|color| {
+            board.can_castle_kingside(*color) || board.can_castle_queenside(*color)
+        });
+        pieces <= self.max_pieces && !castling
+    }
+
+    // The lock is not held while a table is read, as tables can run to
+    // gigabytes. Threads that miss the same table at once each read it, and
+    // the first to finish is kept.
+    fn table(&self, file_name: &str, kind: TableKind, material: &Material) -> Option<Arc<Table>> {
+        let lock = || self.tables.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Some(table) = lock().get(file_name) {
+            return table.clone();
+        }
+        let table = fs::read(self.directory.join(file_name)).ok()
+            .and_then(|data| Table::parse(data, kind, material.clone()).ok())
+            .map(Arc::new);
+        lock().entry(file_name.to_string()).or_insert(table).clone()
+    }
+
+    // Probe the table for the board's material. Returns None if the table is
+    // missing or unreadable, Some(None) if a DTZ table stores the other side.
+    fn probe_table(&self, board: &Board, kind: TableKind, wdl: Wdl) -> Option<Option<i32>> {
+        let material = Material::from_board(board);
+        if material.piece_count() == 2 {
+            return Some(Some(0));
+        }
+        let (name, flip) = if material.is_symmetric() {
+            (material.name(), board.side_to_move() == Color::Black)
+        } else if self.available.contains(&format!("{}.{}", material.name(), kind.suffix())) {
+            (material.name(), false)
+        } else {
+            (material.swapped_name(), true)
+        };
+        let file_name = format!("{}.{}", name, kind.suffix());
+        if !self.available.contains(&file_name) {
+            return None;
+        }
+        let table_material = Material::from_name(&name)?;
+        self.table(&file_name, kind, &table_material)?.probe(board, flip, wdl)
+    }
+
+    // Tables may store anything for positions where a capture is best, so
+    // captures (and for DTZ, pawn moves) are searched and the best result
+    // wins. The flag tells if a zeroing move is known to be best.
+    fn search(&self, board: &mut Board, check_pawn_moves: bool) -> Option<(Wdl, bool)> {
+        let moves = board.legal_moves();
+        let mut best = Wdl::Loss;
+        let mut searched = 0;
+        for mv in moves.iter() {
+            let pawn_move = check_pawn_moves && is_zeroing(board, *mv);
+            if !board.is_capture(*mv) && !pawn_move {
+                continue;
+            }
+            searched += 1;
+            board.make_move(*mv);
+            let value = self.search(board, false);
+            board.unmake_move();
+            let value = -value?.0;
+            if value > best {
+                best = value;
+                if value == Wdl::Win {
+                    return Some((value, true));
+                }
+            }
+        }
+
+        // With every legal move searched the stored value isn't needed, and
+        // may be wrong if the position has en passant rights
+        let all_searched = searched > 0 && searched == moves.len();
+        let value = if all_searched {
+            best
+        } else {
+            Wdl::from_value(self.probe_table(board, TableKind::Wdl, Wdl::Draw)??)
+        };
+        if best >= value {
+            return Some((best, best > Wdl::Draw || all_searched));
+        }
+        Some((value, false))
+    }
+
+    fn probe_wdl(&self, board: &mut Board) -> Option<Wdl> {
+        self.search(board, false).map(|(wdl, _)| wdl)
+    }
+
+    // Plies to the next capture or pawn move with best play, positive when
+    // winning and negative when losing, counting a win after zeroing as 1
+    fn probe_dtz(&self, board: &mut Board) -> Option<i32> {
+        let (wdl, zeroing_best) = self.search(board, true)?;
+        if wdl == Wdl::Draw {
+            return Some(0);
+        }
+        if zeroing_best {
+            return Some(dtz_before_zeroing(wdl));
+        }
+
+        if let Some(dtz) = self.probe_table(board, TableKind::Dtz, wdl)? {
+            let cursed = wdl == Wdl::CursedWin || wdl == Wdl::BlessedLoss;
+            return Some((dtz + if cursed { 100 } else { 0 }) * wdl.sign());
+        }
+
+        // The table stores the other side to move, so look one ply ahead for
+        // the move that keeps the result with the smallest DTZ
+        let mut min_dtz = 0xffff;
+        for mv in board.legal_moves() {
+            let zeroing = is_zeroing(board, mv);
+            board.make_move(mv);
+            let dtz = if zeroing {
+                self.search(board, false).map(|(wdl, _)| -dtz_before_zeroing(wdl))
+            } else {
+                self.probe_dtz(board).map(|dtz| -dtz)
+            };
+            let mates = dtz == Some(1) && board.is_checkmate();
+            board.unmake_move();
+            let mut dtz = dtz?;
+
+            if mates {
+                min_dtz = 1;
+            }
+            if !zeroing {
+                dtz += dtz.signum();
+            }
+            if dtz < min_dtz && dtz.signum() == wdl.sign() {
+                min_dtz = dtz;
+            }
+        }
+        Some(if min_dtz == 0xffff { -1 } else { min_dtz })
+    }
+
+    // Rank every legal move by its DTZ, or by WDL alone if DTZ tables are
+    // missing. Wins that can be converted within the fifty-move rule all rank
+    // highest; losing moves that reach a fifty-move draw rank above others.
+    fn rank_root_moves(&self, board: &mut Board) -> Option<Vec<TablebaseMove>> {
+        if !self.can_probe(board) {
+            return None;
+        }
+        let mut res = self.rank_by_dtz(board).or_else(|| self.rank_by_wdl(board))?;
+        res.sort_by_key(|tb_move| Reverse(tb_move.rank));
+        Some(res)
+    }
+
+    fn rank_by_dtz(&self, board: &mut Board) -> Option<Vec<TablebaseMove>> {
+        let halfmove_clock = board.halfmove_clock() as i32;
+        let mut res = Vec::new();
+        for mv in board.legal_moves() {
+            board.make_move(mv);
+            let dtz = if board.halfmove_clock() == 0 {
+                self.probe_wdl(board).map(|wdl| dtz_before_zeroing(-wdl))
+            } else if board.halfmove_clock() >= 100 {
+                Some(0)
+            } else {
+                self.probe_dtz(board).map(|dtz| -dtz + (-dtz).signum())
+            };
+            let mates = board.is_checkmate();
+            board.unmake_move();
+            let mut dtz = dtz?;
+            if mates && dtz == 2 {
+                dtz = 1;
+            }
+
+            let rank = if dtz > 0 {
+                if dtz + halfmove_clock <= 99 { MAX_DTZ } else { MAX_DTZ - (dtz + halfmove_clock) }
+            } else if dtz < 0 {
+                if -dtz * 2 + halfmove_clock < 100 { -MAX_DTZ } else { -MAX_DTZ + (-dtz + halfmove_clock) }
+            } else {
+                0
+            };
+            let wdl = if rank >= MAX_DTZ - 100 {
+                Wdl::Win
+            } else if rank > 0 {
+                Wdl::CursedWin
+            } else if rank == 0 {
+                Wdl::Draw
+            } else if rank > -MAX_DTZ + 100 {
+                Wdl::BlessedLoss
+            } else {
+                Wdl::Loss
+            };
+            res.push(TablebaseMove{mv, wdl, dtz, rank});
+        }
+        Some(res)
+    }
+
+    fn rank_by_wdl(&self, board: &mut Board) -> Option<Vec<TablebaseMove>> {
+        let mut res = Vec::new();
+        for mv in board.legal_moves() {
+            board.make_move(mv);
+            let wdl = self.probe_wdl(board).map(|wdl| -wdl);
+            board.unmake_move();
+            let wdl = wdl?;
+            let rank = match wdl {
+                Wdl::Win => MAX_DTZ,
+                Wdl::CursedWin => MAX_DTZ - 101,
+                Wdl::Draw => 0,
+                Wdl::BlessedLoss => -MAX_DTZ + 101,
+                Wdl::Loss => -MAX_DTZ
+            };
+            res.push(TablebaseMove{mv, wdl, dtz: 0, rank});
+        }
+        Some(res)
+    }
+}
+
+impl Board {
+    // Win, draw or loss for the side to move, if the tables cover the position
+    pub fn probe_wdl(&mut self, tablebase: &Tablebase) -> Option<Wdl> {
+        if !tablebase.can_probe(self) {
+            return None;
+        }
+        tablebase.probe_wdl(self)
+    }
+
+    // Distance in plies to the next capture or pawn move under best play,
+    // positive if the side to move wins, negative if it loses and 0 if drawn
+    pub fn probe_dtz(&mut self, tablebase: &Tablebase) -> Option<i32> {
+        if !tablebase.can_probe(self) {
+            return None;
+        }
+        tablebase.probe_dtz(self)
+    }
+
+    // Legal moves ranked best first by the tables
+    pub fn tablebase_root_moves(&mut self, tablebase: &Tablebase) -> Option<Vec<TablebaseMove>> {
+        tablebase.rank_root_moves(self)
+    }
+
+    // The root moves that keep the best tablebase result: moves that win
+    // without risking the fifty-move rule, or failing that hold the draw
+    pub fn tablebase_best_moves(&mut self, tablebase: &Tablebase) -> Option<Vec<TablebaseMove>> {
+        let moves = tablebase.rank_root_moves(self)?;
+        let best = moves.first()?.rank;
+        Some(moves.into_iter().filter(|tb_move| tb_move.rank == best).collect())
+    }
+}
+
+#[cfg(test)]
+mod syzygy_tests {
+    use super::*;
+
+    // A KQvK WDL table where every position is won for the side with the
+    // queen to move and lost for the other side
+    fn kqk_wdl() -> Vec<u8> {
+        let mut data = WDL_MAGIC.to_vec();
+        // Split into two sides, no pawns, leading group first for both sides
+        data.extend_from_slice(&[0x01, 0x00]);
+        // Pieces in encoding order for both sides: white king, queen, black king
+        data.extend_from_slice(&[0x66, 0x55, 0xee]);
+        // Word alignment, then single value sub-tables: win (4) and loss (0)
+        data.push(0);
+        data.extend_from_slice(&[FLAG_SINGLE_VALUE, 4, FLAG_SINGLE_VALUE, 0]);
+        data
+    }
+
+    // A KQvK DTZ table storing white to move, with every win 4 moves from zeroing
+    fn kqk_dtz() -> Vec<u8> {
+        let mut data = DTZ_MAGIC.to_vec();
+        data.extend_from_slice(&[0x01, 0x00, 0x66, 0x55, 0xee, 0]);
+        data.extend_from_slice(&[FLAG_SINGLE_VALUE, 4]);
+        data
+    }
+
+    // Tables written to a directory of their own, removed when the test is
+    // done with them
+    struct TestTablebase {
+        tablebase: Tablebase,
+        directory: PathBuf,
+    }
+
+    impl std::ops::Deref for TestTablebase {
+        type Target = Tablebase;
+
+        fn deref(&self) -> &Tablebase {
+            &self.tablebase
+        }
+    }
+
+    impl Drop for TestTablebase {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.directory);
+        }
+    }
+
+    fn tablebase(name: &str, files: &[(&str, Vec<u8>)]) -> TestTablebase {
+        static NEXT: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+        let unique = NEXT.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        let directory = std::env::temp_dir().join(format!("chess-engine-syzygy-{}-{}-{}", name, std::process::id(), unique));
+        fs::create_dir_all(&directory).unwrap();
+        for (file_name, data) in files {
+            fs::write(directory.join(file_name), data).unwrap();
+        }
+        TestTablebase{tablebase: Tablebase::open(&directory).unwrap(), directory}
+    }
+
+    fn material(name: &str) -> Material {
+        Material::from_name(name).unwrap()
+    }
+
+    #[test]
+    fn encoding_tables() {
+        let enc = encoding();
+        assert_eq!(enc.binomial[2][5], 10);
+        assert_eq!(enc.binomial[5][63], 7028847);
+        assert_eq!(enc.map_kk.iter().flat_map(|row| row.iter()).max(), Some(&461));
+        assert_eq!(enc.map_pawns[8], 47);
+        assert_eq!(enc.map_pawns[15], 46);
+        assert_eq!((8..56).map(|sq| enc.map_pawns[sq]).min(), Some(0));
+        assert_eq!(enc.lead_pawns_size[1].iter().sum::<u64>(), 24);
+    }
+
+    #[test]
+    fn material_names() {
+        let board = Board::from_fen("8/8/8/3k4/8/1r6/P7/KR6 w - - 0 1").unwrap();
+        let material = Material::from_board(&board);
+        assert_eq!(material.name(), "KRPvKR");
+        assert_eq!(material.swapped_name(), "KRvKRP");
+        assert_eq!(material.pawn_count(), [1, 0]);
+        assert!(material.has_unique_pieces());
+        assert!(!self::material("KRRvK").has_unique_pieces());
+        assert!(self::material("KRvKR").is_symmetric());
+        assert_eq!(self::material("KPvKPP").pawn_count(), [1, 2]);
+        assert_eq!(self::material("KPPvKP").pawn_count(), [1, 2]);
+        assert!(Material::from_name("KQvQ").is_none());
+        assert!(Material::from_name("KXvK").is_none());
+    }
+
+    // Every placement must land inside the table, and two placements must
+    // share an index exactly when they are mirror images of each other
+    fn check_encoding(table: &Table, pieces: &[u8], transforms: &[fn(usize) -> usize], valid: fn(&[usize]) -> bool) {
+        let d = &table.pairs[0][0];
+        let mut by_index: HashMap<u64, Vec<usize>> = HashMap::new();
+        let mut by_position: HashMap<Vec<usize>, u64> = HashMap::new();
+        for a in 0..64 {
+            for b in 0..64 {
+                for c in 0..64 {
+                    let placement = [a, b, c];
+                    if a == b || a == c || b == c || !valid(&placement) {
+                        continue;
+                    }
+                    let mut squares = placement.to_vec();
+                    let mut codes = pieces.to_vec();
+                    let lead_pawns = if table.material.has_pawns() { 1 } else { 0 };
+                    let idx = table.encode(d, &mut squares, &mut codes, lead_pawns);
+                    assert!(idx < d.table_size(), "{:?} encodes to {}", placement, idx);
+
+                    let canonical = transforms.iter().map(|t| placement.iter().map(|sq| t(*sq)).collect::<Vec<usize>>()).min().unwrap();
+                    assert_eq!(*by_index.entry(idx).or_insert_with(|| canonical.clone()), canonical);
+                    assert_eq!(*by_position.entry(canonical).or_insert(idx), idx);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn pawnless_encoding_is_a_bijection_up_to_symmetry() {
+        let table = Table::parse(kqk_wdl(), TableKind::Wdl, material("KQvK")).unwrap();
+        assert_eq!(table.pairs[0][0].table_size(), 31332);
+        let transforms: [fn(usize) -> usize; 8] = [
+            |sq| sq, |sq| sq ^ 7, |sq| sq ^ 56, |sq| sq ^ 63,
+            flip_diagonal, |sq| flip_diagonal(sq) ^ 7, |sq| flip_diagonal(sq) ^ 56, |sq| flip_diagonal(sq) ^ 63,
+        ];
+        check_encoding(&table, &[6, 5, 14], &transforms, |_| true);
+    }
+
+    #[test]
+    fn pawn_encoding_is_a_bijection_up_to_symmetry() {
+        let mut data = WDL_MAGIC.to_vec();
+        data.push(0x03);
+        for _ in 0..4 {
+            data.extend_from_slice(&[0x00, 0x11, 0x66, 0xee]);
+        }
+        data.push(0);
+        for _ in 0..8 {
+            data.extend_from_slice(&[FLAG_SINGLE_VALUE, 2]);
+        }
+        let table = Table::parse(data, TableKind::Wdl, material("KPvK")).unwrap();
+        assert_eq!(table.pairs[0][0].table_size(), 6 * 63 * 62);
+        let transforms: [fn(usize) -> usize; 2] = [|sq| sq, |sq| sq ^ 7];
+        // Only the a-file table is checked, so keep the pawn on files a and h
+        check_encoding(&table, &[1, 6, 14], &transforms, |placement| {
+            (1..7).contains(&rank_of(placement[0])) && (file_of(placement[0]) == 0 || file_of(placement[0]) == 7)
+        });
+    }
+
+    #[test]
+    fn decompresses_huffman_coded_values() {
+        // Side one stores its values in a single block with a one bit code:
+        // symbol 0 is a draw (2) and symbol 1 a win (4), win when idx % 3 == 0
+        let mut data = WDL_MAGIC.to_vec();
+        data.extend_from_slice(&[0x01, 0x00, 0x66, 0x55, 0xee, 0]);
+        data.extend_from_slice(&[0, 12, 15, 0, 1, 0, 0, 0, 1, 1, 0, 0, 2, 0]);
+        data.extend_from_slice(&[0x02, 0xf0, 0xff, 0x04, 0xf0, 0xff]);
+        data.extend_from_slice(&[FLAG_SINGLE_VALUE, 0]);
+        // Sparse index pointing at the middle of the first span, block length
+        data.extend_from_slice(&[0, 0, 0, 0, 0x00, 0x40]);
+        data.extend_from_slice(&(31332u16 - 1).to_le_bytes());
+        data.resize(data.len().div_ceil(64) * 64, 0);
+        let mut block = vec![0u8; 4096];
+        for idx in (0..31332).step_by(3) {
+            block[idx / 8] |= 0x80 >> (idx % 8);
+        }
+        data.extend_from_slice(&block);
+
+        let table = Table::parse(data, TableKind::Wdl, material("KQvK")).unwrap();
+        let d = &table.pairs[0][0];
+        for idx in (0..31332u64).step_by(997).chain([1, 2, 31330, 31331]) {
+            let expected = if idx.is_multiple_of(3) { 4 } else { 2 };
+            assert_eq!(d.decompress(&table.data, idx), Some(expected), "idx {}", idx);
+        }
+
+        let board = Board::from_fen("8/8/8/8/8/8/1Q6/K6k w - - 0 1").unwrap();
+        let mut squares = vec![0, 9, 7];
+        let idx = table.encode(d, &mut squares, &mut [6, 5, 14], 0);
+        let expected = if idx.is_multiple_of(3) { 2 } else { 0 };
+        assert_eq!(table.probe(&board, false, Wdl::Draw), Some(Some(expected)));
+    }
+
+    #[test]
+    fn rejects_bad_tables() {
+        assert!(Table::parse(vec![0; 16], TableKind::Wdl, material("KQvK")).is_err());
+        assert!(Table::parse(kqk_wdl(), TableKind::Dtz, material("KQvK")).is_err());
+        assert!(Table::parse(kqk_wdl()[..8].to_vec(), TableKind::Wdl, material("KQvK")).is_err());
+    }
+
+    #[test]
+    fn missing_tables_give_no_result() {
+        let tablebase = tablebase("empty", &[]);
+        assert_eq!(tablebase.max_pieces(), 0);
+        let mut board = Board::from_fen("8/8/8/8/8/8/1Q6/K6k w - - 0 1").unwrap();
+        assert_eq!(board.probe_wdl(&tablebase), None);
+        assert_eq!(board.probe_dtz(&tablebase), None);
+        assert_eq!(board.tablebase_root_moves(&tablebase), None);
+        assert!(Tablebase::open("/nonexistent/syzygy").is_err());
+    }
+
+    #[test]
+    fn probes_wdl_with_colors_flipped_and_captures_resolved() {
+        let tablebase = tablebase("wdl", &[("KQvK.rtbw", kqk_wdl())]);
+        assert_eq!(tablebase.max_pieces(), 3);
+
+        let mut white = Board::from_fen("8/8/8/8/8/8/1Q6/K6k w - - 0 1").unwrap();
+        assert_eq!(white.probe_wdl(&tablebase), Some(Wdl::Win));
+        let mut black = Board::from_fen("8/8/8/8/8/8/1Q6/K6k b - - 0 1").unwrap();
+        assert_eq!(black.probe_wdl(&tablebase), Some(Wdl::Loss));
+        // Same material with the colors swapped reads the table flipped
+        let mut flipped = Board::from_fen("k6K/1q6/8/8/8/8/8/8 b - - 0 1").unwrap();
+        assert_eq!(flipped.probe_wdl(&tablebase), Some(Wdl::Win));
+        // Black can take the undefended queen, whatever the table says
+        let mut hanging = Board::from_fen("8/8/8/8/8/8/1Qk5/7K b - - 0 1").unwrap();
+        assert_eq!(hanging.probe_wdl(&tablebase), Some(Wdl::Draw));
+        // Bare kings are drawn without any table
+        let mut bare = Board::from_fen("8/8/8/8/8/8/8/K6k w - - 0 1").unwrap();
+        assert_eq!(bare.probe_wdl(&tablebase), Some(Wdl::Draw));
+        // Positions with castling rights are never in the tables
+        let mut castling = Board::from_fen("8/8/8/8/8/8/8/R3K2k w Q - 0 1").unwrap();
+        assert_eq!(castling.probe_wdl(&tablebase), None);
+    }
+
+    #[test]
+    fn probes_dtz_for_both_sides_to_move() {
+        let tablebase = tablebase("dtz", &[("KQvK.rtbw", kqk_wdl()), ("KQvK.rtbz", kqk_dtz())]);
+        let mut white = Board::from_fen("8/8/8/8/8/8/1Q6/K6k w - - 0 1").unwrap();
+        assert_eq!(white.probe_dtz(&tablebase), Some(9));
+        // Black to move is not stored, so it is found one ply ahead
+        let mut black = Board::from_fen("8/8/8/8/8/8/1Q6/K6k b - - 0 1").unwrap();
+        assert_eq!(black.probe_dtz(&tablebase), Some(-10));
+        let mut hanging = Board::from_fen("8/8/8/8/8/8/1Qk5/7K b - - 0 1").unwrap();
+        assert_eq!(hanging.probe_dtz(&tablebase), Some(0));
+    }
+
+    #[test]
+    fn root_moves_keep_the_win() {
+        let tables = tablebase("root", &[("KQvK.rtbw", kqk_wdl()), ("KQvK.rtbz", kqk_dtz())]);
+        let mut board = Board::from_fen("8/8/8/8/8/2k5/8/KQ6 w - - 0 1").unwrap();
+        let moves = board.tablebase_root_moves(&tables).unwrap();
+        assert_eq!(moves.len(), board.legal_moves().len());
+        assert!(moves.windows(2).all(|pair| pair[0].rank >= pair[1].rank));
+
+        let best = board.tablebase_best_moves(&tables).unwrap();
+        assert!(best.iter().all(|tb_move| tb_move.wdl == Wdl::Win && tb_move.dtz == 11));
+        // Qc2+ hangs the queen to the black king
+        let hanging = Move::from_uci("b1c2").unwrap();
+        assert!(!best.iter().any(|tb_move| tb_move.mv == hanging));
+        assert_eq!(moves.iter().find(|tb_move| tb_move.mv == hanging).unwrap().wdl, Wdl::Draw);
+
+        // Without DTZ tables moves are ranked by WDL alone
+        let wdl_only = tablebase("root-wdl", &[("KQvK.rtbw", kqk_wdl())]);
+        let best = board.tablebase_best_moves(&wdl_only).unwrap();
+        assert!(best.iter().all(|tb_move| tb_move.wdl == Wdl::Win));
+        assert!(!best.iter().any(|tb_move| tb_move.mv == hanging));
+    }
+}
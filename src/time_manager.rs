@@ -0,0 +1,138 @@
+use crate::search::SearchLimits;
+
+use std::time::Duration;
+use std::time::Instant;
+
+// Time kept back for communication and move overhead
+const MOVE_OVERHEAD: Duration = Duration::from_millis(30);
+// Moves the remaining clock is assumed to last when no moves-to-go is given
+const DEFAULT_MOVES_TO_GO: u32 = 30;
+
+// Decides how long a search may run. New iterations only start before the
+// soft limit; the hard limit interrupts an iteration in progress. While
+// pondering neither applies, and the clock starts on ponderhit.
+#[derive(Debug, Clone)]
+pub struct TimeManager {
+    start: Instant,
+    soft_limit: Option<Duration>,
+    hard_limit: Option<Duration>,
+    pondering: bool,
+}
+
+impl TimeManager {
+    pub fn new(limits: &SearchLimits) -> TimeManager {
+        let (soft_limit, hard_limit) = if let Some(movetime) = limits.movetime {
+            (Some(movetime), Some(movetime))
+        } else if let Some(time_left) = limits.time_left {
+            let usable = time_left.saturating_sub(MOVE_OVERHEAD);
+            let moves = limits.moves_to_go.unwrap_or(DEFAULT_MOVES_TO_GO).max(1);
+            let soft = (usable / moves + limits.increment * 3 / 4).min(usable);
+            // Allow an iteration to run on past its share, but never use
+            // more than half the clock unless this is the last move before
+            // the time control
+            let hard = (soft * 4).min(usable / 2).max(soft);
+            (Some(soft), Some(hard))
+        } else {
+            (None, None)
+        };
+        TimeManager{start: Instant::now(), soft_limit, hard_limit, pondering: limits.ponder}
+    }
+
+    pub fn is_pondering(&self) -> bool {
+        self.pondering
+    }
+
+    // The opponent played the move being pondered on: from now on the
+    // search runs on our own clock
+    pub fn ponderhit(&mut self) {
+        self.pondering = false;
+        self.start = Instant::now();
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    pub fn soft_limit(&self) -> Option<Duration> {
+        self.soft_limit
+    }
+
+    pub fn hard_limit(&self) -> Option<Duration> {
+        self.hard_limit
+    }
+
+    // Whether there is time to begin another iteration
+    pub fn can_start_iteration(&self) -> bool {
+        self.pondering || self.soft_limit.is_none_or(|limit| self.elapsed() < limit)
+    }
+
+    // Whether the search must stop now
+    pub fn out_of_time(&self) -> bool {
+        !self.pondering && self.hard_limit.is_some_and(|limit| self.elapsed() >= limit)
+    }
+}
+
+#[cfg(test)]
+mod time_manager_tests {
+    use super::*;
+
+    #[test]
+    fn movetime_is_a_fixed_budget() {
+        let mut limits = SearchLimits::new();
+        limits.movetime = Some(Duration::from_millis(500));
+        let time = TimeManager::new(&limits);
+        assert_eq!(time.soft_limit(), Some(Duration::from_millis(500)));
+        assert_eq!(time.hard_limit(), Some(Duration::from_millis(500)));
+        assert!(time.can_start_iteration());
+        assert!(!time.out_of_time());
+    }
+
+    #[test]
+    fn allocates_from_the_clock() {
+        let mut limits = SearchLimits::new();
+        limits.time_left = Some(Duration::from_millis(60_030));
+        limits.increment = Duration::from_millis(1000);
+        let time = TimeManager::new(&limits);
+        // 60s over 30 moves plus three quarters of the increment
+        assert_eq!(time.soft_limit(), Some(Duration::from_millis(2750)));
+        assert_eq!(time.hard_limit(), Some(Duration::from_millis(11_000)));
+
+        limits.moves_to_go = Some(1);
+        let time = TimeManager::new(&limits);
+        assert_eq!(time.soft_limit(), Some(Duration::from_millis(60_000)));
+        assert_eq!(time.hard_limit(), Some(Duration::from_millis(60_000)));
+    }
+
+    #[test]
+    fn nearly_flagged_clock_stops_at_once() {
+        let mut limits = SearchLimits::new();
+        limits.time_left = Some(Duration::from_millis(10));
+        let time = TimeManager::new(&limits);
+        assert_eq!(time.hard_limit(), Some(Duration::ZERO));
+        assert!(time.out_of_time());
+    }
+
+    #[test]
+    fn no_clock_means_no_limit() {
+        let time = TimeManager::new(&SearchLimits::new());
+        assert_eq!(time.hard_limit(), None);
+        assert!(time.can_start_iteration());
+        assert!(!time.out_of_time());
+    }
+
+    #[test]
+    fn pondering_defers_the_clock() {
+        let mut limits = SearchLimits::new();
+        limits.movetime = Some(Duration::ZERO);
+        limits.ponder = true;
+        let mut time = TimeManager::new(&limits);
+        assert!(time.is_pondering());
+        assert!(time.can_start_iteration());
+        assert!(!time.out_of_time());
+
+        time.ponderhit();
+        assert!(!time.is_pondering());
+        assert!(!time.can_start_iteration());
+        assert!(time.out_of_time());
+    }
+}
@@ -0,0 +1,380 @@
+use crate::chess_move::Move;
+use crate::file::File;
+use crate::rank::Rank;
+use crate::piece::PieceType;
+use crate::search::MATE_BOUND;
+
+use std::mem;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::AtomicU8;
+use std::sync::atomic::Ordering;
+
+pub const DEFAULT_HASH_MB: usize = 16;
+const BUCKET_SIZE: usize = 4;
+
+// How the stored score relates to the true score of the position
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bound {
+    Exact,
+    // The search failed high: the true score is at least this
+    Lower,
+    // The search failed low: the true score is at most this
+    Upper,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TtEntry {
+    pub key: u64,
+    pub best_move: Option<Move>,
+    pub score: i32,
+    pub depth: u8,
+    pub bound: Bound,
+    // Search generation that last wrote the entry
+    pub age: u8,
+}
+
+impl TtEntry {
+    // Everything but the key in one word: the move in the low 16 bits, then
+    // the score, depth, bound and age. A zero word is an empty slot since
+    // the bound is never stored as zero.
+    fn pack(&self) -> u64 {
+        let bound = match self.bound {
+            Bound::Exact => 1,
+            Bound::Lower => 2,
+            Bound::Upper => 3
+        };
+        u64::from(pack_move(self.best_move))
+            | u64::from(self.score as i16 as u16) << 16
+            | u64::from(self.depth) << 32
+            | bound << 40
+            | u64::from(self.age) << 42
+    }
+
+    fn unpack(key: u64, data: u64) -> Option<TtEntry> {
+        let bound = match (data >> 40) & 3 {
+            1 => Bound::Exact,
+            2 => Bound::Lower,
+            3 => Bound::Upper,
+            _ => return None
+        };
+        Some(TtEntry{
+            key,
+            best_move: unpack_move(data as u16),
+            score: i32::from((data >> 16) as u16 as i16),
+            depth: (data >> 32) as u8,
+            bound,
+            age: (data >> 42) as u8,
+        })
+    }
+}
+
+// From and to squares in six bits each, the promotion piece in three more
+// and the top bit set so that no move packs to zero
+fn pack_move(mv: Option<Move>) -> u16 {
+    let mv = match mv {
+        Some(mv) => mv,
+        None => return 0
+    };
+    let square = |file: File, rank: Rank| u16::from(file.value() * 8 + rank.value());
+    let promotion = match mv.promotion {
+        None => 0,
+        Some(PieceType::Knight) => 1,
+        Some(PieceType::Bishop) => 2,
+        Some(PieceType::Rook) => 3,
+        Some(_) => 4
+    };
+    1 << 15 | promotion << 12 | square(mv.to_file, mv.to_rank) << 6 | square(mv.from_file, mv.from_rank)
+}
+
+fn unpack_move(packed: u16) -> Option<Move> {
+    if packed == 0 {
+        return None;
+    }
+    let square = |index: u16| {
+        let index = (index & 63) as u8;
+        (File::build(index / 8).unwrap(), Rank::build(index % 8).unwrap())
+    };
+    let (from_file, from_rank) = square(packed);
+    let (to_file, to_rank) = square(packed >> 6);
+    let mv = Move::new(from_file, from_rank, to_file, to_rank);
+    let promotion = match (packed >> 12) & 7 {
+        1 => PieceType::Knight,
+        2 => PieceType::Bishop,
+        3 => PieceType::Rook,
+        4 => PieceType::Queen,
+        _ => return Some(mv)
+    };
+    Some(Move::with_promotion(from_file, from_rank, to_file, to_rank, promotion))
+}
+
+// A slot keeps the key xored with the data. Threads read and write the two
+// words without locking, so a reader may see halves of different writes;
+// those no longer xor back to the key and the slot reads as a miss.
+#[derive(Default)]
+struct Slot {
+    check: AtomicU64,
+    data: AtomicU64,
+}
+
+impl Slot {
+    fn load(&self) -> Option<TtEntry> {
+        let data = self.data.load(Ordering::Relaxed);
+        let check = self.check.load(Ordering::Relaxed);
+        TtEntry::unpack(check ^ data, data)
+    }
+
+    fn save(&self, entry: &TtEntry) {
+        let data = entry.pack();
+        self.check.store(entry.key ^ data, Ordering::Relaxed);
+        self.data.store(data, Ordering::Relaxed);
+    }
+
+    fn reset(&self) {
+        self.check.store(0, Ordering::Relaxed);
+        self.data.store(0, Ordering::Relaxed);
+    }
+}
+
+type Bucket = [Slot; BUCKET_SIZE];
+
+// Fixed-size hash table of search results keyed by position hash. Entries
+// are grouped in buckets; a new entry replaces the one for the same
+// position, otherwise the shallowest or oldest entry in its bucket. Every
+// method takes &self, so search threads can share one table; a table of
+// another size is a new table.
+pub struct TranspositionTable {
+    buckets: Vec<Bucket>,
+    age: AtomicU8,
+}
+
+impl TranspositionTable {
+    pub fn new(mb: usize) -> TranspositionTable {
+        let bucket_count = (mb * 1024 * 1024 / mem::size_of::<Bucket>()).max(1);
+        TranspositionTable{
+            buckets: (0..bucket_count).map(|_| Bucket::default()).collect(),
+            age: AtomicU8::new(0),
+        }
+    }
+
+    pub fn clear(&self) {
+        for slot in self.buckets.iter().flat_map(|bucket| bucket.iter()) {
+            slot.reset();
+        }
+        self.age.store(0, Ordering::Relaxed);
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.buckets.len() * BUCKET_SIZE
+    }
+
+    // Called once per search so entries from earlier searches get replaced first
+    pub fn new_search(&self) {
+        self.age.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn bucket_index(&self, key: u64) -> usize {
+        // Scale the key onto the table instead of taking a modulo
+        ((key as u128 * self.buckets.len() as u128) >> 64) as usize
+    }
+
+    pub fn probe(&self, key: u64) -> Option<TtEntry> {
+        self.buckets[self.bucket_index(key)].iter()
+            .filter_map(Slot::load)
+            .find(|entry| entry.key == key)
+    }
+
+    pub fn store(&self, key: u64, best_move: Option<Move>, score: i32, depth: u32, bound: Bound) {
+        let age = self.age.load(Ordering::Relaxed);
+        let bucket = &self.buckets[self.bucket_index(key)];
+        let entries: Vec<Option<TtEntry>> = bucket.iter().map(Slot::load).collect();
+
+        let slot = match entries.iter().position(|entry| entry.is_none_or(|entry| entry.key == key)) {
+            Some(slot) => slot,
+            None => {
+                // Every slot holds another position: evict the least valuable,
+                // counting each search since it was written against its depth
+                let worth = |entry: &Option<TtEntry>| {
+                    let entry = entry.unwrap();
+                    i32::from(entry.depth) - 8 * i32::from(age.wrapping_sub(entry.age))
+                };
+                (0..BUCKET_SIZE).min_by_key(|&slot| worth(&entries[slot])).unwrap()
+            }
+        };
+
+        // Keep the old move rather than forget it when the new search has none
+        let best_move = best_move.or_else(|| entries[slot].filter(|entry| entry.key == key).and_then(|entry| entry.best_move));
+        bucket[slot].save(&TtEntry{
+            key,
+            best_move,
+            score,
+            depth: depth.min(u32::from(u8::MAX)) as u8,
+            bound,
+            age,
+        });
+    }
+
+    // Permille of a sample of the table written during the current search
+    pub fn hashfull(&self) -> u32 {
+        let age = self.age.load(Ordering::Relaxed);
+        let sample = self.buckets.iter()
+            .flat_map(|bucket| bucket.iter())
+            .take(1000);
+        let mut total = 0;
+        let mut used = 0;
+        for slot in sample {
+            total += 1;
+            if slot.load().is_some_and(|entry| entry.age == age) {
+                used += 1;
+            }
+        }
+        used * 1000 / total
+    }
+}
+
+impl Default for TranspositionTable {
+    fn default() -> TranspositionTable {
+        TranspositionTable::new(DEFAULT_HASH_MB)
+    }
+}
+
+// Mate scores count plies from the root, but an entry may be reached at any
+// ply. Store them relative to the position instead.
+pub fn score_to_tt(score: i32, ply: usize) -> i32 {
+    if score >= MATE_BOUND {
+        score + ply as i32
+    } else if score <= -MATE_BOUND {
+        score - ply as i32
+    } else {
+        score
+    }
+}
+
+pub fn score_from_tt(score: i32, ply: usize) -> i32 {
+    if score >= MATE_BOUND {
+        score - ply as i32
+    } else if score <= -MATE_BOUND {
+        score + ply as i32
+    } else {
+        score
+    }
+}
+
+#[cfg(test)]
+mod tt_tests {
+    use super::*;
+    use crate::search::MATE_SCORE;
+
+    fn mv(uci: &str) -> Option<Move> {
+        Some(Move::from_uci(uci).unwrap())
+    }
+
+    #[test]
+    fn size_follows_megabytes() {
+        let small = TranspositionTable::new(1);
+        let large = TranspositionTable::new(4);
+        assert_eq!(large.capacity() / small.capacity(), 4);
+        assert!(small.capacity() * mem::size_of::<Slot>() <= 1024 * 1024);
+        assert!(TranspositionTable::new(0).capacity() > 0);
+    }
+
+    #[test]
+    fn stores_and_probes() {
+        let tt = TranspositionTable::new(1);
+        assert_eq!(tt.probe(42), None);
+        tt.store(42, mv("e2e4"), 35, 6, Bound::Exact);
+        let entry = tt.probe(42).unwrap();
+        assert_eq!(entry.best_move, mv("e2e4"));
+        assert_eq!(entry.score, 35);
+        assert_eq!(entry.depth, 6);
+        assert_eq!(entry.bound, Bound::Exact);
+        assert_eq!(tt.probe(43), None);
+
+        // Same position overwrites, keeping the move if none is given
+        tt.store(42, None, -10, 7, Bound::Upper);
+        let entry = tt.probe(42).unwrap();
+        assert_eq!(entry.best_move, mv("e2e4"));
+        assert_eq!(entry.score, -10);
+
+        tt.clear();
+        assert_eq!(tt.probe(42), None);
+    }
+
+    #[test]
+    fn replaces_shallow_and_stale_entries() {
+        // A single bucket, so every key collides
+        let tt = TranspositionTable::new(0);
+        assert_eq!(tt.capacity(), BUCKET_SIZE);
+        for key in 1..=4 {
+            tt.store(key, None, 0, 10 + key as u32, Bound::Exact);
+        }
+        tt.store(5, None, 0, 1, Bound::Exact);
+        assert_eq!(tt.probe(1), None);
+        assert!(tt.probe(5).is_some());
+
+        // After a few searches even deep entries make way
+        for _ in 0..3 {
+            tt.new_search();
+        }
+        tt.store(6, None, 0, 1, Bound::Exact);
+        tt.store(7, None, 0, 1, Bound::Exact);
+        assert!(tt.probe(6).is_some() && tt.probe(7).is_some());
+        assert_eq!(tt.probe(2), None);
+    }
+
+    #[test]
+    fn mate_scores_are_relative_to_the_position() {
+        // Mate in 3 plies found 5 plies from the root
+        let score = MATE_SCORE - 8;
+        assert_eq!(score_to_tt(score, 5), MATE_SCORE - 3);
+        // Reached again 2 plies from the root it is a mate in 5 plies
+        assert_eq!(score_from_tt(MATE_SCORE - 3, 2), MATE_SCORE - 5);
+        assert_eq!(score_from_tt(score_to_tt(-score, 5), 5), -score);
+        assert_eq!(score_to_tt(120, 9), 120);
+    }
+
+    #[test]
+    fn hashfull_counts_the_current_search() {
+        let tt = TranspositionTable::new(1);
+        assert_eq!(tt.hashfull(), 0);
+        for key in 0..200_000u64 {
+            tt.store(key.wrapping_mul(0x9e37_79b9_7f4a_7c15), None, 0, 1, Bound::Exact);
+        }
+        assert!(tt.hashfull() > 500);
+        tt.new_search();
+        assert_eq!(tt.hashfull(), 0);
+    }
+
+    #[test]
+    fn entries_survive_packing() {
+        let tt = TranspositionTable::new(1);
+        for (key, uci, score) in [(1, "a7a8q", -MATE_SCORE + 3), (2, "h2h1n", MATE_SCORE), (3, "e1g1", -1), (4, "b7c8r", 0)].iter() {
+            tt.store(*key, mv(uci), *score, 200, Bound::Lower);
+            let entry = tt.probe(*key).unwrap();
+            assert_eq!(entry.best_move, mv(uci));
+            assert_eq!(entry.score, *score);
+            assert_eq!(entry.depth, 200);
+            assert_eq!(entry.bound, Bound::Lower);
+        }
+    }
+
+    #[test]
+    fn threads_share_the_table() {
+        let tt = TranspositionTable::new(1);
+        std::thread::scope(|scope| {
+            for thread in 0..4u64 {
+                let tt = &tt;
+                scope.spawn(move || {
+                    for key in 1..=1000u64 {
+                        let key = key.wrapping_mul(0x9e37_79b9_7f4a_7c15);
+                        tt.store(key, mv("e2e4"), thread as i32, 1, Bound::Exact);
+                        if let Some(entry) = tt.probe(key) {
+                            assert_eq!(entry.key, key);
+                            assert_eq!(entry.best_move, mv("e2e4"));
+                        }
+                    }
+                });
+            }
+        });
+        assert!(tt.probe(0x9e37_79b9_7f4a_7c15).is_some());
+    }
+}
@@ -0,0 +1,531 @@
+use crate::attacks;
+use crate::attacks::AttackMap;
+use crate::attacks::MOBILITY;
+use crate::board::Board;
+use crate::eval;
+use crate::eval::MATERIAL;
+use crate::eval::MAX_PHASE;
+use crate::eval::PST_EG;
+use crate::eval::PST_MG;
+use crate::pawns;
+use crate::piece::Color;
+
+use std::io;
+use std::io::BufRead;
+use std::io::Write;
+use std::thread;
+
+// Where each group of weights starts in the parameter vector
+const MATERIAL_PARAMS: usize = 0;
+const PST_PARAMS: usize = MATERIAL_PARAMS + 6;
+// Doubled, isolated and backward pawns
+const PAWN_PARAMS: usize = PST_PARAMS + 6 * 64;
+const CONNECTED_PARAMS: usize = PAWN_PARAMS + 3;
+const PASSED_PARAMS: usize = CONNECTED_PARAMS + 8;
+const MOBILITY_PARAMS: usize = PASSED_PARAMS + 8;
+pub const PARAM_COUNT: usize = MOBILITY_PARAMS + 4;
+
+const PAWN_NAMES: [&str; 3] = ["DOUBLED", "ISOLATED", "BACKWARD"];
+
+// Connected pawn bonuses are one value for both game phases
+fn tied(index: usize) -> bool {
+    (CONNECTED_PARAMS..PASSED_PARAMS).contains(&index)
+}
+
+fn param_name(index: usize) -> String {
+    match index {
+        i if i < PST_PARAMS => format!("MATERIAL[{}]", i - MATERIAL_PARAMS),
+        i if i < PAWN_PARAMS => format!("PST[{}][{}]", (i - PST_PARAMS) / 64, (i - PST_PARAMS) % 64),
+        i if i < CONNECTED_PARAMS => String::from(PAWN_NAMES[i - PAWN_PARAMS]),
+        i if i < PASSED_PARAMS => format!("CONNECTED[{}]", i - CONNECTED_PARAMS),
+        i if i < MOBILITY_PARAMS => format!("PASSED[{}]", i - PASSED_PARAMS),
+        i => format!("MOBILITY[{}]", i - MOBILITY_PARAMS)
+    }
+}
+
+// The weights the engine is built with, middlegame and endgame value each
+pub fn engine_params() -> Vec<[f64; 2]> {
+    let mut params = vec![[0.0; 2]; PARAM_COUNT];
+    let mut set = |index: usize, mg: i32, eg: i32| params[index] = [f64::from(mg), f64::from(eg)];
+    for (piece, material) in MATERIAL.iter().enumerate() {
+        set(MATERIAL_PARAMS + piece, material.mg, material.eg);
+        for square in 0..64 {
+            set(PST_PARAMS + piece * 64 + square, PST_MG[piece][square], PST_EG[piece][square]);
+        }
+    }
+    for (offset, score) in [pawns::DOUBLED, pawns::ISOLATED, pawns::BACKWARD].iter().enumerate() {
+        set(PAWN_PARAMS + offset, score.mg, score.eg);
+    }
+    for rank in 0..8 {
+        set(CONNECTED_PARAMS + rank, pawns::CONNECTED[rank], pawns::CONNECTED[rank]);
+        set(PASSED_PARAMS + rank, pawns::PASSED_MG[rank], pawns::PASSED_EG[rank]);
+    }
+    for (offset, (_, weight, _)) in MOBILITY.iter().enumerate() {
+        set(MOBILITY_PARAMS + offset, weight.mg, weight.eg);
+    }
+    params
+}
+
+// How often each weight counts for White minus how often for Black; the
+// tuned part of the evaluation is the sum of coefficient times weight
+fn coefficients(board: &Board) -> Vec<(u16, i8)> {
+    let mut counts = [0i32; PARAM_COUNT];
+    let sign = |color: Color| match color {
+        Color::White => 1,
+        Color::Black => -1
+    };
+    for square in board.pieces() {
+        let piece = square.piece.unwrap();
+        let index = eval::piece_index(piece.piece_type());
+        counts[MATERIAL_PARAMS + index] += sign(piece.get_color());
+        counts[PST_PARAMS + index * 64 + eval::table_square(&piece, square.file, square.rank)] += sign(piece.get_color());
+    }
+    let attacks = AttackMap::new(board);
+    for (color, terms) in [Color::White, Color::Black].iter().zip(pawns::pawn_terms(board).iter()) {
+        let sign = sign(*color);
+        counts[PAWN_PARAMS] += sign * terms.doubled;
+        counts[PAWN_PARAMS + 1] += sign * terms.isolated;
+        counts[PAWN_PARAMS + 2] += sign * terms.backward;
+        for rank in 0..8 {
+            counts[CONNECTED_PARAMS + rank] += sign * terms.connected[rank];
+            counts[PASSED_PARAMS + rank] += sign * terms.passed[rank];
+        }
+        for (offset, count) in attacks::mobility_counts(&attacks, *color).iter().enumerate() {
+            counts[MOBILITY_PARAMS + offset] += sign * count;
+        }
+    }
+    counts.iter()
+        .enumerate()
+        .filter(|(_, count)| **count != 0)
+        .map(|(index, count)| (index as u16, (*count).clamp(-128, 127) as i8))
+        .collect()
+}
+
+// Middlegame and endgame sums of coefficient times weight
+fn linear_score(coefficients: &[(u16, i8)], params: &[[f64; 2]]) -> (f64, f64) {
+    coefficients.iter().fold((0.0, 0.0), |(mg, eg), (index, count)| {
+        let [param_mg, param_eg] = params[usize::from(*index)];
+        (mg + f64::from(*count) * param_mg, eg + f64::from(*count) * param_eg)
+    })
+}
+
+fn sigmoid(k: f64, score: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-k * score / 400.0))
+}
+
+// A position reduced to what the tuner needs
+struct TuningPosition {
+    coefficients: Vec<(u16, i8)>,
+    // Share of the middlegame value, 1 with all pieces on the board
+    mg_share: f64,
+    // The part of the evaluation that is not tuned, such as king safety,
+    // from White's point of view
+    fixed: f64,
+    // 1 for a White win, 0.5 for a draw, 0 for a Black win
+    result: f64,
+}
+
+impl TuningPosition {
+    fn score(&self, params: &[[f64; 2]]) -> f64 {
+        let (mg, eg) = linear_score(&self.coefficients, params);
+        self.fixed + mg * self.mg_share + eg * (1.0 - self.mg_share)
+    }
+}
+
+// Read the result of an EPD line: a c9 style "1-0", "0-1" or "1/2-1/2", a
+// number in brackets such as [0.5], or the last field of the self-play
+// format, FEN | score | result
+fn parse_result(text: &str) -> Option<f64> {
+    if let Some((_, last)) = text.rsplit_once('|') {
+        last.trim().parse().ok().filter(|result| (0.0..=1.0).contains(result))
+    } else if text.contains("1/2-1/2") {
+        Some(0.5)
+    } else if text.contains("1-0") {
+        Some(1.0)
+    } else if text.contains("0-1") {
+        Some(0.0)
+    } else {
+        let start = text.find('[')?;
+        let end = start + text[start..].find(']')?;
+        text[start + 1..end].trim().parse().ok().filter(|result| (0.0..=1.0).contains(result))
+    }
+}
+
+pub fn parse_epd_line(line: &str) -> Result<(Board, f64), &'static str> {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    if fields.len() < 5 {
+        return Err("EPD line needs a position and a result");
+    }
+    let board = Board::from_fen(&fields[..4].join(" "))?;
+    let result = parse_result(&fields[4..].join(" ")).ok_or("EPD line has no game result")?;
+    Ok((board, result))
+}
+
+#[derive(Debug, Clone)]
+pub struct TunerOptions {
+    pub iterations: usize,
+    pub learning_rate: f64,
+    pub threads: usize,
+}
+
+impl TunerOptions {
+    pub fn new() -> TunerOptions {
+        TunerOptions{iterations: 1000, learning_rate: 1.0, threads: 1}
+    }
+}
+
+impl Default for TunerOptions {
+    fn default() -> TunerOptions {
+        TunerOptions::new()
+    }
+}
+
+// Texel tuning: fit the evaluation weights so that a sigmoid of the
+// evaluation predicts the results of the games the positions come from
+pub struct Tuner {
+    params: Vec<[f64; 2]>,
+    // The weights evaluate uses, to tell the tuned part of its score apart
+    engine: Vec<[f64; 2]>,
+    positions: Vec<TuningPosition>,
+    // Scales centipawns in the sigmoid, fitted to the starting weights
+    k: f64,
+}
+
+impl Tuner {
+    pub fn new() -> Tuner {
+        Tuner{params: engine_params(), engine: engine_params(), positions: Vec::new(), k: 1.0}
+    }
+
+    pub fn params(&self) -> &[[f64; 2]] {
+        &self.params
+    }
+
+    // Start from other weights, such as those of an earlier run
+    pub fn set_params(&mut self, params: Vec<[f64; 2]>) -> Result<(), &'static str> {
+        if params.len() != PARAM_COUNT {
+            return Err("Wrong number of parameters");
+        }
+        self.params = params;
+        Ok(())
+    }
+
+    pub fn positions(&self) -> usize {
+        self.positions.len()
+    }
+
+    pub fn k(&self) -> f64 {
+        self.k
+    }
+
+    // Positions in check are left out, as the evaluation of them means
+    // little; returns whether the position was added
+    pub fn add_position(&mut self, board: &Board, result: f64) -> bool {
+        if board.in_check() {
+            return false;
+        }
+        let white_score = match board.side_to_move() {
+            Color::White => eval::evaluate(board),
+            Color::Black => -eval::evaluate(board)
+        };
+        let coefficients = coefficients(board);
+        let mg_share = f64::from(board.phase().clamp(0, MAX_PHASE)) / f64::from(MAX_PHASE);
+        let (mg, eg) = linear_score(&coefficients, &self.engine);
+        let fixed = f64::from(white_score) - (mg * mg_share + eg * (1.0 - mg_share));
+        self.positions.push(TuningPosition{coefficients, mg_share, fixed, result});
+        true
+    }
+
+    // One position per line; returns how many were added
+    pub fn read_epd<R: BufRead>(&mut self, reader: R) -> io::Result<usize> {
+        let mut added = 0;
+        for (number, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let (board, result) = parse_epd_line(&line)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, format!("line {}: {}", number + 1, err)))?;
+            if self.add_position(&board, result) {
+                added += 1;
+            }
+        }
+        Ok(added)
+    }
+
+    // Mean squared difference between results and predictions
+    pub fn error(&self, k: f64) -> f64 {
+        if self.positions.is_empty() {
+            return 0.0;
+        }
+        let total: f64 = self.positions.iter()
+            .map(|position| (position.result - sigmoid(k, position.score(&self.params))).powi(2))
+            .sum();
+        total / self.positions.len() as f64
+    }
+
+    // Golden section search for the scaling that best fits the current weights
+    pub fn fit_k(&mut self) -> f64 {
+        let ratio = (5f64.sqrt() - 1.0) / 2.0;
+        let (mut low, mut high) = (0.01, 5.0);
+        for _ in 0..60 {
+            let a = high - ratio * (high - low);
+            let b = low + ratio * (high - low);
+            if self.error(a) < self.error(b) {
+                high = b;
+            } else {
+                low = a;
+            }
+        }
+        self.k = (low + high) / 2.0;
+        self.k
+    }
+
+    // Gradient of the error with respect to every middlegame and endgame weight
+    fn gradient(&self, threads: usize) -> Vec<[f64; 2]> {
+        let chunk_size = self.positions.len().div_ceil(threads.max(1)).max(1);
+        let chunk_gradient = |positions: &[TuningPosition]| {
+            let mut gradient = vec![[0.0; 2]; PARAM_COUNT];
+            for position in positions {
+                let prediction = sigmoid(self.k, position.score(&self.params));
+                let slope = prediction * (1.0 - prediction) * self.k * 10f64.ln() / 400.0;
+                let factor = -2.0 * (position.result - prediction) * slope;
+                for (index, count) in position.coefficients.iter() {
+                    let weight = factor * f64::from(*count);
+                    let entry = &mut gradient[usize::from(*index)];
+                    entry[0] += weight * position.mg_share;
+                    entry[1] += weight * (1.0 - position.mg_share);
+                }
+            }
+            gradient
+        };
+        let partials: Vec<Vec<[f64; 2]>> = thread::scope(|scope| {
+            let handles: Vec<_> = self.positions.chunks(chunk_size)
+                .map(|chunk| scope.spawn(move || chunk_gradient(chunk)))
+                .collect();
+            handles.into_iter().map(|handle| handle.join().unwrap()).collect()
+        });
+        let count = self.positions.len().max(1) as f64;
+        let mut gradient = vec![[0.0; 2]; PARAM_COUNT];
+        for partial in partials {
+            for (total, part) in gradient.iter_mut().zip(partial) {
+                total[0] += part[0] / count;
+                total[1] += part[1] / count;
+            }
+        }
+        gradient
+    }
+
+    // Adam gradient descent on the weights, calling progress with the
+    // iteration number and error every so often
+    pub fn tune<F: FnMut(usize, f64)>(&mut self, options: &TunerOptions, mut progress: F) {
+        const BETA1: f64 = 0.9;
+        const BETA2: f64 = 0.999;
+        const EPSILON: f64 = 1e-8;
+        let mut momentum = vec![[0.0; 2]; PARAM_COUNT];
+        let mut velocity = vec![[0.0; 2]; PARAM_COUNT];
+        for iteration in 1..=options.iterations {
+            let mut gradient = self.gradient(options.threads);
+            for (index, entry) in gradient.iter_mut().enumerate() {
+                if tied(index) {
+                    let both = entry[0] + entry[1];
+                    *entry = [both, both];
+                }
+            }
+            let correction1 = 1.0 - BETA1.powi(iteration as i32);
+            let correction2 = 1.0 - BETA2.powi(iteration as i32);
+            for index in 0..PARAM_COUNT {
+                for phase in 0..2 {
+                    let g = gradient[index][phase];
+                    momentum[index][phase] = BETA1 * momentum[index][phase] + (1.0 - BETA1) * g;
+                    velocity[index][phase] = BETA2 * velocity[index][phase] + (1.0 - BETA2) * g * g;
+                    let step = (momentum[index][phase] / correction1) / ((velocity[index][phase] / correction2).sqrt() + EPSILON);
+                    self.params[index][phase] -= options.learning_rate * step;
+                }
+            }
+            if iteration % 100 == 0 || iteration == options.iterations {
+                progress(iteration, self.error(self.k));
+            }
+        }
+    }
+
+    // One weight per line: its name and middlegame and endgame value
+    pub fn write_params<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        for (index, [mg, eg]) in self.params.iter().enumerate() {
+            writeln!(writer, "{} {} {}", param_name(index), mg.round(), eg.round())?;
+        }
+        Ok(())
+    }
+
+    // The tuned weights as the constant declarations in eval.rs, pawns.rs
+    // and attacks.rs, ready to paste over the old ones
+    pub fn write_rust<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let value = |index: usize, phase: usize| self.params[index][phase].round() as i32;
+
+        writeln!(writer, "// eval.rs")?;
+        writeln!(writer, "pub const MATERIAL: [Score; 6] = [")?;
+        for piece in 0..6 {
+            writeln!(writer, "    Score::new({}, {}),", value(MATERIAL_PARAMS + piece, 0), value(MATERIAL_PARAMS + piece, 1))?;
+        }
+        writeln!(writer, "];")?;
+        for (name, phase) in [("PST_MG", 0), ("PST_EG", 1)].iter() {
+            writeln!(writer)?;
+            writeln!(writer, "pub const {}: [[i32; 64]; 6] = [", name)?;
+            for piece in 0..6 {
+                writeln!(writer, "    [")?;
+                for row in 0..8 {
+                    let squares: Vec<String> = (0..8)
+                        .map(|file| format!("{:4}", value(PST_PARAMS + piece * 64 + row * 8 + file, *phase)))
+                        .collect();
+                    writeln!(writer, "       {},", squares.join(","))?;
+                }
+                writeln!(writer, "    ],")?;
+            }
+            writeln!(writer, "];")?;
+        }
+
+        writeln!(writer)?;
+        writeln!(writer, "// pawns.rs")?;
+        for (offset, name) in PAWN_NAMES.iter().enumerate() {
+            writeln!(writer, "pub const {}: Score = Score::new({}, {});", name, value(PAWN_PARAMS + offset, 0), value(PAWN_PARAMS + offset, 1))?;
+        }
+        let table = |start: usize, phase: usize| (0..8).map(|rank| value(start + rank, phase).to_string()).collect::<Vec<String>>().join(", ");
+        writeln!(writer, "pub const CONNECTED: [i32; 8] = [{}];", table(CONNECTED_PARAMS, 0))?;
+        writeln!(writer, "pub const PASSED_MG: [i32; 8] = [{}];", table(PASSED_PARAMS, 0))?;
+        writeln!(writer, "pub const PASSED_EG: [i32; 8] = [{}];", table(PASSED_PARAMS, 1))?;
+
+        writeln!(writer)?;
+        writeln!(writer, "// attacks.rs")?;
+        writeln!(writer, "pub const MOBILITY: [(PieceType, Score, i32); 4] = [")?;
+        for (offset, (piece_type, _, usual)) in MOBILITY.iter().enumerate() {
+            writeln!(writer, "    (PieceType::{:?}, Score::new({}, {}), {}),", piece_type, value(MOBILITY_PARAMS + offset, 0), value(MOBILITY_PARAMS + offset, 1), usual)?;
+        }
+        writeln!(writer, "];")
+    }
+}
+
+impl Default for Tuner {
+    fn default() -> Tuner {
+        Tuner::new()
+    }
+}
+
+// Read weights written by write_params
+pub fn read_params<R: BufRead>(reader: R) -> io::Result<Vec<[f64; 2]>> {
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+    let mut params = engine_params();
+    let names: Vec<String> = (0..PARAM_COUNT).map(param_name).collect();
+    for (number, line) in reader.lines().enumerate() {
+        let line = line?;
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.is_empty() {
+            continue;
+        }
+        let index = names.iter()
+            .position(|name| name == fields[0])
+            .ok_or_else(|| invalid(format!("line {}: unknown parameter {}", number + 1, fields[0])))?;
+        let values: Vec<f64> = fields[1..].iter().filter_map(|field| field.parse().ok()).collect();
+        if fields.len() != 3 || values.len() != 2 {
+            return Err(invalid(format!("line {}: expected a name and two values", number + 1)));
+        }
+        params[index] = [values[0], values[1]];
+    }
+    Ok(params)
+}
+
+#[cfg(test)]
+mod tuner_tests {
+    use super::*;
+    use crate::eval::eval_trace;
+
+    const POSITIONS: [&str; 6] = [
+        "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - c9 \"1/2-1/2\";",
+        "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R b KQkq - [0.5]",
+        "4k3/8/8/8/8/8/3PP3/2RQK3 w - - c9 \"1-0\";",
+        "2rqk3/3pp3/8/8/8/8/8/4K3 w - - c9 \"0-1\";",
+        "4k3/8/1P6/8/8/8/8/4K3 b - - [1.0]",
+        "4k3/pp6/8/8/8/8/8/3NK3 w - - 0 1 | -120 | 0.0",
+    ];
+
+    fn tuner() -> Tuner {
+        let mut tuner = Tuner::new();
+        for line in POSITIONS.iter() {
+            let (board, result) = parse_epd_line(line).unwrap();
+            assert!(tuner.add_position(&board, result));
+        }
+        tuner
+    }
+
+    #[test]
+    fn reads_results_in_either_notation() {
+        let results: Vec<f64> = POSITIONS.iter().map(|line| parse_epd_line(line).unwrap().1).collect();
+        assert_eq!(results, vec![0.5, 0.5, 1.0, 0.0, 1.0, 0.0]);
+        assert!(parse_epd_line("4k3/8/8/8/8/8/8/4K3 w - -").is_err());
+        assert!(parse_epd_line("4k3/8/8/8/8/8/8/4K3 w - - [2.0]").is_err());
+
+        let mut tuner = Tuner::new();
+        let text = POSITIONS.join("\n") + "\n\n4k3/8/8/8/8/8/8/r3K3 w - - [0.5]\n";
+        // The position in check is left out
+        assert_eq!(tuner.read_epd(text.as_bytes()).unwrap(), 6);
+        assert!(tuner.read_epd("not a position [0.5]".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn coefficients_reproduce_the_tuned_terms() {
+        let params = engine_params();
+        for line in POSITIONS.iter() {
+            let (board, _) = parse_epd_line(line).unwrap();
+            let trace = eval_trace(&board);
+            let tuned = trace.terms.iter()
+                .filter(|term| ["Material", "Piece-square", "Pawns", "Mobility"].contains(&term.name))
+                .fold(eval::Score::default(), |total, term| total + term.white - term.black);
+            let (mg, eg) = linear_score(&coefficients(&board), &params);
+            assert_eq!((mg, eg), (f64::from(tuned.mg), f64::from(tuned.eg)));
+        }
+    }
+
+    #[test]
+    fn tuning_lowers_the_error() {
+        let mut tuner = tuner();
+        let k = tuner.fit_k();
+        assert!(k > 0.01 && k < 5.0);
+        let before = tuner.error(k);
+        let mut reports = Vec::new();
+        tuner.tune(&TunerOptions{iterations: 200, learning_rate: 2.0, threads: 2}, |iteration, error| reports.push((iteration, error)));
+        assert_eq!(reports.iter().map(|(iteration, _)| *iteration).collect::<Vec<usize>>(), vec![100, 200]);
+        assert!(tuner.error(k) < before);
+        // Connected pawn bonuses stay the same in both phases
+        let connected = tuner.params()[CONNECTED_PARAMS + 1];
+        assert_eq!(connected[0], connected[1]);
+        // The king's material never counts
+        assert_eq!(tuner.params()[MATERIAL_PARAMS + 5], [0.0, 0.0]);
+    }
+
+    #[test]
+    fn writes_weights_back() {
+        let engine = Tuner::new();
+        let mut rust = Vec::new();
+        engine.write_rust(&mut rust).unwrap();
+        let rust = String::from_utf8(rust).unwrap();
+        assert!(rust.contains("    Score::new(82, 94),\n"));
+        assert!(rust.contains("        98, 134,  61,  95,  68, 126,  34, -11,\n"));
+        assert!(rust.contains("      -105, -21, -58, -33, -17, -28, -19, -23,\n"));
+        assert!(rust.contains("pub const CONNECTED: [i32; 8] = [0, 4, 7, 12, 20, 35, 60, 0];"));
+        assert!(rust.contains("(PieceType::Knight, Score::new(4, 4), 4),"));
+
+        let mut params = Vec::new();
+        engine.write_params(&mut params).unwrap();
+        assert_eq!(read_params(params.as_slice()).unwrap(), engine_params());
+        assert!(read_params("PST[9][0] 1 2".as_bytes()).is_err());
+        assert!(read_params("DOUBLED 1 2 3".as_bytes()).is_err());
+
+        // Starting from other weights changes the predictions
+        let mut tuner = tuner();
+        let before = tuner.error(1.0);
+        let mut doubled_queens = engine_params();
+        doubled_queens[MATERIAL_PARAMS + 4] = [2000.0, 2000.0];
+        tuner.set_params(doubled_queens).unwrap();
+        assert!(tuner.error(1.0) != before);
+        assert!(tuner.set_params(Vec::new()).is_err());
+        assert!(read_params("DOUBLED 1".as_bytes()).is_err());
+    }
+}
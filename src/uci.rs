@@ -0,0 +1,592 @@
+use crate::board::Board;
+use crate::chess_move::Move;
+use crate::nnue::Network;
+use crate::polyglot::PolyglotBook;
+use crate::random::Random;
+use crate::search;
+use crate::search::SearchLimits;
+use crate::search::SearchResult;
+use crate::search::Searcher;
+use crate::syzygy::Tablebase;
+use crate::piece::Color;
+use crate::tt;
+use crate::tt::Bound;
+
+use std::fs;
+use std::io;
+use std::io::BufRead;
+use std::io::Write;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+const ENGINE_NAME: &str = "chess-engine";
+const MAX_HASH_MB: usize = 65536;
+const MAX_THREADS: usize = 256;
+const MAX_MULTI_PV: usize = 64;
+
+// The info lines for a search result, one per principal variation
+pub fn info_lines(result: &SearchResult) -> Vec<String> {
+    let millis = result.time.as_millis() as u64;
+    let nps = result.nodes * 1000 / millis.max(1);
+    let single = [(result.score, &result.pv, result.bound)];
+    let lines: Vec<(i32, &Vec<Move>, Bound)> = if result.lines.is_empty() {
+        single.to_vec()
+    } else {
+        result.lines.iter().map(|line| (line.score, &line.pv, line.bound)).collect()
+    };
+    lines.iter().enumerate().map(|(index, (score, pv, bound))| {
+        let score = match search::mate_in(*score) {
+            Some(moves) => format!("mate {}", moves),
+            None => format!("cp {}", score)
+        };
+        let bound = match bound {
+            Bound::Exact => "",
+            Bound::Lower => " lowerbound",
+            Bound::Upper => " upperbound"
+        };
+        let pv: Vec<String> = pv.iter().map(|mv| mv.to_string()).collect();
+        format!("info depth {} multipv {} score {}{} nodes {} nps {} hashfull {} time {} pv {}",
+            result.depth, index + 1, score, bound, result.nodes, nps, result.hashfull, millis, pv.join(" "))
+    }).collect()
+}
+
+// Write a line to the GUI straight away
+pub fn write_line<W: Write>(output: &Mutex<W>, line: &str) {
+    let mut output = output.lock().unwrap();
+    // Nothing to be done if the GUI has gone away
+    let _ = writeln!(output, "{}", line).and_then(|_| output.flush());
+}
+
+// Parse the go command's arguments into search limits for the side to move
+fn parse_go(words: &[&str], side_to_move: Color) -> Result<SearchLimits, String> {
+    let mut limits = SearchLimits::new();
+    let mut rest = words.iter().peekable();
+    while let Some(word) = rest.next() {
+        let mut number = || -> Result<u64, String> {
+            rest.next()
+                .and_then(|value| value.parse::<i64>().ok())
+                .map(|value| value.max(0) as u64)
+                .ok_or_else(|| format!("go {} needs a number", word))
+        };
+        let ours = |color: Color| color == side_to_move;
+        match *word {
+            "wtime" | "btime" => {
+                let millis = number()?;
+                if ours(if *word == "wtime" { Color::White } else { Color::Black }) {
+                    limits.time_left = Some(Duration::from_millis(millis));
+                }
+            },
+            "winc" | "binc" => {
+                let millis = number()?;
+                if ours(if *word == "winc" { Color::White } else { Color::Black }) {
+                    limits.increment = Duration::from_millis(millis);
+                }
+            },
+            "movestogo" => limits.moves_to_go = Some(number()? as u32),
+            "depth" => limits.depth = Some(number()? as u32),
+            "nodes" => limits.nodes = Some(number()?),
+            "mate" => limits.mate = Some(number()? as u32),
+            "movetime" => limits.movetime = Some(Duration::from_millis(number()?)),
+            "ponder" => limits.ponder = true,
+            "infinite" => limits.infinite = true,
+            // Every move that follows, up to the next keyword
+            "searchmoves" => {
+                while let Some(mv) = rest.peek().and_then(|word| Move::from_uci(word).ok()) {
+                    limits.search_moves.push(mv);
+                    rest.next();
+                }
+            },
+            _ => {}
+        }
+    }
+    Ok(limits)
+}
+
+// Universal Chess Interface front end: reads commands a line at a time and
+// writes replies to output. Searches run on their own thread so that stop
+// and ponderhit are handled while they think.
+pub struct UciEngine<W: Write + Send + 'static> {
+    output: Arc<Mutex<W>>,
+    board: Board,
+    // Taken by the search thread while it runs and handed back when done
+    searcher: Option<Searcher>,
+    search: Option<JoinHandle<Searcher>>,
+    stop: Arc<AtomicBool>,
+    ponderhit: Arc<AtomicBool>,
+    // What the GUI asked for. A search clears the searcher's flags as it
+    // starts, so the search thread passes these on again after every
+    // iteration in case they came first.
+    stop_requested: Arc<AtomicBool>,
+    ponderhit_requested: Arc<AtomicBool>,
+    book: Option<PolyglotBook<fs::File>>,
+    own_book: bool,
+    random: Random,
+}
+
+impl<W: Write + Send + 'static> UciEngine<W> {
+    pub fn new(output: W) -> UciEngine<W> {
+        let searcher = Searcher::new();
+        UciEngine{
+            output: Arc::new(Mutex::new(output)),
+            board: Board::build_starting().unwrap(),
+            stop: searcher.stop_flag(),
+            ponderhit: searcher.ponderhit_flag(),
+            searcher: Some(searcher),
+            search: None,
+            stop_requested: Arc::new(AtomicBool::new(false)),
+            ponderhit_requested: Arc::new(AtomicBool::new(false)),
+            book: None,
+            own_book: false,
+            random: Random::from_time(),
+        }
+    }
+
+    pub fn output(&self) -> &Arc<Mutex<W>> {
+        &self.output
+    }
+
+    fn send(&self, line: &str) {
+        write_line(&self.output, line);
+    }
+
+    // Read commands until quit or the end of input, then stop any search
+    pub fn run<R: BufRead>(&mut self, input: R) -> io::Result<()> {
+        for line in input.lines() {
+            if !self.handle(&line?) {
+                break;
+            }
+        }
+        self.stop_search();
+        Ok(())
+    }
+
+    // Handle one command; false once it was quit
+    pub fn handle(&mut self, line: &str) -> bool {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.first().copied() {
+            Some("uci") => self.identify(),
+            Some("isready") => self.send("readyok"),
+            Some("ucinewgame") => {
+                self.stop_search();
+                self.searcher_mut().clear_hash();
+            },
+            Some("position") => {
+                self.stop_search();
+                if let Err(err) = self.set_position(&words[1..]) {
+                    self.send(&format!("info string {}", err));
+                }
+            },
+            Some("go") => {
+                self.stop_search();
+                match parse_go(&words[1..], self.board.side_to_move()) {
+                    Ok(limits) => self.go(limits),
+                    Err(err) => self.send(&format!("info string {}", err))
+                }
+            },
+            Some("stop") => self.stop_search(),
+            Some("ponderhit") => {
+                self.ponderhit_requested.store(true, Ordering::Relaxed);
+                self.ponderhit.store(true, Ordering::Relaxed);
+            },
+            Some("setoption") => {
+                self.stop_search();
+                if let Err(err) = self.set_option(&words[1..]) {
+                    self.send(&format!("info string {}", err));
+                }
+            },
+            Some("quit") => {
+                self.stop_search();
+                return false;
+            },
+            // Unknown commands are ignored, as the protocol asks
+            _ => {}
+        }
+        true
+    }
+
+    fn identify(&self) {
+        self.send(&format!("id name {}", ENGINE_NAME));
+        self.send("id author the chess-engine authors");
+        self.send(&format!("option name Hash type spin default {} min 1 max {}", tt::DEFAULT_HASH_MB, MAX_HASH_MB));
+        self.send(&format!("option name Threads type spin default 1 min 1 max {}", MAX_THREADS));
+        self.send(&format!("option name MultiPV type spin default 1 min 1 max {}", MAX_MULTI_PV));
+        self.send("option name Ponder type check default false");
+        for name in search::SWITCHES.iter() {
+            self.send(&format!("option name {} type check default true", name));
+        }
+        self.send("option name OwnBook type check default false");
+        self.send("option name BookFile type string default <empty>");
+        self.send("option name SyzygyPath type string default <empty>");
+        self.send("option name EvalFile type string default <empty>");
+        self.send("option name Clear Hash type button");
+        self.send("uciok");
+    }
+
+    fn searcher_mut(&mut self) -> &mut Searcher {
+        self.searcher.as_mut().unwrap()
+    }
+
+    // Block until the running search, if any, has sent its best move
+    pub fn wait(&mut self) {
+        if let Some(handle) = self.search.take() {
+            self.searcher = Some(handle.join().unwrap());
+        }
+    }
+
+    fn stop_search(&mut self) {
+        if self.search.is_some() {
+            self.stop_requested.store(true, Ordering::Relaxed);
+            self.stop.store(true, Ordering::Relaxed);
+            self.wait();
+        }
+    }
+
+    // position [startpos | fen FEN] [moves MOVE...]
+    fn set_position(&mut self, words: &[&str]) -> Result<(), String> {
+        let moves_at = words.iter().position(|word| *word == "moves").unwrap_or(words.len());
+        let mut board = match words.first().copied() {
+            Some("startpos") => Board::build_starting().map_err(String::from)?,
+            Some("fen") => Board::from_fen(&words[1..moves_at].join(" ")).map_err(String::from)?,
+            _ => return Err(String::from("position needs startpos or fen"))
+        };
+        for word in words.iter().skip(moves_at + 1) {
+            Move::from_uci(word)
+                .and_then(|mv| board.play(mv))
+                .map_err(|err| format!("{}: {}", word, err))?;
+        }
+        self.board = board;
+        Ok(())
+    }
+
+    // setoption name NAME [value VALUE]
+    fn set_option(&mut self, words: &[&str]) -> Result<(), String> {
+        let value_at = words.iter().position(|word| *word == "value").unwrap_or(words.len());
+        if words.first() != Some(&"name") {
+            return Err(String::from("setoption needs a name"));
+        }
+        let name = words[1..value_at].join(" ").to_lowercase();
+        let value = words.get(value_at + 1..).map(|value| value.join(" ")).unwrap_or_default();
+        let number = || value.parse::<usize>().map_err(|_| format!("invalid value for {}", name));
+        let path = || Some(value.as_str()).filter(|path| !path.is_empty() && *path != "<empty>");
+        match name.as_str() {
+            "hash" => {
+                let mb = number()?.clamp(1, MAX_HASH_MB);
+                self.searcher_mut().set_hash_size(mb);
+            },
+            "threads" | "multipv" => {
+                let count = number()?.clamp(1, if name == "threads" { MAX_THREADS } else { MAX_MULTI_PV });
+                let mut options = self.searcher_mut().options().clone();
+                if name == "threads" {
+                    options.threads = count;
+                } else {
+                    options.multi_pv = count;
+                }
+                self.searcher_mut().set_options(options);
+            },
+            // Pondering is up to the GUI, which sends go ponder
+            "ponder" => {},
+            "ownbook" => self.own_book = value == "true",
+            "bookfile" => {
+                self.book = match path() {
+                    Some(path) => Some(PolyglotBook::open(path).map_err(|err| format!("{}: {}", path, err))?),
+                    None => None
+                };
+            },
+            "syzygypath" => {
+                let tablebase = match path() {
+                    Some(path) => Some(Arc::new(Tablebase::open(path).map_err(|err| format!("{}: {}", path, err))?)),
+                    None => None
+                };
+                self.searcher_mut().set_tablebase(tablebase);
+            },
+            "evalfile" => {
+                let network = match path() {
+                    Some(path) => Some(Arc::new(Network::load(path).map_err(|err| format!("{}: {}", path, err))?)),
+                    None => None
+                };
+                self.searcher_mut().set_network(network);
+            },
+            "clear hash" => self.searcher_mut().clear_hash(),
+            _ => {
+                let mut options = self.searcher_mut().options().clone();
+                let switch = options.switch_mut(&name).ok_or_else(|| format!("unknown option {}", name))?;
+                *switch = match value.as_str() {
+                    "true" => true,
+                    "false" => false,
+                    _ => return Err(format!("invalid value for {}", name))
+                };
+                self.searcher_mut().set_options(options);
+            }
+        }
+        Ok(())
+    }
+
+    fn book_move(&mut self) -> Option<Move> {
+        if !self.own_book {
+            return None;
+        }
+        let book = self.book.as_mut()?;
+        book.weighted_move(&mut self.board, &mut self.random).ok().flatten()
+    }
+
+    fn go(&mut self, limits: SearchLimits) {
+        if !limits.ponder {
+            if let Some(mv) = self.book_move() {
+                self.send(&format!("bestmove {}", mv));
+                return;
+            }
+        }
+
+        let mut searcher = self.searcher.take().unwrap();
+        let mut board = self.board.clone();
+        let output = Arc::clone(&self.output);
+        let (stop, ponderhit) = (Arc::clone(&self.stop), Arc::clone(&self.ponderhit));
+        let (stop_requested, ponderhit_requested) = (Arc::clone(&self.stop_requested), Arc::clone(&self.ponderhit_requested));
+        stop_requested.store(false, Ordering::Relaxed);
+        ponderhit_requested.store(false, Ordering::Relaxed);
+        self.search = Some(thread::spawn(move || {
+            let result = searcher.search_with_info(&mut board, &limits, |result| {
+                if stop_requested.load(Ordering::Relaxed) {
+                    stop.store(true, Ordering::Relaxed);
+                }
+                if ponderhit_requested.load(Ordering::Relaxed) {
+                    ponderhit.store(true, Ordering::Relaxed);
+                }
+                for line in info_lines(result) {
+                    write_line(&output, &line);
+                }
+            });
+            // A ponder search that ends on its own keeps its move until the
+            // GUI says whether the predicted move was played, and an
+            // infinite one until it is stopped
+            let waiting = || (limits.ponder && !ponderhit_requested.load(Ordering::Relaxed)) || limits.infinite;
+            while waiting() && !stop_requested.load(Ordering::Relaxed) {
+                thread::sleep(Duration::from_millis(1));
+            }
+            let line = match result.best_move {
+                Some(mv) => match searcher.ponder_move(&mut board, &result) {
+                    Some(ponder) => format!("bestmove {} ponder {}", mv, ponder),
+                    None => format!("bestmove {}", mv)
+                },
+                None => String::from("bestmove 0000")
+            };
+            write_line(&output, &line);
+            searcher
+        }));
+    }
+}
+
+#[cfg(test)]
+mod uci_tests {
+    use super::*;
+    use crate::polyglot;
+    use crate::polyglot::BookEntry;
+
+    fn engine() -> UciEngine<Vec<u8>> {
+        UciEngine::new(Vec::new())
+    }
+
+    // Everything written so far, which is then cleared
+    fn take_output(engine: &UciEngine<Vec<u8>>) -> Vec<String> {
+        let mut output = engine.output().lock().unwrap();
+        let text = String::from_utf8(output.clone()).unwrap();
+        output.clear();
+        text.lines().map(String::from).collect()
+    }
+
+    // Output until a line starting with prefix turns up
+    fn wait_for(engine: &UciEngine<Vec<u8>>, prefix: &str) -> Vec<String> {
+        let deadline = std::time::Instant::now() + Duration::from_secs(60);
+        let mut lines = Vec::new();
+        while !lines.iter().any(|line: &String| line.starts_with(prefix)) {
+            assert!(std::time::Instant::now() < deadline, "no {} line", prefix);
+            thread::sleep(Duration::from_millis(1));
+            lines.extend(take_output(engine));
+        }
+        lines
+    }
+
+    fn best_move(lines: &[String]) -> Option<&str> {
+        lines.iter().rev().find_map(|line| line.strip_prefix("bestmove ")).map(|rest| rest.split(' ').next().unwrap())
+    }
+
+    #[test]
+    fn identifies_itself() {
+        let mut engine = engine();
+        assert!(engine.handle("uci"));
+        engine.handle("isready");
+        let lines = take_output(&engine);
+        assert!(lines[0].starts_with("id name "));
+        assert!(lines.iter().any(|line| line.starts_with("option name Hash type spin")));
+        assert!(lines.iter().any(|line| line == "option name OwnBook type check default false"));
+        assert!(lines.iter().any(|line| line == "option name CheckExtensions type check default true"));
+        assert_eq!(&lines[lines.len() - 2..], ["uciok", "readyok"]);
+        assert!(!engine.handle("quit"));
+    }
+
+    #[test]
+    fn searches_the_position_given() {
+        let mut engine = engine();
+        engine.handle("position fen 4k3/8/8/3q4/8/8/8/3RK3 w - - 0 1");
+        engine.handle("go depth 4");
+        engine.wait();
+        let lines = take_output(&engine);
+        assert!(lines.iter().any(|line| line.starts_with("info depth 1 multipv 1 score cp ")));
+        assert!(lines.iter().any(|line| line.starts_with("info depth 4 ") && line.contains(" pv d1d5")));
+        assert_eq!(best_move(&lines), Some("d1d5"));
+
+        engine.handle("position startpos moves e2e4 e7e5 g1f3");
+        engine.handle("go depth 1");
+        engine.wait();
+        let lines = take_output(&engine);
+        assert!(best_move(&lines).is_some());
+
+        engine.handle("position startpos moves e2e5");
+        engine.handle("position fen not a fen");
+        let lines = take_output(&engine);
+        assert_eq!(lines.len(), 2);
+        assert!(lines.iter().all(|line| line.starts_with("info string ")));
+    }
+
+    #[test]
+    fn reports_mates_and_positions_without_moves() {
+        let mut engine = engine();
+        engine.handle("position fen 6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1");
+        engine.handle("go mate 1");
+        engine.wait();
+        let lines = take_output(&engine);
+        assert!(lines.iter().any(|line| line.contains(" score mate 1 ")));
+        assert_eq!(best_move(&lines), Some("a1a8"));
+
+        engine.handle("position fen 6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1 moves a1a8");
+        engine.handle("go depth 3");
+        engine.wait();
+        assert_eq!(take_output(&engine).last().unwrap(), "bestmove 0000");
+    }
+
+    #[test]
+    fn stop_ends_an_infinite_search() {
+        let mut engine = engine();
+        engine.handle("go infinite");
+        thread::sleep(Duration::from_millis(50));
+        engine.handle("isready");
+        engine.handle("stop");
+        let lines = take_output(&engine);
+        assert!(lines.iter().any(|line| line == "readyok"));
+        assert!(best_move(&lines).is_some());
+        // Stopped straight away, before a search could clear the flag
+        engine.handle("go infinite");
+        engine.handle("stop");
+        assert!(best_move(&take_output(&engine)).is_some());
+    }
+
+    #[test]
+    fn infinite_searches_answer_only_after_stop() {
+        let mut engine = engine();
+        engine.handle("go infinite depth 1");
+        let lines = wait_for(&engine, "info depth 1 ");
+        assert_eq!(best_move(&lines), None);
+        assert!(!engine.search.as_ref().unwrap().is_finished());
+        engine.handle("stop");
+        assert!(best_move(&take_output(&engine)).is_some());
+
+        // Even with no moves to search
+        engine.handle("position fen 6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1 moves a1a8");
+        engine.handle("go infinite");
+        // Time to answer if it were going to; waiting longer can't fail
+        thread::sleep(Duration::from_millis(20));
+        assert!(!engine.search.as_ref().unwrap().is_finished());
+        assert_eq!(best_move(&take_output(&engine)), None);
+        engine.handle("stop");
+        assert_eq!(best_move(&take_output(&engine)), Some("0000"));
+    }
+
+    #[test]
+    fn searchmoves_restricts_the_root_moves() {
+        let limits = parse_go(&["searchmoves", "e2e4", "g1f3", "depth", "3"], Color::White).unwrap();
+        assert_eq!(limits.search_moves, [Move::from_uci("e2e4").unwrap(), Move::from_uci("g1f3").unwrap()]);
+        assert_eq!(limits.depth, Some(3));
+
+        // Left alone, the engine takes the hanging queen
+        let mut engine = engine();
+        engine.handle("position fen 4k3/8/8/3q4/8/8/8/3RK3 w - - 0 1");
+        engine.handle("go depth 3 searchmoves e1e2 e1f1");
+        engine.wait();
+        let lines = take_output(&engine);
+        assert!(["e1e2", "e1f1"].contains(&best_move(&lines).unwrap()));
+        assert!(lines.iter().filter(|line| line.starts_with("info ")).all(|line| !line.contains(" pv d1d5")));
+    }
+
+    #[test]
+    fn ponder_holds_the_move_until_ponderhit() {
+        let mut engine = engine();
+        engine.handle("position startpos moves e2e4");
+        engine.handle("go ponder depth 2 wtime 60000 btime 60000");
+        // The search is done but the move waits for the ponderhit
+        let lines = wait_for(&engine, "info depth 2 ");
+        assert_eq!(best_move(&lines), None);
+        assert!(!engine.search.as_ref().unwrap().is_finished());
+        engine.handle("ponderhit");
+        engine.wait();
+        let lines = take_output(&engine);
+        assert!(best_move(&lines).is_some());
+        assert!(lines.last().unwrap().contains(" ponder "));
+
+        // A miss is stopped and searched again
+        engine.handle("go ponder wtime 60000 btime 60000");
+        engine.handle("stop");
+        assert!(best_move(&take_output(&engine)).is_some());
+    }
+
+    #[test]
+    fn options_change_the_search() {
+        let mut engine = engine();
+        engine.handle("setoption name Hash value 1");
+        engine.handle("setoption name Threads value 2");
+        engine.handle("setoption name MultiPV value 3");
+        engine.handle("setoption name Clear Hash");
+        engine.handle("setoption name Unknown value 1");
+        assert_eq!(take_output(&engine), ["info string unknown option unknown"]);
+        engine.handle("setoption name NullMove value false");
+        engine.handle("setoption name LMR value false");
+        engine.handle("setoption name Razoring value maybe");
+        assert_eq!(take_output(&engine), ["info string invalid value for razoring"]);
+        let options = engine.searcher.as_ref().unwrap().options();
+        assert!(!options.null_move_pruning && !options.late_move_reductions && options.razoring);
+        engine.handle("go depth 2");
+        engine.wait();
+        let lines = take_output(&engine);
+        assert!(lines.iter().any(|line| line.starts_with("info depth 2 multipv 3 ")));
+        assert!(best_move(&lines).is_some());
+
+        let limits = parse_go(&["wtime", "1000", "btime", "2000", "winc", "10", "binc", "20", "movestogo", "5"], Color::Black).unwrap();
+        assert_eq!((limits.time_left, limits.increment, limits.moves_to_go), (Some(Duration::from_millis(2000)), Duration::from_millis(20), Some(5)));
+        assert!(parse_go(&["depth", "x"], Color::White).is_err());
+    }
+
+    #[test]
+    fn plays_from_its_own_book() {
+        let board = Board::build_starting().unwrap();
+        let mv = Move::from_uci("b1c3").unwrap();
+        let entry = BookEntry{key: board.zobrist_key(), raw_move: polyglot::encode_move(&board, &mv), weight: 1, learn: 0};
+        let path = std::env::temp_dir().join(format!("chess-engine-uci-book-{}.bin", std::process::id()));
+        fs::write(&path, entry.to_bytes()).unwrap();
+
+        let mut engine = engine();
+        engine.handle(&format!("setoption name BookFile value {}", path.display()));
+        engine.handle("setoption name OwnBook value true");
+        engine.handle("go depth 1");
+        assert_eq!(take_output(&engine), ["bestmove b1c3"]);
+        // Out of book the engine searches
+        engine.handle("position startpos moves b1c3");
+        engine.handle("go depth 1");
+        engine.wait();
+        assert!(take_output(&engine).iter().any(|line| line.starts_with("info depth 1 ")));
+        fs::remove_file(&path).unwrap();
+    }
+}
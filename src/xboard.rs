@@ -0,0 +1,531 @@
+use crate::board::Board;
+use crate::chess_move::Move;
+use crate::pgn;
+use crate::piece::Color;
+use crate::search;
+use crate::search::SearchLimits;
+use crate::search::SearchResult;
+use crate::search::Searcher;
+use crate::uci::write_line;
+
+use std::io;
+use std::io::BufRead;
+use std::io::Write;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+const ENGINE_NAME: &str = "chess-engine";
+// Mate scores in thinking output are this plus the moves to mate
+const XBOARD_MATE: i32 = 100000;
+
+// The time control set by level, st and sd, and the clock reported by time
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Clock {
+    // Moves per time control, None when the base time is for the whole game
+    moves_per_session: Option<u32>,
+    base: Duration,
+    increment: Duration,
+    // Fixed time per move
+    move_time: Option<Duration>,
+    depth: Option<u32>,
+    time_left: Option<Duration>,
+}
+
+impl Clock {
+    // xboard's default of 40 moves in 5 minutes
+    fn new() -> Clock {
+        Clock{
+            moves_per_session: Some(40),
+            base: Duration::from_secs(300),
+            increment: Duration::ZERO,
+            move_time: None,
+            depth: None,
+            time_left: None,
+        }
+    }
+
+    fn limits(&self, board: &Board) -> SearchLimits {
+        let mut limits = SearchLimits{depth: self.depth, ..SearchLimits::new()};
+        match self.move_time {
+            Some(move_time) => limits.movetime = Some(move_time),
+            None => {
+                limits.time_left = Some(self.time_left.unwrap_or(self.base));
+                limits.increment = self.increment;
+                limits.moves_to_go = self.moves_per_session.map(|moves| moves - (board.fullmove_number() - 1) % moves);
+            }
+        }
+        limits
+    }
+}
+
+// level MPS BASE INC, with BASE in minutes or minutes:seconds and INC in
+// seconds
+fn parse_level(words: &[&str]) -> Result<(Option<u32>, Duration, Duration), String> {
+    if words.len() != 3 {
+        return Err(String::from("level needs moves, base time and increment"));
+    }
+    let invalid = || format!("invalid time control {}", words.join(" "));
+    let moves: u32 = words[0].parse().map_err(|_| invalid())?;
+    let base = match words[1].split_once(':') {
+        Some((minutes, seconds)) => {
+            let minutes: u64 = minutes.parse().map_err(|_| invalid())?;
+            let seconds: u64 = seconds.parse().map_err(|_| invalid())?;
+            Duration::from_secs(minutes * 60 + seconds)
+        },
+        None => Duration::from_secs(words[1].parse::<u64>().map_err(|_| invalid())? * 60)
+    };
+    let increment: f64 = words[2].parse().map_err(|_| invalid())?;
+    if !(0.0..1e6).contains(&increment) {
+        return Err(invalid());
+    }
+    Ok((Some(moves).filter(|moves| *moves > 0), base, Duration::from_secs_f64(increment)))
+}
+
+// Thinking output: ply, score, time in centiseconds, nodes and the line
+pub fn thinking_line(result: &SearchResult) -> String {
+    let score = match search::mate_in(result.score) {
+        Some(moves) if moves > 0 => XBOARD_MATE + moves,
+        Some(moves) => -XBOARD_MATE + moves,
+        None => result.score
+    };
+    let pv: Vec<String> = result.pv.iter().map(|mv| mv.to_string()).collect();
+    format!("{} {} {} {} {}", result.depth, score, result.time.as_millis() / 10, result.nodes, pv.join(" "))
+}
+
+// The result to announce once the game is over
+fn game_result(board: &mut Board) -> Option<&'static str> {
+    if board.legal_moves().is_empty() {
+        return Some(match (board.in_check(), board.side_to_move()) {
+            (true, Color::Black) => "1-0 {White mates}",
+            (true, Color::White) => "0-1 {Black mates}",
+            (false, _) => "1/2-1/2 {Stalemate}"
+        });
+    }
+    if board.halfmove_clock() >= 100 {
+        return Some("1/2-1/2 {Fifty move rule}");
+    }
+    None
+}
+
+// Chess Engine Communication Protocol (xboard) front end, version 2. Like
+// the UCI one it searches on its own thread, which prints the move it
+// makes; the move is played on the board here once the thread is joined.
+pub struct XboardEngine<W: Write + Send + 'static> {
+    output: Arc<Mutex<W>>,
+    board: Board,
+    searcher: Option<Searcher>,
+    search: Option<JoinHandle<(Searcher, Option<Move>)>>,
+    stop: Arc<AtomicBool>,
+    // Passed on to the searcher after every iteration, in case the search
+    // cleared its flag after the request came
+    stop_requested: Arc<AtomicBool>,
+    // Set to end a search without it making its move
+    discard: Arc<AtomicBool>,
+    // The side the engine plays, None in force mode
+    engine_color: Option<Color>,
+    analysing: bool,
+    post: bool,
+    clock: Clock,
+}
+
+impl<W: Write + Send + 'static> XboardEngine<W> {
+    pub fn new(output: W) -> XboardEngine<W> {
+        let searcher = Searcher::new();
+        XboardEngine{
+            output: Arc::new(Mutex::new(output)),
+            board: Board::build_starting().unwrap(),
+            stop: searcher.stop_flag(),
+            searcher: Some(searcher),
+            search: None,
+            stop_requested: Arc::new(AtomicBool::new(false)),
+            discard: Arc::new(AtomicBool::new(false)),
+            engine_color: Some(Color::Black),
+            analysing: false,
+            post: false,
+            clock: Clock::new(),
+        }
+    }
+
+    pub fn output(&self) -> &Arc<Mutex<W>> {
+        &self.output
+    }
+
+    pub fn board(&self) -> &Board {
+        &self.board
+    }
+
+    fn send(&self, line: &str) {
+        write_line(&self.output, line);
+    }
+
+    pub fn run<R: BufRead>(&mut self, input: R) -> io::Result<()> {
+        for line in input.lines() {
+            if !self.handle(&line?) {
+                break;
+            }
+        }
+        self.stop_thinking(true);
+        Ok(())
+    }
+
+    // Handle one command; false once it was quit
+    pub fn handle(&mut self, line: &str) -> bool {
+        // Play the move of a search that has finished before anything else
+        if self.search.as_ref().is_some_and(|search| search.is_finished()) {
+            self.wait();
+        }
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let command = match words.first() {
+            Some(command) => *command,
+            None => return true
+        };
+        match command {
+            "protover" => self.send(&format!(
+                "feature myname=\"{}\" setboard=1 usermove=1 ping=1 playother=1 analyze=1 colors=0 sigint=0 sigterm=0 reuse=1 done=1",
+                ENGINE_NAME)),
+            "new" => {
+                self.stop_thinking(true);
+                self.board = Board::build_starting().unwrap();
+                self.engine_color = Some(Color::Black);
+                self.analysing = false;
+                self.clock.depth = None;
+                self.searcher_mut().clear_hash();
+            },
+            "force" => {
+                self.stop_thinking(true);
+                self.engine_color = None;
+            },
+            "go" => {
+                self.stop_thinking(true);
+                self.engine_color = Some(self.board.side_to_move());
+                self.think();
+            },
+            "playother" => {
+                self.stop_thinking(true);
+                self.engine_color = Some(self.board.side_to_move().opposite());
+            },
+            "?" => self.stop_thinking(false),
+            "usermove" => match words.get(1) {
+                Some(word) => self.user_move(word),
+                None => self.send("Error (no move): usermove")
+            },
+            "undo" | "remove" => {
+                self.stop_thinking(true);
+                let plies = if command == "undo" { 1 } else { 2 };
+                for _ in 0..plies {
+                    if self.board.last_move().is_some() {
+                        self.board.unmake_move();
+                    }
+                }
+                self.restart_analysis();
+            },
+            "setboard" => {
+                self.stop_thinking(true);
+                match Board::from_fen(&words[1..].join(" ")) {
+                    Ok(board) => self.board = board,
+                    Err(err) => self.send(&format!("tellusererror Illegal position: {}", err))
+                }
+                self.restart_analysis();
+            },
+            "level" => match parse_level(&words[1..]) {
+                Ok((moves, base, increment)) => {
+                    self.clock.moves_per_session = moves;
+                    self.clock.base = base;
+                    self.clock.increment = increment;
+                    self.clock.move_time = None;
+                },
+                Err(err) => self.send(&format!("Error ({}): {}", err, line))
+            },
+            "st" => match words.get(1).and_then(|seconds| seconds.parse::<f64>().ok()).filter(|seconds| (0.0..1e6).contains(seconds)) {
+                Some(seconds) => self.clock.move_time = Some(Duration::from_secs_f64(seconds)),
+                None => self.send(&format!("Error (invalid time): {}", line))
+            },
+            "sd" => match words.get(1).and_then(|depth| depth.parse().ok()) {
+                Some(depth) => self.clock.depth = Some(depth),
+                None => self.send(&format!("Error (invalid depth): {}", line))
+            },
+            "time" => match words.get(1).and_then(|centiseconds| centiseconds.parse::<u64>().ok()) {
+                Some(centiseconds) => self.clock.time_left = Some(Duration::from_millis(centiseconds * 10)),
+                None => self.send(&format!("Error (invalid time): {}", line))
+            },
+            // The opponent's clock does not change how long we think
+            "otim" => {},
+            "analyze" => {
+                self.stop_thinking(true);
+                self.analysing = true;
+                self.engine_color = None;
+                self.think();
+            },
+            "exit" => {
+                self.stop_thinking(true);
+                self.analysing = false;
+            },
+            "post" => self.post = true,
+            "nopost" => self.post = false,
+            "ping" => {
+                // The pong must come after the move being thought about
+                if !self.analysing {
+                    self.wait();
+                }
+                self.send(&format!("pong {}", words.get(1).unwrap_or(&"")));
+            },
+            "quit" => {
+                self.stop_thinking(true);
+                return false;
+            },
+            // Nothing to do for these
+            "xboard" | "accepted" | "rejected" | "random" | "hard" | "easy" | "computer" | "name" | "rating" | "result" | "." => {},
+            // Without the usermove feature moves come on their own
+            _ => {
+                if Move::from_uci(command).is_ok() {
+                    self.user_move(command);
+                } else {
+                    self.send(&format!("Error (unknown command): {}", command));
+                }
+            }
+        }
+        true
+    }
+
+    fn searcher_mut(&mut self) -> &mut Searcher {
+        self.searcher.as_mut().unwrap()
+    }
+
+    fn user_move(&mut self, word: &str) {
+        self.stop_thinking(true);
+        let mv = match Move::from_uci(word) {
+            Ok(mv) => Ok(mv),
+            Err(_) => pgn::parse_san(&mut self.board, word)
+        };
+        if mv.and_then(|mv| self.board.play(mv)).is_err() {
+            self.send(&format!("Illegal move: {}", word));
+            return;
+        }
+        if self.analysing {
+            self.restart_analysis();
+        } else if self.engine_color == Some(self.board.side_to_move()) {
+            self.think();
+        }
+    }
+
+    fn restart_analysis(&mut self) {
+        if self.analysing {
+            self.think();
+        }
+    }
+
+    // Block until the running search is done and play the move it made
+    pub fn wait(&mut self) {
+        if let Some(handle) = self.search.take() {
+            let (searcher, mv) = handle.join().unwrap();
+            self.searcher = Some(searcher);
+            if let Some(mv) = mv {
+                self.board.make_move(mv);
+                if let Some(result) = game_result(&mut self.board) {
+                    self.send(result);
+                }
+            }
+        }
+    }
+
+    // Stop a running search, which either makes its move now or not at all
+    fn stop_thinking(&mut self, discard: bool) {
+        if self.search.is_some() {
+            if discard {
+                self.discard.store(true, Ordering::Relaxed);
+            }
+            self.stop_requested.store(true, Ordering::Relaxed);
+            self.stop.store(true, Ordering::Relaxed);
+            self.wait();
+        }
+    }
+
+    fn think(&mut self) {
+        if let Some(result) = game_result(&mut self.board) {
+            if !self.analysing {
+                self.send(result);
+            }
+            return;
+        }
+        let limits = if self.analysing { SearchLimits::new() } else { self.clock.limits(&self.board) };
+        let post = self.post || self.analysing;
+        let mut searcher = self.searcher.take().unwrap();
+        let mut board = self.board.clone();
+        let output = Arc::clone(&self.output);
+        let stop = Arc::clone(&self.stop);
+        let (stop_requested, discard) = (Arc::clone(&self.stop_requested), Arc::clone(&self.discard));
+        stop_requested.store(false, Ordering::Relaxed);
+        // Analysis never plays its move
+        discard.store(self.analysing, Ordering::Relaxed);
+        self.search = Some(thread::spawn(move || {
+            let result = searcher.search_with_info(&mut board, &limits, |result| {
+                if stop_requested.load(Ordering::Relaxed) {
+                    stop.store(true, Ordering::Relaxed);
+                }
+                if post {
+                    write_line(&output, &thinking_line(result));
+                }
+            });
+            let mv = result.best_move.filter(|_| !discard.load(Ordering::Relaxed));
+            if let Some(mv) = mv {
+                write_line(&output, &format!("move {}", mv));
+            }
+            (searcher, mv)
+        }));
+    }
+}
+
+#[cfg(test)]
+mod xboard_tests {
+    use super::*;
+
+    fn engine() -> XboardEngine<Vec<u8>> {
+        XboardEngine::new(Vec::new())
+    }
+
+    fn take_output(engine: &XboardEngine<Vec<u8>>) -> Vec<String> {
+        let mut output = engine.output().lock().unwrap();
+        let text = String::from_utf8(output.clone()).unwrap();
+        output.clear();
+        text.lines().map(String::from).collect()
+    }
+
+    // Output until a line starting with prefix turns up
+    fn wait_for(engine: &XboardEngine<Vec<u8>>, prefix: &str) -> Vec<String> {
+        let deadline = std::time::Instant::now() + Duration::from_secs(60);
+        let mut lines = Vec::new();
+        while !lines.iter().any(|line: &String| line.starts_with(prefix)) {
+            assert!(std::time::Instant::now() < deadline, "no {} line", prefix);
+            thread::sleep(Duration::from_millis(1));
+            lines.extend(take_output(engine));
+        }
+        lines
+    }
+
+    fn moves(lines: &[String]) -> Vec<&str> {
+        lines.iter().filter_map(|line| line.strip_prefix("move ")).collect()
+    }
+
+    #[test]
+    fn announces_its_features() {
+        let mut engine = engine();
+        engine.handle("xboard");
+        engine.handle("protover 2");
+        engine.handle("ping 7");
+        let lines = take_output(&engine);
+        assert!(lines[0].starts_with("feature myname=\"chess-engine\"") && lines[0].ends_with(" done=1"));
+        assert!(lines[0].contains(" usermove=1 ") && lines[0].contains(" setboard=1 "));
+        assert_eq!(lines[1], "pong 7");
+        assert!(!engine.handle("quit"));
+    }
+
+    #[test]
+    fn replies_to_moves() {
+        let mut engine = engine();
+        engine.handle("new");
+        engine.handle("sd 2");
+        engine.handle("usermove e2e4");
+        engine.handle("ping 2");
+        let lines = take_output(&engine);
+        assert_eq!(moves(&lines).len(), 1);
+        assert_eq!(lines.last().unwrap(), "pong 2");
+        assert_eq!(engine.board().side_to_move(), Color::White);
+
+        // SAN is taken too; an illegal move is refused and nothing happens
+        engine.handle("usermove Nf3");
+        engine.wait();
+        assert_eq!(moves(&take_output(&engine)).len(), 1);
+        engine.handle("usermove e4e6");
+        assert_eq!(take_output(&engine), ["Illegal move: e4e6"]);
+    }
+
+    #[test]
+    fn force_mode_and_taking_back() {
+        let mut engine = engine();
+        engine.handle("new");
+        engine.handle("force");
+        engine.handle("usermove e2e4");
+        engine.handle("usermove e7e5");
+        engine.handle("e1e2");
+        assert!(take_output(&engine).is_empty());
+        assert_eq!(engine.board().ply(), 3);
+        engine.handle("undo");
+        assert_eq!(engine.board().ply(), 2);
+        engine.handle("remove");
+        assert_eq!(engine.board().ply(), 0);
+
+        // go makes the engine play the side to move from then on
+        engine.handle("sd 1");
+        engine.handle("go");
+        engine.wait();
+        assert_eq!(moves(&take_output(&engine)).len(), 1);
+        assert_eq!(engine.board().side_to_move(), Color::Black);
+        engine.handle("usermove e7e5");
+        engine.wait();
+        assert_eq!(moves(&take_output(&engine)).len(), 1);
+    }
+
+    #[test]
+    fn time_controls() {
+        let mut engine = engine();
+        let board = Board::build_starting().unwrap();
+        engine.handle("level 40 5 0");
+        engine.handle("time 12000");
+        engine.handle("otim 9000");
+        let limits = engine.clock.limits(&board);
+        assert_eq!((limits.time_left, limits.moves_to_go), (Some(Duration::from_secs(120)), Some(40)));
+        engine.handle("level 0 2:30 12");
+        assert_eq!(engine.clock.limits(&board).increment, Duration::from_secs(12));
+        assert_eq!(engine.clock.moves_per_session, None);
+        assert_eq!(engine.clock.base, Duration::from_secs(150));
+        engine.handle("st 3");
+        engine.handle("sd 5");
+        let limits = engine.clock.limits(&board);
+        assert_eq!((limits.movetime, limits.depth, limits.time_left), (Some(Duration::from_secs(3)), Some(5), None));
+        engine.handle("level 40");
+        assert!(take_output(&engine)[0].starts_with("Error"));
+    }
+
+    #[test]
+    fn move_now_and_mates() {
+        let mut engine = engine();
+        engine.handle("force");
+        engine.handle("setboard 6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1");
+        engine.handle("post");
+        engine.handle("sd 3");
+        engine.handle("go");
+        engine.wait();
+        let lines = take_output(&engine);
+        assert!(lines.iter().any(|line| line.starts_with("1 100001 ")));
+        assert_eq!(moves(&lines), ["a1a8"]);
+        assert_eq!(lines.last().unwrap(), "1-0 {White mates}");
+
+        engine.handle("new");
+        engine.handle("st 60");
+        engine.handle("go");
+        let mut lines = wait_for(&engine, "1 ");
+        engine.handle("?");
+        lines.extend(take_output(&engine));
+        assert_eq!(moves(&lines).len(), 1);
+        engine.handle("setboard not a position");
+        assert!(take_output(&engine)[0].starts_with("tellusererror"));
+    }
+
+    #[test]
+    fn analysis_follows_the_board_without_moving() {
+        let mut engine = engine();
+        engine.handle("analyze");
+        let mut lines = wait_for(&engine, "1 ");
+        engine.handle("usermove e2e4");
+        lines.extend(wait_for(&engine, "1 "));
+        engine.handle("exit");
+        lines.extend(take_output(&engine));
+        assert!(moves(&lines).is_empty());
+        assert_eq!(engine.board().ply(), 1);
+    }
+}